@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// The public key images have to be signed with is compiled in (see
+// keys/README.md). `make` derives it from SIGNING_KEY and passes it in
+// PINETIME_SIGNING_KEY, plain cargo builds use keys/image-signing.pub.der.
+fn main() {
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let path = match env::var_os("PINETIME_SIGNING_KEY") {
+        Some(path) => manifest_dir.join(path),
+        None => manifest_dir.join("keys/image-signing.pub.der"),
+    };
+
+    println!("cargo:rerun-if-env-changed=PINETIME_SIGNING_KEY");
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    // A DER SubjectPublicKeyInfo of a P-256 key is 91 bytes
+    match fs::read(&path) {
+        Ok(key) if key.len() == 91 => {},
+        Ok(_) => panic!(
+            "{} is not a DER encoded ECDSA-P256 public key, see keys/README.md",
+            path.display(),
+        ),
+        Err(e) => panic!(
+            "No image signing key at {} ({}). Run `make keys` to generate one, \
+             or point PINETIME_SIGNING_KEY at the public key, see keys/README.md",
+            path.display(),
+            e,
+        ),
+    }
+
+    println!("cargo:rustc-env=PINETIME_SIGNING_KEY={}", path.display());
+}
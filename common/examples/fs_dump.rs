@@ -0,0 +1,56 @@
+// List the files in a dump of the 4MiB external flash, or write one of them
+// to stdout:
+//
+//   make fs_dump DUMP=flash.bin [FILE=name]
+//
+// or from common/, building for the host instead of the PineTime:
+//
+//   cargo run --target x86_64-unknown-linux-gnu --features simulated --example fs_dump -- flash.bin [name]
+//
+// The filesystem isn't littlefs, so this is the host tool for reading it.
+
+use pinetime_common::flash::SimulatedFlash;
+use pinetime_common::flash::partition::Partition;
+use pinetime_common::filesystem::Filesystem;
+
+use std::io::Write;
+
+// USER_FILESYSTEM in src/drivers/flash/partition.rs, the dump has to
+// include everything up to its end
+const USER_FILESYSTEM: Partition<SimulatedFlash> =
+    Partition::new("USER_FILESYSTEM", 0x0013_f000, 2820 * 1024);
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: {} <flash dump> [file name]", args[0]);
+        std::process::exit(1);
+    }
+
+    let image = std::fs::read(&args[1]).expect("Could not read the flash dump");
+    if image.len() != USER_FILESYSTEM.end() as usize {
+        eprintln!("Expected a dump of the whole external flash ({} bytes)", USER_FILESYSTEM.end());
+        std::process::exit(1);
+    }
+
+    let mut flash = SimulatedFlash::from_image(image);
+    let fs = match Filesystem::mount(&mut flash, USER_FILESYSTEM, || {}) {
+        Ok(fs) => fs,
+        Err(e) => {
+            eprintln!("Could not mount the filesystem: {:?}", e);
+            std::process::exit(1);
+        },
+    };
+
+    match args.get(2) {
+        None => for entry in fs.list() {
+            println!("{:>8} {}", entry.size, entry.name);
+        },
+        Some(name) => {
+            let mut file = fs.open(name).expect("File not found");
+            let mut contents = vec![0; fs.size(&file).unwrap() as usize];
+            fs.read(&mut flash, &mut file, &mut contents).unwrap();
+            std::io::stdout().write_all(&contents).unwrap();
+        },
+    }
+}
@@ -0,0 +1,349 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
+
+// iBeacon and Eddystone frames, the firmware wraps them in advertising data
+//
+// A BeaconConfig is stored in the settings as: kind (u8), interval in ms
+// (u16), then per kind:
+//  - iBeacon: UUID (16 bytes), major (u16), minor (u16), measured power (i8)
+//  - Eddystone-UID: TX power (i8), namespace (10 bytes), instance (6 bytes)
+//  - Eddystone-URL: TX power (i8), URL (UTF-8, the rest of the value)
+// Integers are little-endian, like the rest of the settings.
+
+// Apple's company identifier, used by iBeacon (little-endian)
+pub const APPLE_COMPANY_ID: [u8; 2] = [0x4c, 0x00];
+
+// 16-bit UUID of the Eddystone service (little-endian)
+pub const EDDYSTONE_UUID: [u8; 2] = [0xaa, 0xfe];
+
+// An encoded Eddystone URL can be at most 17 bytes (without scheme)
+const EDDYSTONE_URL_MAX_LEN: usize = 17;
+
+const EDDYSTONE_URL_SCHEMES: [&str; 4] = [
+    "http://www.",
+    "https://www.",
+    "http://",
+    "https://",
+];
+
+const EDDYSTONE_URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/",
+    ".com", ".org", ".edu", ".net", ".info", ".biz", ".gov",
+];
+
+// Advertising interval limits for non-connectable advertising
+pub const MIN_INTERVAL_MS: u32 = 100;
+pub const MAX_INTERVAL_MS: u32 = 10_240;
+
+const KIND_IBEACON: u8 = 0x01;
+const KIND_EDDYSTONE_UID: u8 = 0x02;
+const KIND_EDDYSTONE_URL: u8 = 0x03;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BeaconError {
+    InvalidKind,
+    InvalidLength,  // Stored config has the wrong size for its kind
+    InvalidUrl,     // Unknown scheme or characters that can't be sent
+    UrlTooLong,     // More than 17 bytes after encoding
+    InvalidInterval,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BeaconKind {
+    IBeacon {
+        uuid: [u8; 16],
+        major: u16,
+        minor: u16,
+        measured_power: i8,     // RSSI at 1 meter
+    },
+    EddystoneUid {
+        namespace: [u8; 10],
+        instance: [u8; 6],
+        tx_power: i8,           // RSSI at 0 meter
+    },
+    EddystoneUrl {
+        url: String,
+        tx_power: i8,           // RSSI at 0 meter
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeaconConfig {
+    pub kind: BeaconKind,
+    pub interval_ms: u32,
+}
+
+impl BeaconKind {
+    // The iBeacon/Eddystone frame, without the AD structure header
+    pub fn frame(&self) -> Result<Vec<u8>, BeaconError> {
+        match self {
+            BeaconKind::IBeacon { uuid, major, minor, measured_power } => {
+                let mut frame = APPLE_COMPANY_ID.to_vec();
+                // iBeacon type and remaining length
+                frame.extend_from_slice(&[0x02, 0x15]);
+                frame.extend_from_slice(uuid);
+                frame.extend_from_slice(&major.to_be_bytes());
+                frame.extend_from_slice(&minor.to_be_bytes());
+                frame.push(*measured_power as u8);
+                Ok(frame)
+            },
+            BeaconKind::EddystoneUid { namespace, instance, tx_power } => {
+                let mut frame = EDDYSTONE_UUID.to_vec();
+                frame.extend_from_slice(&[0x00, *tx_power as u8]);
+                frame.extend_from_slice(namespace);
+                frame.extend_from_slice(instance);
+                // Reserved for future use
+                frame.extend_from_slice(&[0x00, 0x00]);
+                Ok(frame)
+            },
+            BeaconKind::EddystoneUrl { url, tx_power } => {
+                let mut frame = EDDYSTONE_UUID.to_vec();
+                frame.extend_from_slice(&[0x10, *tx_power as u8]);
+                frame.extend(encode_url(url)?);
+                Ok(frame)
+            },
+        }
+    }
+}
+
+// Eddystone-URL encoding: scheme prefix code, then the URL with common
+// domain endings replaced by a code
+pub fn encode_url(url: &str) -> Result<Vec<u8>, BeaconError> {
+    // Longest matching scheme first, "http://www." also matches "http://"
+    let (scheme, mut remaining) = EDDYSTONE_URL_SCHEMES.iter()
+        .enumerate()
+        .filter_map(|(i, prefix)| {
+            url.strip_prefix(prefix).map(|rest| (i as u8, rest))
+        })
+        .min_by_key(|(_, rest)| rest.len())
+        .ok_or(BeaconError::InvalidUrl)?;
+
+    let mut encoded = vec![scheme];
+    while !remaining.is_empty() {
+        let expansion = EDDYSTONE_URL_EXPANSIONS.iter()
+            .position(|expansion| remaining.starts_with(expansion));
+
+        if let Some(code) = expansion {
+            encoded.push(code as u8);
+            remaining = &remaining[EDDYSTONE_URL_EXPANSIONS[code].len()..];
+        } else {
+            let c = remaining.as_bytes()[0];
+            if !c.is_ascii_graphic() {
+                return Err(BeaconError::InvalidUrl);
+            }
+            encoded.push(c);
+            remaining = &remaining[1..];
+        }
+    }
+
+    // Scheme byte is not counted
+    if encoded.len() - 1 > EDDYSTONE_URL_MAX_LEN {
+        return Err(BeaconError::UrlTooLong);
+    }
+
+    Ok(encoded)
+}
+
+impl BeaconConfig {
+    // Everything that can make sending the beacon fail is checked here, so a
+    // config that passes can always be advertised
+    pub fn validate(&self) -> Result<(), BeaconError> {
+        if !(MIN_INTERVAL_MS..=MAX_INTERVAL_MS).contains(&self.interval_ms) {
+            return Err(BeaconError::InvalidInterval);
+        }
+
+        self.kind.frame().map(|_| ())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let kind = match self.kind {
+            BeaconKind::IBeacon { .. } => KIND_IBEACON,
+            BeaconKind::EddystoneUid { .. } => KIND_EDDYSTONE_UID,
+            BeaconKind::EddystoneUrl { .. } => KIND_EDDYSTONE_URL,
+        };
+
+        let mut bytes = vec![kind];
+        // Intervals above u16::MAX don't validate anyway
+        bytes.extend_from_slice(&(self.interval_ms.min(u16::MAX as u32) as u16).to_le_bytes());
+
+        match &self.kind {
+            BeaconKind::IBeacon { uuid, major, minor, measured_power } => {
+                bytes.extend_from_slice(uuid);
+                bytes.extend_from_slice(&major.to_le_bytes());
+                bytes.extend_from_slice(&minor.to_le_bytes());
+                bytes.push(*measured_power as u8);
+            },
+            BeaconKind::EddystoneUid { namespace, instance, tx_power } => {
+                bytes.push(*tx_power as u8);
+                bytes.extend_from_slice(namespace);
+                bytes.extend_from_slice(instance);
+            },
+            BeaconKind::EddystoneUrl { url, tx_power } => {
+                bytes.push(*tx_power as u8);
+                bytes.extend_from_slice(url.as_bytes());
+            },
+        }
+
+        bytes
+    }
+
+    // Parse and validate a config written by to_bytes (or by the phone)
+    pub fn parse(bytes: &[u8]) -> Result<BeaconConfig, BeaconError> {
+        if bytes.len() < 4 {
+            return Err(BeaconError::InvalidLength);
+        }
+
+        let interval_ms = u16::from_le_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[3..];
+
+        let kind = match (bytes[0], data.len()) {
+            (KIND_IBEACON, 21) => BeaconKind::IBeacon {
+                uuid: data[0..16].try_into().unwrap(),
+                major: u16::from_le_bytes([data[16], data[17]]),
+                minor: u16::from_le_bytes([data[18], data[19]]),
+                measured_power: data[20] as i8,
+            },
+            (KIND_EDDYSTONE_UID, 17) => BeaconKind::EddystoneUid {
+                tx_power: data[0] as i8,
+                namespace: data[1..11].try_into().unwrap(),
+                instance: data[11..17].try_into().unwrap(),
+            },
+            (KIND_EDDYSTONE_URL, _) => BeaconKind::EddystoneUrl {
+                tx_power: data[0] as i8,
+                url: String::from_utf8(data[1..].to_vec())
+                    .map_err(|_| BeaconError::InvalidUrl)?,
+            },
+            (KIND_IBEACON, _) | (KIND_EDDYSTONE_UID, _) => return Err(BeaconError::InvalidLength),
+            _ => return Err(BeaconError::InvalidKind),
+        };
+
+        let config = BeaconConfig { kind, interval_ms };
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> BeaconKind {
+        BeaconKind::EddystoneUrl {
+            url: String::from(url),
+            tx_power: -20,
+        }
+    }
+
+    #[test]
+    fn url_schemes() {
+        assert_eq!(encode_url("http://www.a").unwrap(), [0x00, b'a']);
+        assert_eq!(encode_url("https://www.a").unwrap(), [0x01, b'a']);
+        assert_eq!(encode_url("http://a").unwrap(), [0x02, b'a']);
+        assert_eq!(encode_url("https://a").unwrap(), [0x03, b'a']);
+        assert_eq!(encode_url("ftp://a"), Err(BeaconError::InvalidUrl));
+        assert_eq!(encode_url("a"), Err(BeaconError::InvalidUrl));
+    }
+
+    #[test]
+    fn url_expansions() {
+        // ".com/" before ".com", and expansions anywhere in the URL
+        assert_eq!(encode_url("https://example.com/x").unwrap(), b"\x03example\x00x");
+        assert_eq!(encode_url("https://example.com").unwrap(), b"\x03example\x07");
+        assert_eq!(encode_url("http://www.a.info/b.gov").unwrap(), b"\x00a\x04b\x0d");
+    }
+
+    #[test]
+    fn url_limits() {
+        // 17 bytes after the scheme is the maximum
+        assert!(encode_url("https://abcdefghijklmnopq").is_ok());
+        assert_eq!(encode_url("https://abcdefghijklmnopqr"), Err(BeaconError::UrlTooLong));
+        assert_eq!(encode_url("https://a b"), Err(BeaconError::InvalidUrl));
+        assert_eq!(encode_url("https://é"), Err(BeaconError::InvalidUrl));
+    }
+
+    #[test]
+    fn ibeacon_frame() {
+        let kind = BeaconKind::IBeacon {
+            uuid: [0x11; 16],
+            major: 0x0102,
+            minor: 0x0304,
+            measured_power: -59,
+        };
+
+        let mut expected = vec![0x4c, 0x00, 0x02, 0x15];
+        expected.extend_from_slice(&[0x11; 16]);
+        expected.extend_from_slice(&[0x01, 0x02, 0x03, 0x04, 0xc5]);
+        assert_eq!(kind.frame().unwrap(), expected);
+    }
+
+    #[test]
+    fn eddystone_uid_frame() {
+        let kind = BeaconKind::EddystoneUid {
+            namespace: [0x22; 10],
+            instance: [0x33; 6],
+            tx_power: -20,
+        };
+
+        let mut expected = vec![0xaa, 0xfe, 0x00, 0xec];
+        expected.extend_from_slice(&[0x22; 10]);
+        expected.extend_from_slice(&[0x33; 6]);
+        expected.extend_from_slice(&[0x00, 0x00]);
+        assert_eq!(kind.frame().unwrap(), expected);
+    }
+
+    #[test]
+    fn eddystone_url_frame() {
+        assert_eq!(url("https://pine64.org").frame().unwrap(), b"\xaa\xfe\x10\xec\x03pine64\x08");
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let configs = [
+            BeaconConfig {
+                kind: BeaconKind::IBeacon {
+                    uuid: [0x11; 16],
+                    major: 1,
+                    minor: 2,
+                    measured_power: -59,
+                },
+                interval_ms: 100,
+            },
+            BeaconConfig {
+                kind: BeaconKind::EddystoneUid {
+                    namespace: [0x22; 10],
+                    instance: [0x33; 6],
+                    tx_power: -20,
+                },
+                interval_ms: 1000,
+            },
+            BeaconConfig {
+                kind: url("https://www.example.com/"),
+                interval_ms: MAX_INTERVAL_MS,
+            },
+        ];
+
+        for config in configs {
+            assert_eq!(BeaconConfig::parse(&config.to_bytes()), Ok(config));
+        }
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let config = |kind, interval_ms| BeaconConfig { kind, interval_ms };
+
+        assert_eq!(config(url("https://a"), 99).validate(), Err(BeaconError::InvalidInterval));
+        assert_eq!(config(url("https://a"), 10_241).validate(), Err(BeaconError::InvalidInterval));
+        assert_eq!(config(url("https://abcdefghijklmnopqr"), 100).validate(), Err(BeaconError::UrlTooLong));
+
+        // parse validates too
+        let too_long = config(url("https://abcdefghijklmnopqr"), 100).to_bytes();
+        assert_eq!(BeaconConfig::parse(&too_long), Err(BeaconError::UrlTooLong));
+
+        assert_eq!(BeaconConfig::parse(&[]), Err(BeaconError::InvalidLength));
+        assert_eq!(BeaconConfig::parse(&[0x01, 0xe8, 0x03, 0x00]), Err(BeaconError::InvalidLength));
+        assert_eq!(BeaconConfig::parse(&[0x04, 0xe8, 0x03, 0x00]), Err(BeaconError::InvalidKind));
+        assert_eq!(BeaconConfig::parse(&[0x03, 0xe8, 0x03, 0x00, 0xff]), Err(BeaconError::InvalidUrl));
+    }
+}
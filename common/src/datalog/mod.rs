@@ -0,0 +1,452 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::flash::crc32;
+use crate::flash::partition::{Partition, PartitionError, FlashOrigin};
+
+use alloc::vec::Vec;
+
+// Time-series data log
+//
+// Fixed-size records are appended to the sectors of the partition in a ring.
+// When the current sector is full, the next one (which holds the oldest
+// records) is erased and takes over with a higher sequence number. Records
+// are never modified, so the log survives reboots and an interrupted write
+// only loses that record.
+//
+// Sector header (16 bytes): magic ("PTDL"), sequence number (u32), reserved
+// (u32), CRC-32 of the previous 12 bytes.
+// Record (16 bytes): timestamp (i64, seconds since the epoch), kind (u8),
+// flags (u8), value (u32), lower 16 bits of the CRC-32 of the previous 14
+// bytes. An erased record is all 0xff.
+
+const MAGIC: [u8; 4] = *b"PTDL";
+const HEADER_SIZE: u32 = 16;
+const RECORD_SIZE: u32 = 16;
+
+// Records read at once when scanning
+const READ_RECORDS: usize = 16;
+
+// The clock wasn't set yet, so the timestamp may be far off
+const FLAG_APPROXIMATE: u8 = 0x01;
+
+#[derive(Debug)]
+pub enum DataLogError<E> {
+    Flash(PartitionError<E>),
+    InvalidPartition,   // Less than two erase sectors
+}
+
+impl<E> From<PartitionError<E>> for DataLogError<E> {
+    fn from(error: PartitionError<E>) -> DataLogError<E> {
+        DataLogError::Flash(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataPoint {
+    BatteryVoltage(u32),    // mV
+    Steps(u32),
+    HeartRate(u32),         // Beats per minute
+    Event(u32),             // Application defined
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    pub timestamp: i64,
+    pub approximate: bool,  // Timestamp from a clock that wasn't set
+    pub point: DataPoint,
+}
+
+impl DataPoint {
+    fn kind(&self) -> u8 {
+        match self {
+            DataPoint::BatteryVoltage(_) => 0x01,
+            DataPoint::Steps(_) => 0x02,
+            DataPoint::HeartRate(_) => 0x03,
+            DataPoint::Event(_) => 0x04,
+        }
+    }
+
+    fn value(&self) -> u32 {
+        match self {
+            DataPoint::BatteryVoltage(value) => *value,
+            DataPoint::Steps(value) => *value,
+            DataPoint::HeartRate(value) => *value,
+            DataPoint::Event(value) => *value,
+        }
+    }
+
+    // Returns None for unknown kinds
+    fn parse(kind: u8, value: u32) -> Option<DataPoint> {
+        match kind {
+            0x01 => Some(DataPoint::BatteryVoltage(value)),
+            0x02 => Some(DataPoint::Steps(value)),
+            0x03 => Some(DataPoint::HeartRate(value)),
+            0x04 => Some(DataPoint::Event(value)),
+            _ => None,
+        }
+    }
+}
+
+impl Record {
+    fn to_bytes(self) -> [u8; RECORD_SIZE as usize] {
+        let mut bytes = [0; RECORD_SIZE as usize];
+        bytes[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[8] = self.point.kind();
+        bytes[9] = if self.approximate { FLAG_APPROXIMATE } else { 0 };
+        bytes[10..14].copy_from_slice(&self.point.value().to_le_bytes());
+        let crc = crc32(&bytes[0..14]) as u16;
+        bytes[14..16].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    // Returns None for corrupted records and unknown kinds
+    fn parse(bytes: &[u8]) -> Option<Record> {
+        let crc = u16::from_le_bytes(bytes[14..16].try_into().unwrap());
+        if crc32(&bytes[0..14]) as u16 != crc {
+            return None;
+        }
+
+        let timestamp = i64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let approximate = bytes[9] & FLAG_APPROXIMATE != 0;
+        let value = u32::from_le_bytes(bytes[10..14].try_into().unwrap());
+
+        DataPoint::parse(bytes[8], value).map(|point| Record { timestamp, approximate, point })
+    }
+}
+
+fn is_erased(bytes: &[u8]) -> bool {
+    bytes.iter().all(|b| *b == 0xff)
+}
+
+pub struct DataLog<F> {
+    partition: Partition<F>,
+    // Sector and sequence number of the sector being written, None if the log
+    // is empty
+    current: Option<(u32, u32)>,
+    // Where the next record goes in the current sector
+    write_offset: u32,
+}
+
+impl<F: NorFlash + FlashOrigin> DataLog<F> {
+    // `partition` has to be at least two erase sectors, a ring of one sector
+    // would lose all records when it is full
+    pub fn mount(flash: &mut F, partition: Partition<F>) -> Result<Self, DataLogError<F::Error>> {
+        if partition.size < 2 * F::ERASE_SIZE as u32 {
+            return Err(DataLogError::InvalidPartition);
+        }
+
+        let mut log = DataLog {
+            partition,
+            current: None,
+            write_offset: HEADER_SIZE,
+        };
+
+        for (sector, sequence) in log.sectors(flash)? {
+            if log.current.is_none_or(|(_, newest)| sequence > newest) {
+                log.current = Some((sector, sequence));
+            }
+        }
+
+        // An empty log starts in the first sector on the first append
+        if let Some((sector, _)) = log.current {
+            log.write_offset = log.find_end(flash, sector)?;
+        }
+
+        Ok(log)
+    }
+
+    pub fn append(&mut self, flash: &mut F, record: Record) -> Result<(), DataLogError<F::Error>> {
+        let sector = match self.current {
+            Some((sector, _)) if self.write_offset + RECORD_SIZE <= self.sector_size() => sector,
+            _ => self.next_sector(flash)?,
+        };
+
+        let address = self.sector_start(sector) + self.write_offset;
+
+        // Skip the slot even if the write fails, it may be half written
+        self.write_offset += RECORD_SIZE;
+        self.partition.write(flash, address, &record.to_bytes())?;
+
+        Ok(())
+    }
+
+    // All records with `from <= timestamp < to`, oldest first
+    pub fn query(&self, flash: &mut F, from: i64, to: i64) -> Result<Vec<Record>, DataLogError<F::Error>> {
+        let mut sectors = self.sectors(flash)?;
+        sectors.sort_by_key(|(_, sequence)| *sequence);
+
+        let mut records = Vec::new();
+        let mut buffer = [0; READ_RECORDS * RECORD_SIZE as usize];
+
+        for (sector, _) in sectors {
+            let start = self.sector_start(sector);
+            let mut offset = HEADER_SIZE;
+
+            'sector: while offset < self.sector_size() {
+                let len = buffer.len().min((self.sector_size() - offset) as usize);
+                self.partition.read(flash, start + offset, &mut buffer[..len])?;
+
+                for bytes in buffer[..len].chunks(RECORD_SIZE as usize) {
+                    if is_erased(bytes) {
+                        break 'sector;
+                    }
+
+                    if let Some(record) = Record::parse(bytes) {
+                        if record.timestamp >= from && record.timestamp < to {
+                            records.push(record);
+                        }
+                    }
+                }
+
+                offset += len as u32;
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn sector_size(&self) -> u32 {
+        F::ERASE_SIZE as u32
+    }
+
+    fn sector_count(&self) -> u32 {
+        self.partition.size / self.sector_size()
+    }
+
+    fn sector_start(&self, sector: u32) -> u32 {
+        sector * self.sector_size()
+    }
+
+    // Sectors with a valid header and their sequence numbers
+    fn sectors(&self, flash: &mut F) -> Result<Vec<(u32, u32)>, PartitionError<F::Error>> {
+        let mut sectors = Vec::new();
+
+        for sector in 0..self.sector_count() {
+            let mut header = [0; HEADER_SIZE as usize];
+            self.partition.read(flash, self.sector_start(sector), &mut header)?;
+
+            if let Some(sequence) = parse_header(&header) {
+                sectors.push((sector, sequence));
+            }
+        }
+
+        Ok(sectors)
+    }
+
+    // Offset after the last written (or half written) record of `sector`
+    fn find_end(&self, flash: &mut F, sector: u32) -> Result<u32, PartitionError<F::Error>> {
+        let start = self.sector_start(sector);
+        let mut end = HEADER_SIZE;
+        let mut offset = HEADER_SIZE;
+        let mut buffer = [0; READ_RECORDS * RECORD_SIZE as usize];
+
+        while offset < self.sector_size() {
+            let len = buffer.len().min((self.sector_size() - offset) as usize);
+            self.partition.read(flash, start + offset, &mut buffer[..len])?;
+
+            for (i, bytes) in buffer[..len].chunks(RECORD_SIZE as usize).enumerate() {
+                if !is_erased(bytes) {
+                    end = offset + (i as u32 + 1) * RECORD_SIZE;
+                }
+            }
+
+            offset += len as u32;
+        }
+
+        Ok(end)
+    }
+
+    // Erase the sector after the current one (dropping the oldest records)
+    // and make it the current sector
+    fn next_sector(&mut self, flash: &mut F) -> Result<u32, PartitionError<F::Error>> {
+        let (sector, sequence) = match self.current {
+            Some((sector, sequence)) => ((sector + 1) % self.sector_count(), sequence + 1),
+            None => (0, 0),
+        };
+        let start = self.sector_start(sector);
+
+        self.partition.erase(flash, start, start + self.sector_size())?;
+        self.partition.write(flash, start, &header(sequence))?;
+
+        self.current = Some((sector, sequence));
+        self.write_offset = HEADER_SIZE;
+
+        Ok(sector)
+    }
+}
+
+fn header(sequence: u32) -> [u8; HEADER_SIZE as usize] {
+    let mut header = [0; HEADER_SIZE as usize];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4..8].copy_from_slice(&sequence.to_le_bytes());
+    let crc = crc32(&header[0..12]);
+    header[12..16].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+// Returns the sequence number if the header is valid
+fn parse_header(header: &[u8]) -> Option<u32> {
+    let crc = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    if header[0..4] != MAGIC || crc32(&header[0..12]) != crc {
+        return None;
+    }
+
+    Some(u32::from_le_bytes(header[4..8].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::SimulatedFlash;
+
+    const SECTOR: u32 = 0x1000;
+    const RECORDS_PER_SECTOR: i64 = ((SECTOR - HEADER_SIZE) / RECORD_SIZE) as i64;
+
+    fn partition(sectors: u32) -> Partition<SimulatedFlash> {
+        Partition::new("DATA_LOG", SECTOR, sectors * SECTOR)
+    }
+
+    fn record(timestamp: i64) -> Record {
+        Record {
+            timestamp,
+            approximate: false,
+            point: DataPoint::BatteryVoltage(3000 + timestamp as u32),
+        }
+    }
+
+    fn timestamps(records: &[Record]) -> Vec<i64> {
+        records.iter().map(|record| record.timestamp).collect()
+    }
+
+    #[test]
+    fn append_and_query() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut log = DataLog::mount(&mut flash, partition(2)).unwrap();
+        assert!(log.query(&mut flash, i64::MIN, i64::MAX).unwrap().is_empty());
+
+        for timestamp in 0..10 {
+            log.append(&mut flash, record(timestamp)).unwrap();
+        }
+        log.append(&mut flash, Record {
+            timestamp: 10,
+            approximate: true,
+            point: DataPoint::Steps(1234),
+        }).unwrap();
+
+        let records = log.query(&mut flash, 3, 11).unwrap();
+        assert_eq!(timestamps(&records), (3..11).collect::<Vec<_>>());
+        assert_eq!(records[0], record(3));
+        assert_eq!(records[7], Record { timestamp: 10, approximate: true, point: DataPoint::Steps(1234) });
+
+        // Nothing outside of the partition was touched
+        assert!(flash.contents()[..SECTOR as usize].iter().all(|b| *b == 0xff));
+        assert!(flash.contents()[3 * SECTOR as usize..].iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn remount_continues_at_the_end() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut log = DataLog::mount(&mut flash, partition(2)).unwrap();
+        for timestamp in 0..RECORDS_PER_SECTOR + 5 {
+            log.append(&mut flash, record(timestamp)).unwrap();
+        }
+
+        let mut log = DataLog::mount(&mut flash, partition(2)).unwrap();
+        log.append(&mut flash, record(1000)).unwrap();
+
+        let records = log.query(&mut flash, i64::MIN, i64::MAX).unwrap();
+        assert_eq!(records.len() as i64, RECORDS_PER_SECTOR + 6);
+        assert_eq!(records.last(), Some(&record(1000)));
+    }
+
+    #[test]
+    fn ring_drops_the_oldest_sector() {
+        let mut flash = SimulatedFlash::new(5 * SECTOR);
+        let mut log = DataLog::mount(&mut flash, partition(3)).unwrap();
+
+        // Around the ring twice
+        let count = 6 * RECORDS_PER_SECTOR + 7;
+        for timestamp in 0..count {
+            log.append(&mut flash, record(timestamp)).unwrap();
+        }
+
+        // The two full sectors before the current one are left, oldest first
+        let records = log.query(&mut flash, i64::MIN, i64::MAX).unwrap();
+        let first = 4 * RECORDS_PER_SECTOR;
+        assert_eq!(timestamps(&records), (first..count).collect::<Vec<_>>());
+
+        // And the order survives a remount
+        let log = DataLog::mount(&mut flash, partition(3)).unwrap();
+        assert_eq!(log.query(&mut flash, i64::MIN, i64::MAX).unwrap(), records);
+
+        // Seven sectors were started
+        let erase_counts: Vec<_> = (1..4).map(|sector| flash.erase_count(sector * SECTOR)).collect();
+        assert_eq!(erase_counts, [3, 2, 2]);
+    }
+
+    #[test]
+    fn power_loss_during_append() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut log = DataLog::mount(&mut flash, partition(2)).unwrap();
+        for timestamp in 0..5 {
+            log.append(&mut flash, record(timestamp)).unwrap();
+        }
+
+        flash.fail_after(0);
+        assert!(log.append(&mut flash, record(5)).is_err());
+        flash.power_cycle();
+
+        // Only the half written record is lost, its slot is skipped
+        let mut log = DataLog::mount(&mut flash, partition(2)).unwrap();
+        log.append(&mut flash, record(6)).unwrap();
+        let records = log.query(&mut flash, i64::MIN, i64::MAX).unwrap();
+        assert_eq!(timestamps(&records), [0, 1, 2, 3, 4, 6]);
+    }
+
+    #[test]
+    fn power_loss_starting_a_sector() {
+        // During the erase and during the header write
+        for operations in 0..2 {
+            let mut flash = SimulatedFlash::new(4 * SECTOR);
+            let mut log = DataLog::mount(&mut flash, partition(2)).unwrap();
+            for timestamp in 0..RECORDS_PER_SECTOR {
+                log.append(&mut flash, record(timestamp)).unwrap();
+            }
+
+            flash.fail_after(operations);
+            assert!(log.append(&mut flash, record(RECORDS_PER_SECTOR)).is_err());
+            flash.power_cycle();
+
+            let mut log = DataLog::mount(&mut flash, partition(2)).unwrap();
+            assert_eq!(log.query(&mut flash, i64::MIN, i64::MAX).unwrap().len() as i64, RECORDS_PER_SECTOR);
+
+            log.append(&mut flash, record(1000)).unwrap();
+            let records = log.query(&mut flash, i64::MIN, i64::MAX).unwrap();
+            assert_eq!(records.len() as i64, RECORDS_PER_SECTOR + 1);
+            assert_eq!(records.last(), Some(&record(1000)));
+        }
+    }
+
+    #[test]
+    fn corrupted_records_are_skipped() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut log = DataLog::mount(&mut flash, partition(2)).unwrap();
+        for timestamp in 0..3 {
+            log.append(&mut flash, record(timestamp)).unwrap();
+        }
+
+        flash.inject_bit_error(SECTOR + HEADER_SIZE + RECORD_SIZE + 2, 0);
+
+        let records = log.query(&mut flash, i64::MIN, i64::MAX).unwrap();
+        assert_eq!(timestamps(&records), [0, 2]);
+    }
+
+    #[test]
+    fn invalid_partition() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        assert!(matches!(
+            DataLog::mount(&mut flash, partition(1)),
+            Err(DataLogError::InvalidPartition),
+        ));
+    }
+}
@@ -0,0 +1,1118 @@
+mod record;
+
+use record::{
+    RecordHeader, RecordKind, ParsedRecord, block_header, parse_block_header,
+    record_size, BLOCK_HEADER_SIZE, RECORD_HEADER_SIZE, RECORD_ALIGN,
+};
+
+use crate::flash::crc32;
+use crate::flash::partition::{Partition, PartitionError, FlashOrigin};
+
+use embedded_storage::nor_flash::MultiwriteNorFlash;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
+
+use core::marker::PhantomData;
+
+// Log-structured filesystem
+//
+// All changes are appended as records to a circular log of erase blocks, so
+// every block gets erased equally often. When space runs out, the oldest
+// block is garbage collected: its live records are copied to the head of the
+// log, after which the block is reused. An interrupted write or garbage
+// collection leaves either the old or the new state behind, never a mix.
+//
+// The flash is passed to every call instead of being owned, so it can stay a
+// separate shared resource.
+
+const FREE_BLOCK: u32 = u32::MAX;
+
+// Blocks kept free so garbage collection always has somewhere to copy to,
+// even when it has been interrupted before
+const RESERVED_BLOCKS: usize = 2;
+
+// Don't start a data record at the end of a block with less room than this
+const MIN_DATA_CHUNK: u32 = 64;
+
+// Used while mounting for files whose Create record wasn't found (yet)
+const NOT_CREATED: u32 = u32::MAX;
+
+// Extents are kept in RAM, so a file that has more of them than this on top
+// of what it needs when written in one go is rewritten in big records
+const MAX_FRAGMENTS: usize = 16;
+
+pub const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug)]
+pub enum FilesystemError<E> {
+    Flash(PartitionError<E>),
+    NotFormatted,   // No valid blocks found while mounting
+    NotFound,
+    NoSpace,
+    InvalidName,
+    InvalidSeek,
+}
+
+impl<E> From<PartitionError<E>> for FilesystemError<E> {
+    fn from(error: PartitionError<E>) -> FilesystemError<E> {
+        FilesystemError::Flash(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(u32),
+    End(i32),
+    Current(i32),
+}
+
+// Handle to an open file, only valid for the filesystem that returned it
+#[derive(Debug)]
+pub struct File {
+    id: u32,
+    position: u32,
+}
+
+#[derive(Debug)]
+pub struct DirEntry<'a> {
+    pub name: &'a str,
+    pub size: u32,
+}
+
+#[derive(Debug)]
+struct Extent {
+    seq: u32,
+    offset: u32,    // Offset in the file
+    len: u32,
+    address: u32,   // Offset of the record in the partition
+}
+
+#[derive(Debug)]
+struct FileEntry {
+    id: u32,
+    name: String,
+    created: u32,
+    address: u32,           // Offset of the Create record in the partition
+    extents: Vec<Extent>,   // Sorted by sequence number
+}
+
+impl FileEntry {
+    fn size(&self) -> u32 {
+        self.extents.iter()
+            .map(|extent| extent.offset + extent.len)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // An extent is only needed while newer extents don't cover all of it
+    fn is_shadowed(&self, extent: &Extent) -> bool {
+        let end = extent.offset + extent.len;
+
+        let mut newer: Vec<(u32, u32)> = self.extents.iter()
+            .filter(|other| {
+                other.seq > extent.seq
+                    && other.offset < end
+                    && other.offset + other.len > extent.offset
+            })
+            .map(|other| (other.offset, other.offset + other.len))
+            .collect();
+        newer.sort_unstable();
+
+        let mut covered = extent.offset;
+        for (from, to) in newer {
+            if from > covered {
+                return false;
+            }
+            covered = covered.max(to);
+        }
+
+        covered >= end
+    }
+
+    // Forget extents that can't be read anymore, garbage collection drops
+    // their records
+    fn prune(&mut self) {
+        let mut i = 0;
+        while i < self.extents.len() {
+            if self.is_shadowed(&self.extents[i]) {
+                self.extents.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+pub struct Filesystem<F> {
+    partition: Partition<F>,
+    block_size: u32,
+    blocks: Vec<u32>,           // Sequence number of every block, or FREE_BLOCK
+    head: Option<(u32, u32)>,   // Block and offset to append to, None if full
+    next_seq: u32,
+    next_block_seq: u32,
+    next_file_id: u32,
+    files: Vec<FileEntry>,
+    _flash: PhantomData<F>,
+}
+
+impl<F: MultiwriteNorFlash + FlashOrigin> Filesystem<F> {
+    fn empty(partition: Partition<F>) -> Self {
+        let block_size = F::ERASE_SIZE as u32;
+
+        // Records and block headers are written in RECORD_ALIGN chunks
+        assert!((RECORD_ALIGN as usize).is_multiple_of(F::WRITE_SIZE));
+        assert!(partition.start.is_multiple_of(block_size) && partition.size.is_multiple_of(block_size));
+
+        Filesystem {
+            partition,
+            block_size,
+            blocks: vec![FREE_BLOCK; (partition.size / block_size) as usize],
+            head: None,
+            next_seq: 0,
+            next_block_seq: 0,
+            next_file_id: 0,
+            files: Vec::new(),
+            _flash: PhantomData,
+        }
+    }
+
+    // Mount the filesystem in `partition`. This reads every record header,
+    // so `progress` is called after every block to allow petting the
+    // watchdog.
+    pub fn mount(
+        flash: &mut F,
+        partition: Partition<F>,
+        mut progress: impl FnMut(),
+    ) -> Result<Self, FilesystemError<F::Error>> {
+        let mut fs = Self::empty(partition);
+        fs.scan_block_headers(flash)?;
+
+        if fs.blocks.iter().all(|seq| *seq == FREE_BLOCK) {
+            return Err(FilesystemError::NotFormatted);
+        }
+
+        let mut used_blocks: Vec<u32> = (0..fs.blocks.len() as u32)
+            .filter(|block| fs.blocks[*block as usize] != FREE_BLOCK)
+            .collect();
+        used_blocks.sort_by_key(|block| fs.blocks[*block as usize]);
+
+        let mut deleted = Vec::new();
+        for block in used_blocks.iter() {
+            let end = fs.replay_block(flash, *block, &mut deleted)?;
+
+            // Only the newest block can be appended to, and only if nothing
+            // was (partially) written after its last record
+            if Some(block) == used_blocks.last() {
+                if let Some(offset) = end {
+                    if fs.is_erased(flash, *block, offset)? {
+                        fs.head = Some((*block, offset));
+                    }
+                }
+            }
+            fs.next_block_seq = fs.blocks[*block as usize] + 1;
+            progress();
+        }
+
+        fs.files.retain(|file| file.created != NOT_CREATED && !deleted.contains(&file.id));
+
+        // A file that was replaced by create() could still be there if the
+        // Delete record didn't make it to flash
+        let mut i = 0;
+        while i < fs.files.len() {
+            let newer_exists = fs.files.iter().any(|other| {
+                other.name == fs.files[i].name && other.created > fs.files[i].created
+            });
+            if newer_exists {
+                fs.files.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        for file in fs.files.iter_mut() {
+            file.extents.sort_by_key(|extent| extent.seq);
+            file.prune();
+        }
+
+        Ok(fs)
+    }
+
+    // Create an empty filesystem in `partition`, `progress` is called like
+    // for mount()
+    pub fn format(
+        flash: &mut F,
+        partition: Partition<F>,
+        mut progress: impl FnMut(),
+    ) -> Result<Self, FilesystemError<F::Error>> {
+        let mut fs = Self::empty(partition);
+        fs.scan_block_headers(flash)?;
+
+        for block in 0..fs.blocks.len() as u32 {
+            if fs.blocks[block as usize] != FREE_BLOCK {
+                fs.retire_block(flash, block)?;
+                progress();
+            }
+        }
+
+        fs.allocate_block(flash)?;
+
+        Ok(fs)
+    }
+
+    pub fn mount_or_format(
+        flash: &mut F,
+        partition: Partition<F>,
+        mut progress: impl FnMut(),
+    ) -> Result<Self, FilesystemError<F::Error>> {
+        match Self::mount(flash, partition, &mut progress) {
+            Err(FilesystemError::NotFormatted) => Self::format(flash, partition, progress),
+            result => result,
+        }
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = DirEntry<'_>> {
+        self.files.iter().map(|file| DirEntry {
+            name: &file.name,
+            size: file.size(),
+        })
+    }
+
+    pub fn open(&self, name: &str) -> Result<File, FilesystemError<F::Error>> {
+        let file = self.files.iter()
+            .find(|file| file.name == name)
+            .ok_or(FilesystemError::NotFound)?;
+
+        Ok(File {
+            id: file.id,
+            position: 0,
+        })
+    }
+
+    // Create a new, empty file, replacing the file with the same name if
+    // there is one
+    pub fn create(&mut self, flash: &mut F, name: &str) -> Result<File, FilesystemError<F::Error>> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(FilesystemError::InvalidName);
+        }
+
+        let id = self.next_file_id;
+        let seq = self.next_seq;
+        let header = RecordHeader::new(RecordKind::Create, id, seq, 0, name.as_bytes());
+        let address = self.append(flash, header, name.as_bytes(), false)?;
+        self.next_file_id += 1;
+        self.next_seq += 1;
+
+        let replaced = self.files.iter().position(|file| file.name == name);
+
+        self.files.push(FileEntry {
+            id,
+            name: String::from(name),
+            created: seq,
+            address,
+            extents: Vec::new(),
+        });
+
+        if let Some(index) = replaced {
+            let old_id = self.files[index].id;
+            self.files.remove(index);
+            self.append_delete(flash, old_id)?;
+        }
+
+        Ok(File {
+            id,
+            position: 0,
+        })
+    }
+
+    pub fn delete(&mut self, flash: &mut F, name: &str) -> Result<(), FilesystemError<F::Error>> {
+        let index = self.files.iter()
+            .position(|file| file.name == name)
+            .ok_or(FilesystemError::NotFound)?;
+
+        // Out of the index first, so garbage collection can make room for
+        // the Delete record on a full filesystem
+        let entry = self.files.remove(index);
+        if let Err(e) = self.append_delete(flash, entry.id) {
+            self.files.insert(index, entry);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    pub fn size(&self, file: &File) -> Result<u32, FilesystemError<F::Error>> {
+        Ok(self.file_entry(file.id)?.size())
+    }
+
+    // Move the position of `file`, returns the new position
+    pub fn seek(&self, file: &mut File, position: SeekFrom) -> Result<u32, FilesystemError<F::Error>> {
+        let (base, delta) = match position {
+            SeekFrom::Start(position) => (position as i64, 0),
+            SeekFrom::End(delta) => (self.size(file)? as i64, delta as i64),
+            SeekFrom::Current(delta) => (file.position as i64, delta as i64),
+        };
+
+        let new_position: u32 = (base + delta).try_into()
+            .map_err(|_| FilesystemError::InvalidSeek)?;
+        file.position = new_position;
+
+        Ok(new_position)
+    }
+
+    // Read from the current position of `file`, returns the amount of bytes
+    // read (0 at the end of the file). Unwritten parts of the file read as 0.
+    pub fn read(&self, flash: &mut F, file: &mut File, buffer: &mut [u8]) -> Result<usize, FilesystemError<F::Error>> {
+        let entry = self.file_entry(file.id)?;
+
+        let size = entry.size();
+        if file.position >= size {
+            return Ok(0);
+        }
+
+        let len = buffer.len().min((size - file.position) as usize);
+        let start = file.position;
+        let end = start + len as u32;
+        let buffer = &mut buffer[..len];
+        buffer.fill(0);
+
+        // Extents are sorted, so newer data overwrites older data
+        for extent in entry.extents.iter() {
+            let from = start.max(extent.offset);
+            let to = end.min(extent.offset + extent.len);
+            if from >= to {
+                continue;
+            }
+
+            self.partition.read(
+                flash,
+                extent.address + RECORD_HEADER_SIZE + (from - extent.offset),
+                &mut buffer[(from - start) as usize..(to - start) as usize],
+            )?;
+        }
+
+        file.position = end;
+
+        Ok(len)
+    }
+
+    // Write `data` at the current position of `file`
+    pub fn write(&mut self, flash: &mut F, file: &mut File, data: &[u8]) -> Result<(), FilesystemError<F::Error>> {
+        self.write_records(flash, file, data)?;
+
+        let max_payload = self.max_payload();
+        let entry = self.file_entry_mut(file.id)?;
+        entry.prune();
+
+        // Every chunk can be split once at the end of a block
+        let needed = 2 * entry.size().div_ceil(max_payload) as usize;
+        if entry.extents.len() > needed + MAX_FRAGMENTS {
+            match self.compact(flash, file.id) {
+                // The data is written, it just stays fragmented for now
+                Err(FilesystemError::NoSpace) => {},
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn max_payload(&self) -> u32 {
+        (self.block_size - BLOCK_HEADER_SIZE - RECORD_HEADER_SIZE).min(u16::MAX as u32)
+    }
+
+    // Rewrite the whole file in records that are as big as possible, which
+    // makes all of its old extents obsolete
+    fn compact(&mut self, flash: &mut F, id: u32) -> Result<(), FilesystemError<F::Error>> {
+        let size = self.file_entry(id)?.size();
+        let mut buffer = vec![0; self.max_payload() as usize];
+        let mut file = File {
+            id,
+            position: 0,
+        };
+
+        while file.position < size {
+            let start = file.position;
+            let len = self.read(flash, &mut file, &mut buffer)?;
+            file.position = start;
+            self.write_records(flash, &mut file, &buffer[..len])?;
+        }
+
+        self.file_entry_mut(id)?.prune();
+
+        Ok(())
+    }
+
+    fn write_records(&mut self, flash: &mut F, file: &mut File, data: &[u8]) -> Result<(), FilesystemError<F::Error>> {
+        self.file_entry(file.id)?;
+
+        let max_payload = self.max_payload();
+
+        let mut remaining = data;
+        while !remaining.is_empty() {
+            // Fill up the current block if a reasonable chunk still fits
+            let room = match self.head {
+                Some((_, offset)) => (self.block_size - offset)
+                    .saturating_sub(RECORD_HEADER_SIZE) / RECORD_ALIGN * RECORD_ALIGN,
+                None => 0,
+            };
+            let wanted = remaining.len() as u32;
+            let len = if room >= wanted || room >= MIN_DATA_CHUNK {
+                room.min(wanted)
+            } else {
+                max_payload.min(wanted)
+            };
+
+            let (chunk, rest) = remaining.split_at(len as usize);
+
+            let seq = self.next_seq;
+            let header = RecordHeader::new(RecordKind::Data, file.id, seq, file.position, chunk);
+            let address = self.append(flash, header, chunk, false)?;
+            self.next_seq += 1;
+
+            self.file_entry_mut(file.id)?.extents.push(Extent {
+                seq,
+                offset: file.position,
+                len,
+                address,
+            });
+
+            file.position += len;
+            remaining = rest;
+        }
+
+        Ok(())
+    }
+
+    fn file_entry(&self, id: u32) -> Result<&FileEntry, FilesystemError<F::Error>> {
+        self.files.iter()
+            .find(|file| file.id == id)
+            .ok_or(FilesystemError::NotFound)
+    }
+
+    fn file_entry_mut(&mut self, id: u32) -> Result<&mut FileEntry, FilesystemError<F::Error>> {
+        self.files.iter_mut()
+            .find(|file| file.id == id)
+            .ok_or(FilesystemError::NotFound)
+    }
+
+    // Offset of `block` in the partition
+    fn block_address(&self, block: u32) -> u32 {
+        block * self.block_size
+    }
+
+    fn free_blocks(&self) -> usize {
+        self.blocks.iter().filter(|seq| **seq == FREE_BLOCK).count()
+    }
+
+    fn scan_block_headers(&mut self, flash: &mut F) -> Result<(), FilesystemError<F::Error>> {
+        let mut header = [0; BLOCK_HEADER_SIZE as usize];
+        for block in 0..self.blocks.len() as u32 {
+            self.partition.read(flash, self.block_address(block), &mut header)?;
+            self.blocks[block as usize] = parse_block_header(&header).unwrap_or(FREE_BLOCK);
+        }
+
+        Ok(())
+    }
+
+    fn read_record_header(&self, flash: &mut F, address: u32) -> Result<ParsedRecord, FilesystemError<F::Error>> {
+        let mut header = [0; RECORD_HEADER_SIZE as usize];
+        self.partition.read(flash, address, &mut header)?;
+
+        Ok(RecordHeader::parse(&header))
+    }
+
+    // Add all records of `block` to the index, returns the offset after the
+    // last record or None if an invalid record was found
+    fn replay_block(
+        &mut self,
+        flash: &mut F,
+        block: u32,
+        deleted: &mut Vec<u32>,
+    ) -> Result<Option<u32>, FilesystemError<F::Error>> {
+        let mut offset = BLOCK_HEADER_SIZE;
+
+        while offset + RECORD_HEADER_SIZE <= self.block_size {
+            let address = self.block_address(block) + offset;
+            let header = match self.read_record_header(flash, address)? {
+                ParsedRecord::Valid(header) => header,
+                ParsedRecord::Unwritten => return Ok(Some(offset)),
+                ParsedRecord::Invalid => return Ok(None),
+            };
+
+            self.next_seq = self.next_seq.max(header.seq + 1);
+            self.next_file_id = self.next_file_id.max(header.file_id + 1);
+
+            match header.kind {
+                RecordKind::Create => {
+                    let mut name = vec![0; header.len as usize];
+                    self.partition.read(flash, address + RECORD_HEADER_SIZE, &mut name)?;
+                    let name = String::from_utf8(name).unwrap_or_default();
+
+                    let entry = self.mount_entry(header.file_id);
+                    entry.name = name;
+                    entry.created = header.seq;
+                    entry.address = address;
+                },
+                RecordKind::Data => {
+                    let entry = self.mount_entry(header.file_id);
+                    // Interrupted garbage collection leaves duplicates behind,
+                    // blocks are replayed oldest first so keep the last copy
+                    match entry.extents.iter_mut().find(|extent| extent.seq == header.seq) {
+                        Some(extent) => extent.address = address,
+                        None => entry.extents.push(Extent {
+                            seq: header.seq,
+                            offset: header.offset,
+                            len: header.len as u32,
+                            address,
+                        }),
+                    }
+                },
+                RecordKind::Delete => deleted.push(header.file_id),
+            }
+
+            offset += header.total_size();
+        }
+
+        Ok(Some(offset.min(self.block_size)))
+    }
+
+    // Records of a file can be replayed before its Create record, because
+    // garbage collection moves the Create record forward
+    fn mount_entry(&mut self, id: u32) -> &mut FileEntry {
+        let index = match self.files.iter().position(|file| file.id == id) {
+            Some(index) => index,
+            None => {
+                self.files.push(FileEntry {
+                    id,
+                    name: String::new(),
+                    created: NOT_CREATED,
+                    address: 0,
+                    extents: Vec::new(),
+                });
+                self.files.len() - 1
+            }
+        };
+
+        &mut self.files[index]
+    }
+
+    fn is_erased(&self, flash: &mut F, block: u32, offset: u32) -> Result<bool, FilesystemError<F::Error>> {
+        let mut buffer = [0; 64];
+        let mut address = self.block_address(block) + offset;
+        let end = self.block_address(block) + self.block_size;
+
+        while address < end {
+            let len = buffer.len().min((end - address) as usize);
+            self.partition.read(flash, address, &mut buffer[..len])?;
+            if buffer[..len].iter().any(|b| *b != 0xff) {
+                return Ok(false);
+            }
+            address += len as u32;
+        }
+
+        Ok(true)
+    }
+
+    // Append a record to the log, returns its address. Only garbage
+    // collection itself may use the reserved blocks.
+    fn append(
+        &mut self,
+        flash: &mut F,
+        header: RecordHeader,
+        payload: &[u8],
+        collecting: bool,
+    ) -> Result<u32, FilesystemError<F::Error>> {
+        let size = header.total_size();
+        let fits = |fs: &Self| matches!(fs.head, Some((_, offset)) if offset + size <= fs.block_size);
+
+        if !fits(self) {
+            if !collecting {
+                self.ensure_free_blocks(flash, size)?;
+            }
+            if !fits(self) {
+                self.allocate_block(flash)?;
+            }
+        }
+
+        let (block, offset) = self.head.unwrap();
+        let address = self.block_address(block) + offset;
+
+        // If anything fails from here on, the rest of the block can't be used
+        self.head = None;
+
+        if !payload.is_empty() {
+            let mut padded = payload.to_vec();
+            padded.resize((size - RECORD_HEADER_SIZE) as usize, 0xff);
+            self.partition.write(flash, address + RECORD_HEADER_SIZE, &padded)?;
+        }
+        self.partition.write(flash, address, &header.to_bytes())?;
+
+        self.head = Some((block, offset + size));
+
+        Ok(address)
+    }
+
+    fn append_delete(&mut self, flash: &mut F, id: u32) -> Result<(), FilesystemError<F::Error>> {
+        let header = RecordHeader::new(RecordKind::Delete, id, self.next_seq, 0, &[]);
+        self.append(flash, header, &[], false)?;
+        self.next_seq += 1;
+
+        Ok(())
+    }
+
+    // Start a new block after the current head
+    fn allocate_block(&mut self, flash: &mut F) -> Result<(), FilesystemError<F::Error>> {
+        let count = self.blocks.len() as u32;
+        let after = self.head.map(|(block, _)| block)
+            .or_else(|| (0..count).max_by_key(|block| {
+                let seq = self.blocks[*block as usize];
+                if seq == FREE_BLOCK { 0 } else { seq as u64 + 1 }
+            }))
+            .unwrap_or(count - 1);
+
+        let block = (1..=count)
+            .map(|i| (after + i) % count)
+            .find(|block| self.blocks[*block as usize] == FREE_BLOCK)
+            .ok_or(FilesystemError::NoSpace)?;
+
+        let address = self.block_address(block);
+        self.head = None;
+        self.partition.erase(flash, address, address + self.block_size)?;
+        self.partition.write(flash, address, &block_header(self.next_block_seq))?;
+
+        self.blocks[block as usize] = self.next_block_seq;
+        self.next_block_seq += 1;
+        self.head = Some((block, BLOCK_HEADER_SIZE));
+
+        Ok(())
+    }
+
+    // Garbage collect until a new block can be allocated without touching
+    // the reserved blocks
+    fn ensure_free_blocks(&mut self, flash: &mut F, needed: u32) -> Result<(), FilesystemError<F::Error>> {
+        if self.free_blocks() > RESERVED_BLOCKS {
+            return Ok(());
+        }
+
+        // Don't shuffle everything around if it would never fit
+        let usable = (self.blocks.len() - RESERVED_BLOCKS - 1) as u32
+            * (self.block_size - BLOCK_HEADER_SIZE);
+        if self.live_size() + needed > usable {
+            return Err(FilesystemError::NoSpace);
+        }
+
+        for _ in 0..self.blocks.len() {
+            if self.free_blocks() > RESERVED_BLOCKS {
+                return Ok(());
+            }
+            self.collect(flash)?;
+        }
+
+        Err(FilesystemError::NoSpace)
+    }
+
+    // Flash space needed for all live records
+    fn live_size(&self) -> u32 {
+        self.files.iter()
+            .map(|file| {
+                record_size(file.name.len() as u32) + file.extents.iter()
+                    .filter(|extent| !file.is_shadowed(extent))
+                    .map(|extent| record_size(extent.len))
+                    .sum::<u32>()
+            })
+            .sum()
+    }
+
+    // Copy the live records of the oldest block to the head, then free it
+    fn collect(&mut self, flash: &mut F) -> Result<(), FilesystemError<F::Error>> {
+        let head_block = self.head.map(|(block, _)| block);
+        let tail = (0..self.blocks.len() as u32)
+            .filter(|block| self.blocks[*block as usize] != FREE_BLOCK && Some(*block) != head_block)
+            .min_by_key(|block| self.blocks[*block as usize])
+            .ok_or(FilesystemError::NoSpace)?;
+
+        let mut offset = BLOCK_HEADER_SIZE;
+        while offset + RECORD_HEADER_SIZE <= self.block_size {
+            let address = self.block_address(tail) + offset;
+            let header = match self.read_record_header(flash, address)? {
+                ParsedRecord::Valid(header) => header,
+                _ => break,
+            };
+            offset += header.total_size();
+
+            if !self.is_live(&header, address) {
+                continue;
+            }
+
+            let mut payload = vec![0; header.len as usize];
+            self.partition.read(flash, address + RECORD_HEADER_SIZE, &mut payload)?;
+            if crc32(&payload) != header.data_crc {
+                // Bit errors, nothing we can do but drop it
+                self.forget_extent(&header, address);
+                continue;
+            }
+
+            let new_address = self.append(flash, header, &payload, true)?;
+
+            if let Ok(file) = self.file_entry_mut(header.file_id) {
+                match header.kind {
+                    RecordKind::Create => file.address = new_address,
+                    _ => if let Some(extent) = file.extents.iter_mut()
+                        .find(|extent| extent.seq == header.seq)
+                    {
+                        extent.address = new_address;
+                    },
+                }
+            }
+        }
+
+        self.retire_block(flash, tail)
+    }
+
+    fn is_live(&mut self, header: &RecordHeader, address: u32) -> bool {
+        let file = match self.files.iter_mut().find(|file| file.id == header.file_id) {
+            Some(file) => file,
+            // Deleted files have no live records, and neither do their
+            // Delete records because everything before them is gone by now
+            None => return false,
+        };
+
+        match header.kind {
+            RecordKind::Create => file.address == address,
+            RecordKind::Delete => false,
+            RecordKind::Data => {
+                let index = match file.extents.iter()
+                    .position(|extent| extent.seq == header.seq && extent.address == address)
+                {
+                    Some(index) => index,
+                    None => return false,
+                };
+
+                if file.is_shadowed(&file.extents[index]) {
+                    file.extents.remove(index);
+                    return false;
+                }
+
+                true
+            },
+        }
+    }
+
+    fn forget_extent(&mut self, header: &RecordHeader, address: u32) {
+        if let Ok(file) = self.file_entry_mut(header.file_id) {
+            file.extents.retain(|extent| !(extent.seq == header.seq && extent.address == address));
+        }
+    }
+
+    // Invalidate the block header so the block is free, even if it is only
+    // partially erased when it gets reused
+    fn retire_block(&mut self, flash: &mut F, block: u32) -> Result<(), FilesystemError<F::Error>> {
+        self.partition.write(flash, self.block_address(block), &[0; BLOCK_HEADER_SIZE as usize])?;
+        self.blocks[block as usize] = FREE_BLOCK;
+
+        if matches!(self.head, Some((head, _)) if head == block) {
+            self.head = None;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::{SimulatedFlash, SimulatedFlashError};
+
+    const BLOCKS: u32 = 16;
+    const SIZE: u32 = BLOCKS * 0x1000;
+
+    type Fs = Filesystem<SimulatedFlash>;
+
+    const PARTITION: Partition<SimulatedFlash> = Partition::new("TEST", 0, SIZE);
+
+    fn mount(flash: &mut SimulatedFlash) -> Fs {
+        Fs::mount(flash, PARTITION, || {}).unwrap()
+    }
+
+    fn read_all(fs: &Fs, flash: &mut SimulatedFlash, name: &str) -> Vec<u8> {
+        let mut file = fs.open(name).unwrap();
+        let mut contents = vec![0; fs.size(&file).unwrap() as usize];
+        assert_eq!(fs.read(flash, &mut file, &mut contents).unwrap(), contents.len());
+        contents
+    }
+
+    fn write_file(fs: &mut Fs, flash: &mut SimulatedFlash, name: &str, data: &[u8]) {
+        let mut file = fs.create(flash, name).unwrap();
+        fs.write(flash, &mut file, data).unwrap();
+    }
+
+    fn extent_count(fs: &Fs, name: &str) -> usize {
+        fs.files.iter().find(|file| file.name == name).unwrap().extents.len()
+    }
+
+    #[test]
+    fn format_and_mount() {
+        let mut flash = SimulatedFlash::new(SIZE);
+        assert!(matches!(Fs::mount(&mut flash, PARTITION, || {}), Err(FilesystemError::NotFormatted)));
+
+        let mut blocks = 0;
+        let mut fs = Fs::mount_or_format(&mut flash, PARTITION, || blocks += 1).unwrap();
+        write_file(&mut fs, &mut flash, "hello", b"world");
+
+        let mut blocks = 0;
+        let fs = Fs::mount_or_format(&mut flash, PARTITION, || blocks += 1).unwrap();
+        assert_eq!(blocks, 1);
+        assert_eq!(read_all(&fs, &mut flash, "hello"), b"world");
+
+        let entries: Vec<(&str, u32)> = fs.list().map(|entry| (entry.name, entry.size)).collect();
+        assert_eq!(entries, [("hello", 5)]);
+    }
+
+    #[test]
+    fn stays_in_partition() {
+        // Filled up and garbage collected between two neighbours
+        let partition = Partition::new("TEST", 0x1000, SIZE);
+        let mut flash = SimulatedFlash::new(SIZE + 0x2000);
+        let mut fs = Fs::format(&mut flash, partition, || {}).unwrap();
+        for i in 0..64u32 {
+            write_file(&mut fs, &mut flash, "file", &[i as u8; 2000]);
+        }
+
+        let fs = Fs::mount(&mut flash, partition, || {}).unwrap();
+        assert_eq!(read_all(&fs, &mut flash, "file"), [63; 2000]);
+
+        let contents = flash.contents();
+        assert!(contents[..0x1000].iter().all(|b| *b == 0xff));
+        assert!(contents[0x1000 + SIZE as usize..].iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn seek_and_overwrite() {
+        let mut flash = SimulatedFlash::new(SIZE);
+        let mut fs = Fs::format(&mut flash, PARTITION, || {}).unwrap();
+
+        let mut file = fs.create(&mut flash, "a").unwrap();
+        fs.write(&mut flash, &mut file, b"0123456789").unwrap();
+        assert_eq!(fs.seek(&mut file, SeekFrom::Start(2)).unwrap(), 2);
+        fs.write(&mut flash, &mut file, b"ab").unwrap();
+        assert_eq!(fs.seek(&mut file, SeekFrom::End(2)).unwrap(), 12);
+        fs.write(&mut flash, &mut file, b"cd").unwrap();
+        assert!(matches!(fs.seek(&mut file, SeekFrom::Current(-15)), Err(FilesystemError::InvalidSeek)));
+
+        // The gap reads as zeros
+        let expected = b"01ab456789\0\0cd";
+        assert_eq!(read_all(&fs, &mut flash, "a"), expected);
+        let fs = mount(&mut flash);
+        assert_eq!(read_all(&fs, &mut flash, "a"), expected);
+    }
+
+    #[test]
+    fn create_replaces_and_delete() {
+        let mut flash = SimulatedFlash::new(SIZE);
+        let mut fs = Fs::format(&mut flash, PARTITION, || {}).unwrap();
+
+        write_file(&mut fs, &mut flash, "a", b"old");
+        write_file(&mut fs, &mut flash, "a", b"new!");
+        write_file(&mut fs, &mut flash, "b", b"gone");
+        fs.delete(&mut flash, "b").unwrap();
+        assert!(matches!(fs.delete(&mut flash, "b"), Err(FilesystemError::NotFound)));
+        assert!(matches!(fs.create(&mut flash, ""), Err(FilesystemError::InvalidName)));
+
+        let fs = mount(&mut flash);
+        assert_eq!(read_all(&fs, &mut flash, "a"), b"new!");
+        assert!(matches!(fs.open("b"), Err(FilesystemError::NotFound)));
+        assert_eq!(fs.list().count(), 1);
+    }
+
+    #[test]
+    fn extents_covered_together_are_dropped() {
+        let mut flash = SimulatedFlash::new(SIZE);
+        let mut fs = Fs::format(&mut flash, PARTITION, || {}).unwrap();
+
+        let mut file = fs.create(&mut flash, "a").unwrap();
+        fs.write(&mut flash, &mut file, &[1; 8]).unwrap();
+        fs.seek(&mut file, SeekFrom::Start(4)).unwrap();
+        fs.write(&mut flash, &mut file, &[2; 8]).unwrap();
+        assert_eq!(extent_count(&fs, "a"), 2);
+
+        // Neither newer extent covers the first one on its own
+        fs.seek(&mut file, SeekFrom::Start(0)).unwrap();
+        fs.write(&mut flash, &mut file, &[3; 4]).unwrap();
+        assert_eq!(extent_count(&fs, "a"), 2);
+
+        let expected = [3, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2];
+        assert_eq!(read_all(&fs, &mut flash, "a"), expected);
+        let fs = mount(&mut flash);
+        assert_eq!(extent_count(&fs, "a"), 2);
+        assert_eq!(read_all(&fs, &mut flash, "a"), expected);
+    }
+
+    #[test]
+    fn small_appends_are_compacted() {
+        let mut flash = SimulatedFlash::new(SIZE);
+        let mut fs = Fs::format(&mut flash, PARTITION, || {}).unwrap();
+
+        let mut file = fs.create(&mut flash, "log").unwrap();
+        let mut expected = Vec::new();
+        for i in 0..3000u32 {
+            let byte = [i as u8];
+            fs.write(&mut flash, &mut file, &byte).unwrap();
+            expected.push(byte[0]);
+            assert!(extent_count(&fs, "log") <= 2 + MAX_FRAGMENTS + 1);
+        }
+
+        assert_eq!(read_all(&fs, &mut flash, "log"), expected);
+        let fs = mount(&mut flash);
+        assert!(extent_count(&fs, "log") <= 2 + MAX_FRAGMENTS + 1);
+        assert_eq!(read_all(&fs, &mut flash, "log"), expected);
+    }
+
+    #[test]
+    fn garbage_collection_levels_wear() {
+        let mut flash = SimulatedFlash::new(SIZE);
+        let mut fs = Fs::format(&mut flash, PARTITION, || {}).unwrap();
+
+        write_file(&mut fs, &mut flash, "keep", &[0x5a; 3000]);
+        for i in 0..200 {
+            write_file(&mut fs, &mut flash, "churn", &[i as u8; 2000]);
+        }
+
+        let fs = mount(&mut flash);
+        assert_eq!(read_all(&fs, &mut flash, "keep"), [0x5a; 3000]);
+        assert_eq!(read_all(&fs, &mut flash, "churn"), [199; 2000]);
+
+        let counts: Vec<u32> = (0..BLOCKS).map(|block| flash.erase_count(block * 0x1000)).collect();
+        let min = *counts.iter().min().unwrap();
+        let max = *counts.iter().max().unwrap();
+        assert!(min > 0 && max - min <= 2, "{:?}", counts);
+    }
+
+    #[test]
+    fn full_filesystem() {
+        let mut flash = SimulatedFlash::new(SIZE);
+        let mut fs = Fs::format(&mut flash, PARTITION, || {}).unwrap();
+
+        let mut file = fs.create(&mut flash, "big").unwrap();
+        let result = fs.write(&mut flash, &mut file, &[0; SIZE as usize]);
+        assert!(matches!(result, Err(FilesystemError::NoSpace)));
+
+        // Still usable after deleting
+        fs.delete(&mut flash, "big").unwrap();
+        write_file(&mut fs, &mut flash, "small", b"fits");
+        let fs = mount(&mut flash);
+        assert_eq!(read_all(&fs, &mut flash, "small"), b"fits");
+    }
+
+    // Loses power during every flash operation of `change` in turn, and
+    // checks the result with `check` after remounting
+    fn power_loss_test(
+        setup: impl Fn(&mut Fs, &mut SimulatedFlash),
+        change: impl Fn(&mut Fs, &mut SimulatedFlash) -> Result<(), FilesystemError<SimulatedFlashError>>,
+        check: impl Fn(&Fs, &mut SimulatedFlash),
+    ) {
+        for operations in 0.. {
+            let mut flash = SimulatedFlash::new(SIZE);
+            let mut fs = Fs::format(&mut flash, PARTITION, || {}).unwrap();
+            setup(&mut fs, &mut flash);
+
+            flash.fail_after(operations);
+            let completed = change(&mut fs, &mut flash).is_ok();
+            flash.power_cycle();
+
+            let mut fs = mount(&mut flash);
+            check(&fs, &mut flash);
+
+            // Still writable
+            write_file(&mut fs, &mut flash, "after", b"ok");
+            let fs = mount(&mut flash);
+            assert_eq!(read_all(&fs, &mut flash, "after"), b"ok");
+            check(&fs, &mut flash);
+
+            if completed {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn power_loss_during_overwrite() {
+        let old = vec![1; 6000];
+        let new = vec![2; 6000];
+
+        power_loss_test(
+            |fs, flash| write_file(fs, flash, "a", &old),
+            |fs, flash| {
+                let mut file = fs.open("a")?;
+                fs.write(flash, &mut file, &new)
+            },
+            |fs, flash| {
+                // Records are complete or missing, so a prefix of the new data
+                // can be there
+                let contents = read_all(fs, flash, "a");
+                assert_eq!(contents.len(), 6000);
+                let written = contents.iter().take_while(|b| **b == 2).count();
+                assert!(contents[written..].iter().all(|b| *b == 1));
+            },
+        );
+    }
+
+    #[test]
+    fn power_loss_during_replace() {
+        power_loss_test(
+            |fs, flash| write_file(fs, flash, "a", b"old"),
+            |fs, flash| {
+                let mut file = fs.create(flash, "a")?;
+                fs.write(flash, &mut file, b"new")
+            },
+            |fs, flash| {
+                let contents = read_all(fs, flash, "a");
+                assert!(contents.is_empty() || contents == b"old" || contents == b"new");
+                assert_eq!(fs.list().filter(|entry| entry.name == "a").count(), 1);
+            },
+        );
+    }
+
+    #[test]
+    fn power_loss_during_garbage_collection() {
+        power_loss_test(
+            |fs, flash| {
+                write_file(fs, flash, "keep", &[0x5a; 5000]);
+                // Fill up until the next write needs garbage collection
+                for i in 0..6 {
+                    write_file(fs, flash, "churn", &[i; 8000]);
+                }
+            },
+            |fs, flash| {
+                let mut file = fs.create(flash, "churn")?;
+                fs.write(flash, &mut file, &[9; 8000])
+            },
+            |fs, flash| {
+                assert_eq!(read_all(fs, flash, "keep"), [0x5a; 5000]);
+            },
+        );
+    }
+
+    #[test]
+    fn bit_error_in_record() {
+        let mut flash = SimulatedFlash::new(SIZE);
+        let mut fs = Fs::format(&mut flash, PARTITION, || {}).unwrap();
+        write_file(&mut fs, &mut flash, "a", b"first");
+        write_file(&mut fs, &mut flash, "b", b"second");
+
+        // Corrupt the Create record of "b", which ends the valid part of the
+        // block so nothing after it can be trusted
+        let address = fs.files.iter().find(|file| file.name == "b").unwrap().address;
+        flash.inject_bit_error(address + 4, 0);
+
+        let mut fs = mount(&mut flash);
+        assert_eq!(read_all(&fs, &mut flash, "a"), b"first");
+        assert!(fs.open("b").is_err());
+
+        // Appending continues in a new block
+        write_file(&mut fs, &mut flash, "c", b"third");
+        let fs = mount(&mut flash);
+        assert_eq!(read_all(&fs, &mut flash, "c"), b"third");
+    }
+}
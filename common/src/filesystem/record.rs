@@ -0,0 +1,159 @@
+use crate::flash::crc32;
+
+// On-flash layout
+//
+// Every erase block starts with a block header, followed by records. Each
+// record is a header followed by its payload, padded to RECORD_ALIGN bytes.
+// Unwritten space is erased (0xff), so a record kind of 0xff marks the end
+// of the block.
+//
+// Block header (16 bytes):
+//  - magic (4 bytes, "PTFS")
+//  - format version (u32)
+//  - block sequence number (u32), increasing with every allocated block
+//  - CRC-32 of the previous 12 bytes
+//
+// Record header (24 bytes):
+//  - kind (u8)
+//  - reserved (u8, 0xff)
+//  - payload length (u16)
+//  - file id (u32)
+//  - record sequence number (u32), newer records override older ones
+//  - offset in the file (u32, data records only)
+//  - CRC-32 of the payload
+//  - CRC-32 of the previous 20 bytes
+//
+// The payload is always written before the header, so a record with a
+// valid header also has a complete payload.
+
+pub const BLOCK_MAGIC: [u8; 4] = *b"PTFS";
+pub const BLOCK_VERSION: u32 = 1;
+pub const BLOCK_HEADER_SIZE: u32 = 16;
+
+pub const RECORD_HEADER_SIZE: u32 = 24;
+pub const RECORD_ALIGN: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordKind {
+    Create,     // Payload is the file name
+    Data,       // Payload is written at `offset` in the file
+    Delete,     // No payload
+}
+
+impl RecordKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            RecordKind::Create => 0x01,
+            RecordKind::Data => 0x02,
+            RecordKind::Delete => 0x03,
+        }
+    }
+
+    fn from_byte(value: u8) -> Option<RecordKind> {
+        match value {
+            0x01 => Some(RecordKind::Create),
+            0x02 => Some(RecordKind::Data),
+            0x03 => Some(RecordKind::Delete),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordHeader {
+    pub kind: RecordKind,
+    pub len: u16,
+    pub file_id: u32,
+    pub seq: u32,
+    pub offset: u32,
+    pub data_crc: u32,
+}
+
+pub enum ParsedRecord {
+    Valid(RecordHeader),
+    Unwritten,  // End of the records in this block
+    Invalid,    // Interrupted or corrupted write
+}
+
+pub fn block_header(seq: u32) -> [u8; BLOCK_HEADER_SIZE as usize] {
+    let mut header = [0; BLOCK_HEADER_SIZE as usize];
+    header[0..4].copy_from_slice(&BLOCK_MAGIC);
+    header[4..8].copy_from_slice(&BLOCK_VERSION.to_le_bytes());
+    header[8..12].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc32(&header[0..12]);
+    header[12..16].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+// Returns the block sequence number if the header is valid
+pub fn parse_block_header(header: &[u8]) -> Option<u32> {
+    let crc = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    if header[0..4] != BLOCK_MAGIC
+        || u32::from_le_bytes(header[4..8].try_into().unwrap()) != BLOCK_VERSION
+        || crc32(&header[0..12]) != crc
+    {
+        return None;
+    }
+
+    Some(u32::from_le_bytes(header[8..12].try_into().unwrap()))
+}
+
+impl RecordHeader {
+    pub fn new(kind: RecordKind, file_id: u32, seq: u32, offset: u32, payload: &[u8]) -> Self {
+        RecordHeader {
+            kind,
+            len: payload.len().try_into().unwrap(),
+            file_id,
+            seq,
+            offset,
+            data_crc: crc32(payload),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; RECORD_HEADER_SIZE as usize] {
+        let mut header = [0; RECORD_HEADER_SIZE as usize];
+        header[0] = self.kind.to_byte();
+        header[1] = 0xff;
+        header[2..4].copy_from_slice(&self.len.to_le_bytes());
+        header[4..8].copy_from_slice(&self.file_id.to_le_bytes());
+        header[8..12].copy_from_slice(&self.seq.to_le_bytes());
+        header[12..16].copy_from_slice(&self.offset.to_le_bytes());
+        header[16..20].copy_from_slice(&self.data_crc.to_le_bytes());
+        let crc = crc32(&header[0..20]);
+        header[20..24].copy_from_slice(&crc.to_le_bytes());
+        header
+    }
+
+    pub fn parse(header: &[u8]) -> ParsedRecord {
+        if header.iter().all(|b| *b == 0xff) {
+            return ParsedRecord::Unwritten;
+        }
+
+        let crc = u32::from_le_bytes(header[20..24].try_into().unwrap());
+        if crc32(&header[0..20]) != crc {
+            return ParsedRecord::Invalid;
+        }
+
+        match RecordKind::from_byte(header[0]) {
+            Some(kind) => ParsedRecord::Valid(RecordHeader {
+                kind,
+                len: u16::from_le_bytes(header[2..4].try_into().unwrap()),
+                file_id: u32::from_le_bytes(header[4..8].try_into().unwrap()),
+                seq: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+                offset: u32::from_le_bytes(header[12..16].try_into().unwrap()),
+                data_crc: u32::from_le_bytes(header[16..20].try_into().unwrap()),
+            }),
+            None => ParsedRecord::Invalid,
+        }
+    }
+
+    // Size of header and payload, including padding
+    pub fn total_size(&self) -> u32 {
+        record_size(self.len as u32)
+    }
+}
+
+pub fn record_size(payload_len: u32) -> u32 {
+    let size = RECORD_HEADER_SIZE + payload_len;
+    size.div_ceil(RECORD_ALIGN) * RECORD_ALIGN
+}
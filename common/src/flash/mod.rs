@@ -0,0 +1,10 @@
+mod crc;
+#[cfg(any(test, feature = "simulated"))]
+mod simulated;
+pub mod sfdp;
+pub mod partition;
+
+pub use crc::crc32;
+#[cfg(any(test, feature = "simulated"))]
+pub use simulated::{SimulatedFlash, SimulatedFlashError};
+pub use sfdp::{FlashGeometry, EraseType};
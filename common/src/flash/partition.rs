@@ -0,0 +1,193 @@
+use embedded_storage::nor_flash::{ErrorType, ReadNorFlash, NorFlash};
+
+use core::marker::PhantomData;
+
+// Partitions use absolute addresses, reads and writes take an offset relative
+// to the start of the partition and are rejected if they don't fit in it. The
+// partition table itself is part of the firmware.
+
+// Address that offset 0 of a flash driver corresponds to
+pub trait FlashOrigin {
+    const ORIGIN: u32;
+}
+
+#[derive(Debug)]
+pub enum PartitionError<E> {
+    OutOfBounds,
+    Flash(E),
+}
+
+impl<E> From<E> for PartitionError<E> {
+    fn from(error: E) -> PartitionError<E> {
+        PartitionError::Flash(error)
+    }
+}
+
+#[derive(Debug)]
+pub struct Partition<F> {
+    pub name: &'static str,
+    pub start: u32,     // Absolute address
+    pub size: u32,
+    _flash: PhantomData<F>,
+}
+
+// Derived Clone/Copy would require F: Clone/Copy
+impl<F> Clone for Partition<F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<F> Copy for Partition<F> {}
+
+impl<F> Partition<F> {
+    pub const fn new(name: &'static str, start: u32, size: u32) -> Partition<F> {
+        Partition {
+            name,
+            start,
+            size,
+            _flash: PhantomData,
+        }
+    }
+
+    pub const fn end(&self) -> u32 {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.start && address < self.end()
+    }
+
+    // Whether [from, to) (absolute addresses) overlaps the partition
+    pub fn overlaps(&self, from: u32, to: u32) -> bool {
+        from < self.end() && to > self.start
+    }
+
+    // Whether `len` bytes starting at `offset` fit in the partition
+    fn fits(&self, offset: u32, len: usize) -> bool {
+        matches!(offset.checked_add(len as u32), Some(end) if end <= self.size)
+    }
+}
+
+// Whether `partitions` are sorted, don't overlap and fit in `capacity`, to
+// check the partition table at compile time
+pub const fn is_valid_layout<F>(partitions: &[Partition<F>], capacity: u32) -> bool {
+    let mut end = 0;
+    let mut i = 0;
+    while i < partitions.len() {
+        if partitions[i].start < end {
+            return false;
+        }
+        end = partitions[i].end();
+        i += 1;
+    }
+
+    end <= capacity
+}
+
+impl<F: FlashOrigin + ErrorType> Partition<F> {
+    // Start of the partition as an offset for the flash driver, fails if the
+    // partition starts before the flash does
+    pub fn offset(&self) -> Result<u32, PartitionError<F::Error>> {
+        self.start.checked_sub(F::ORIGIN).ok_or(PartitionError::OutOfBounds)
+    }
+}
+
+impl<F: NorFlash + FlashOrigin> Partition<F> {
+    pub fn read(&self, flash: &mut F, offset: u32, bytes: &mut [u8]) -> Result<(), PartitionError<F::Error>> {
+        if !self.fits(offset, bytes.len()) {
+            return Err(PartitionError::OutOfBounds);
+        }
+
+        Ok(ReadNorFlash::read(flash, self.offset()? + offset, bytes)?)
+    }
+
+    pub fn write(&self, flash: &mut F, offset: u32, bytes: &[u8]) -> Result<(), PartitionError<F::Error>> {
+        if !self.fits(offset, bytes.len()) {
+            return Err(PartitionError::OutOfBounds);
+        }
+
+        Ok(NorFlash::write(flash, self.offset()? + offset, bytes)?)
+    }
+
+    // Erase [from, to), both have to be aligned to F::ERASE_SIZE
+    pub fn erase(&self, flash: &mut F, from: u32, to: u32) -> Result<(), PartitionError<F::Error>> {
+        if from > to || !self.fits(from, (to - from) as usize) {
+            return Err(PartitionError::OutOfBounds);
+        }
+
+        let offset = self.offset()?;
+
+        Ok(NorFlash::erase(flash, offset + from, offset + to)?)
+    }
+
+    pub fn erase_all(&self, flash: &mut F) -> Result<(), PartitionError<F::Error>> {
+        self.erase(flash, 0, self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::SimulatedFlash;
+
+    use embedded_storage::nor_flash::NorFlashErrorKind;
+
+    // Flash that starts at 0x8000, like the internal flash driver
+    struct PrimarySlot;
+
+    impl ErrorType for PrimarySlot {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl FlashOrigin for PrimarySlot {
+        const ORIGIN: u32 = 0x8000;
+    }
+
+    #[test]
+    fn offset() {
+        let partition: Partition<PrimarySlot> = Partition::new("A", 0x9000, 0x1000);
+        assert_eq!(partition.offset().unwrap(), 0x1000);
+
+        let partition: Partition<PrimarySlot> = Partition::new("B", 0x7000, 0x1000);
+        assert!(matches!(partition.offset(), Err(PartitionError::OutOfBounds)));
+    }
+
+    #[test]
+    fn bounds() {
+        let mut flash = SimulatedFlash::new(0x4000);
+        let partition: Partition<SimulatedFlash> = Partition::new("A", 0x1000, 0x2000);
+        assert!(partition.contains(0x1000) && partition.contains(0x2fff));
+        assert!(!partition.contains(0x0fff) && !partition.contains(0x3000));
+        assert!(partition.overlaps(0x0000, 0x1001) && partition.overlaps(0x2fff, 0x4000));
+        assert!(!partition.overlaps(0x0000, 0x1000) && !partition.overlaps(0x3000, 0x4000));
+
+        partition.write(&mut flash, 0x1ffe, &[1, 2]).unwrap();
+        assert_eq!(flash.contents()[0x2ffe..0x3000], [1, 2]);
+        let mut bytes = [0; 2];
+        partition.read(&mut flash, 0x1ffe, &mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2]);
+
+        assert!(matches!(partition.write(&mut flash, 0x1fff, &[1, 2]), Err(PartitionError::OutOfBounds)));
+        assert!(matches!(partition.read(&mut flash, u32::MAX, &mut bytes), Err(PartitionError::OutOfBounds)));
+        assert!(matches!(partition.erase(&mut flash, 0x1000, 0x0000), Err(PartitionError::OutOfBounds)));
+        assert!(matches!(partition.erase(&mut flash, 0x1000, 0x3000), Err(PartitionError::OutOfBounds)));
+
+        partition.erase_all(&mut flash).unwrap();
+        assert!(flash.contents()[0x1000..0x3000].iter().all(|b| *b == 0xff));
+        assert_eq!(flash.erase_count(0x0000), 0);
+        assert_eq!(flash.erase_count(0x3000), 0);
+    }
+
+    #[test]
+    fn layout() {
+        let a: Partition<SimulatedFlash> = Partition::new("A", 0x0000, 0x1000);
+        let b = Partition::new("B", 0x1000, 0x2000);
+        let c = Partition::new("C", 0x2000, 0x1000);
+
+        assert!(is_valid_layout(&[a, b], 0x3000));
+        assert!(!is_valid_layout(&[a, b], 0x2fff));
+        assert!(!is_valid_layout(&[b, a], 0x3000));
+        assert!(!is_valid_layout(&[a, b, c], 0x4000));
+    }
+}
@@ -0,0 +1,242 @@
+// Serial Flash Discoverable Parameters (JESD216)
+//
+// Only the JEDEC Basic Flash Parameter Table is used, for the capacity, page
+// size and erase types. Everything else about the flash is assumed to be the
+// same as the XT25F32B.
+
+const SIGNATURE: u32 = 0x5044_4653; // "SFDP"
+const BASIC_PARAMETER_TABLE_ID: u8 = 0x00;
+
+// DWORDs 1, 2, 8, 9 and 11 of the Basic Flash Parameter Table
+const MIN_TABLE_DWORDS: u8 = 9;
+const PAGE_SIZE_DWORDS: u8 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EraseType {
+    pub size: u32,
+    pub opcode: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlashGeometry {
+    pub capacity: u32,
+    pub page_size: u32,
+    // Smallest first, unused entries are None
+    pub erase_types: [Option<EraseType>; 4],
+}
+
+impl Default for FlashGeometry {
+    // XT25F32B
+    fn default() -> FlashGeometry {
+        FlashGeometry {
+            capacity: 0x40_0000,
+            page_size: 0x100,
+            erase_types: [
+                Some(EraseType { size: 0x1000, opcode: 0x20 }),
+                Some(EraseType { size: 0x8000, opcode: 0x52 }),
+                Some(EraseType { size: 0x1_0000, opcode: 0xd8 }),
+                None,
+            ],
+        }
+    }
+}
+
+impl FlashGeometry {
+    pub fn smallest_erase(&self) -> Option<EraseType> {
+        self.erase_types[0]
+    }
+
+    // Largest erase that starts at `address` and doesn't go past `to`
+    pub fn largest_erase(&self, address: u32, to: u32) -> Option<EraseType> {
+        self.erase_types.iter()
+            .flatten()
+            .rfind(|erase| address.is_multiple_of(erase.size) && address + erase.size <= to)
+            .copied()
+    }
+}
+
+fn dword(bytes: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap())
+}
+
+// Parse the SFDP tables, `read` reads from the SFDP address space. Returns
+// None if the flash doesn't have (usable) SFDP tables.
+pub fn parse<E>(
+    mut read: impl FnMut(u32, &mut [u8]) -> Result<(), E>
+) -> Result<Option<FlashGeometry>, E> {
+    let mut header = [0; 8];
+    read(0, &mut header)?;
+
+    if dword(&header, 0) != SIGNATURE {
+        return Ok(None);
+    }
+
+    // The first parameter header is always the Basic Flash Parameter Table
+    let mut parameter_header = [0; 8];
+    read(8, &mut parameter_header)?;
+
+    let id = parameter_header[0];
+    let length = parameter_header[3];
+    let pointer = dword(&parameter_header, 1) & 0x00ff_ffff;
+
+    if id != BASIC_PARAMETER_TABLE_ID || length < MIN_TABLE_DWORDS {
+        return Ok(None);
+    }
+
+    let mut table = [0; PAGE_SIZE_DWORDS as usize * 4];
+    let table_len = length.min(PAGE_SIZE_DWORDS) as usize * 4;
+    read(pointer, &mut table[..table_len])?;
+
+    // DWORD 2: density in bits, as N - 1 or as 2^N
+    let density = dword(&table, 1);
+    let capacity = if density & 0x8000_0000 == 0 {
+        (density + 1) / 8
+    } else {
+        match (density & 0x7fff_ffff).checked_sub(3).and_then(|n| 1u32.checked_shl(n)) {
+            Some(capacity) => capacity,
+            None => return Ok(None),
+        }
+    };
+
+    // DWORDs 8 and 9: 4 erase types as (2^N size, opcode), N = 0 is unused
+    let mut erase_types = [None; 4];
+    for (i, erase_type) in erase_types.iter_mut().enumerate() {
+        let value = dword(&table, 7 + i / 2) >> ((i % 2) * 16);
+        let size_exponent = value & 0xff;
+        let opcode = ((value >> 8) & 0xff) as u8;
+
+        if size_exponent != 0 && size_exponent < 32 {
+            *erase_type = Some(EraseType { size: 1 << size_exponent, opcode });
+        }
+    }
+    erase_types.sort_by_key(|erase_type| erase_type.map_or(u32::MAX, |e| e.size));
+
+    // DWORD 11 (JESD216A and up): page size as 2^N, older tables mean 256
+    let page_size = if length >= PAGE_SIZE_DWORDS {
+        1 << ((dword(&table, 10) >> 4) & 0xf)
+    } else {
+        0x100
+    };
+
+    Ok(Some(FlashGeometry {
+        capacity,
+        page_size,
+        erase_types,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE_POINTER: u32 = 0x30;
+
+    // SFDP address space with a Basic Flash Parameter Table of `dwords`
+    fn sfdp(dwords: &[u32]) -> Vec<u8> {
+        let mut bytes = vec![0xff; TABLE_POINTER as usize];
+        bytes[0..4].copy_from_slice(b"SFDP");
+        bytes[4..8].copy_from_slice(&[0x06, 0x01, 0x00, 0xff]);
+        bytes[8..12].copy_from_slice(&[BASIC_PARAMETER_TABLE_ID, 0x06, 0x01, dwords.len() as u8]);
+        bytes[12..16].copy_from_slice(&TABLE_POINTER.to_le_bytes());
+        bytes[15] = 0xff;
+        for dword in dwords {
+            bytes.extend_from_slice(&dword.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn parse_bytes(bytes: &[u8]) -> Option<FlashGeometry> {
+        parse(|address, buffer: &mut [u8]| {
+            let start = address as usize;
+            buffer.copy_from_slice(&bytes[start..start + buffer.len()]);
+            Ok::<(), ()>(())
+        }).unwrap()
+    }
+
+    // Erase types as (2^N size, opcode), 4 of them over DWORDs 8 and 9
+    fn erase_dwords(types: [(u8, u8); 4]) -> [u32; 2] {
+        let erase = |(size, opcode): (u8, u8)| u32::from(size) | u32::from(opcode) << 8;
+        [
+            erase(types[0]) | erase(types[1]) << 16,
+            erase(types[2]) | erase(types[3]) << 16,
+        ]
+    }
+
+    fn table(density: u32, erase_types: [(u8, u8); 4], page_size: Option<u32>) -> Vec<u32> {
+        let [dword8, dword9] = erase_dwords(erase_types);
+        let mut dwords = vec![0xffff_ffff, density, 0, 0, 0, 0, 0, dword8, dword9];
+        if let Some(page_size) = page_size {
+            dwords.extend_from_slice(&[0, page_size.trailing_zeros() << 4]);
+        }
+        dwords
+    }
+
+    #[test]
+    fn xt25f32b() {
+        // 32 Mbit, 4K/32K/64K erases and 256 byte pages
+        let bytes = sfdp(&table(0x01ff_ffff, [(12, 0x20), (15, 0x52), (16, 0xd8), (0, 0)], Some(0x100)));
+        assert_eq!(parse_bytes(&bytes), Some(FlashGeometry::default()));
+    }
+
+    #[test]
+    fn erase_types_are_sorted() {
+        let bytes = sfdp(&table(0x01ff_ffff, [(16, 0xd8), (0, 0), (12, 0x20), (15, 0x52)], Some(0x100)));
+        let geometry = parse_bytes(&bytes).unwrap();
+
+        assert_eq!(geometry.erase_types, FlashGeometry::default().erase_types);
+        assert_eq!(geometry.smallest_erase(), Some(EraseType { size: 0x1000, opcode: 0x20 }));
+    }
+
+    #[test]
+    fn page_size_and_density() {
+        // JESD216 tables without DWORD 11 have 256 byte pages
+        let bytes = sfdp(&table(0x00ff_ffff, [(12, 0x20), (0, 0), (0, 0), (0, 0)], None));
+        let geometry = parse_bytes(&bytes).unwrap();
+        assert_eq!(geometry.capacity, 0x20_0000);
+        assert_eq!(geometry.page_size, 0x100);
+
+        // Density as 2^N bits, 2^34 bits is 2 GiB
+        let bytes = sfdp(&table(0x8000_0022, [(12, 0x20), (0, 0), (0, 0), (0, 0)], Some(0x200)));
+        let geometry = parse_bytes(&bytes).unwrap();
+        assert_eq!(geometry.capacity, 0x8000_0000);
+        assert_eq!(geometry.page_size, 0x200);
+
+        // Too large to address
+        let bytes = sfdp(&table(0x8000_0040, [(12, 0x20), (0, 0), (0, 0), (0, 0)], Some(0x100)));
+        assert_eq!(parse_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn unusable_tables() {
+        let valid = sfdp(&table(0x01ff_ffff, [(12, 0x20), (0, 0), (0, 0), (0, 0)], Some(0x100)));
+
+        // No SFDP support reads back as 0xff
+        assert_eq!(parse_bytes(&[0xff; 0x100]), None);
+
+        let mut bytes = valid.clone();
+        bytes[8] = 0x84;
+        assert_eq!(parse_bytes(&bytes), None, "not the basic parameter table");
+
+        let mut bytes = valid.clone();
+        bytes[11] = MIN_TABLE_DWORDS - 1;
+        assert_eq!(parse_bytes(&bytes), None, "table too short");
+    }
+
+    #[test]
+    fn read_errors() {
+        assert_eq!(parse(|_, _: &mut [u8]| Err("timeout")).unwrap_err(), "timeout");
+    }
+
+    #[test]
+    fn largest_erase() {
+        let geometry = FlashGeometry::default();
+        let size = |address, to| geometry.largest_erase(address, to).map(|erase| erase.size);
+
+        assert_eq!(size(0, 0x40_0000), Some(0x1_0000));
+        assert_eq!(size(0x8000, 0x40_0000), Some(0x8000));
+        assert_eq!(size(0x1_0000, 0x1_8000), Some(0x8000));
+        assert_eq!(size(0x1000, 0x40_0000), Some(0x1000));
+        assert_eq!(size(0x1000, 0x1800), None);
+        assert_eq!(size(0x800, 0x40_0000), None);
+    }
+}
@@ -0,0 +1,372 @@
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash, NorFlash,
+    MultiwriteNorFlash, check_read, check_write, check_erase,
+};
+
+use super::partition::FlashOrigin;
+
+use alloc::vec::Vec;
+use alloc::vec;
+
+// NOR flash in RAM, behaving like the XT25F32B-S external flash: programming
+// can only clear bits, erasing works on 4KiB sectors and a page program wraps
+// around at the end of its 256 byte page. It implements the same traits as
+// ExternalFlash, so storage code can run against it instead of real flash.
+//
+// Power loss is simulated by failing the n-th program/erase operation halfway
+// through, after which every operation fails until power_cycle() is called.
+
+const PAGE_SIZE: u32 = 0x100;
+const SECTOR_SIZE: u32 = 0x1000;
+
+pub struct SimulatedFlash {
+    data: Vec<u8>,
+    erase_counts: Vec<u32>,
+
+    operations_until_power_loss: Option<u32>,
+    powered: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SimulatedFlashError {
+    OutOfBounds,
+    NotAligned,
+    PowerLoss,
+}
+
+impl NorFlashError for SimulatedFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            SimulatedFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            SimulatedFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            SimulatedFlashError::PowerLoss => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for SimulatedFlashError {
+    fn from(kind: NorFlashErrorKind) -> SimulatedFlashError {
+        match kind {
+            NorFlashErrorKind::NotAligned => SimulatedFlashError::NotAligned,
+            _ => SimulatedFlashError::OutOfBounds,
+        }
+    }
+}
+
+impl SimulatedFlash {
+    // `size` has to be a multiple of the sector size, starts fully erased
+    pub fn new(size: u32) -> SimulatedFlash {
+        assert!(size.is_multiple_of(SECTOR_SIZE));
+
+        SimulatedFlash {
+            data: vec![0xff; size as usize],
+            erase_counts: vec![0; (size / SECTOR_SIZE) as usize],
+            operations_until_power_loss: None,
+            powered: true,
+        }
+    }
+
+    // Flash with the contents of a dump, `image` has to be a multiple of the
+    // sector size
+    pub fn from_image(image: Vec<u8>) -> SimulatedFlash {
+        let mut flash = SimulatedFlash::new(image.len() as u32);
+        flash.data = image;
+        flash
+    }
+
+    // Lose power during the program/erase operation after `operations` more
+    // successful ones
+    pub fn fail_after(&mut self, operations: u32) {
+        self.operations_until_power_loss = Some(operations);
+    }
+
+    // Restore power after a simulated power loss
+    pub fn power_cycle(&mut self) {
+        self.operations_until_power_loss = None;
+        self.powered = true;
+    }
+
+    // Flip a stored bit, like a retention error would
+    pub fn inject_bit_error(&mut self, address: u32, bit: u8) {
+        self.data[address as usize] ^= 1 << bit;
+    }
+
+    // Amount of times the sector `address` is in has been erased
+    pub fn erase_count(&self, address: u32) -> u32 {
+        self.erase_counts[(address / SECTOR_SIZE) as usize]
+    }
+
+    pub fn contents(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Returns false if power should be lost during this operation
+    fn start_operation(&mut self) -> Result<bool, SimulatedFlashError> {
+        if !self.powered {
+            return Err(SimulatedFlashError::PowerLoss);
+        }
+
+        match self.operations_until_power_loss.as_mut() {
+            Some(0) => {
+                self.powered = false;
+                Ok(false)
+            },
+            Some(remaining) => {
+                *remaining -= 1;
+                Ok(true)
+            },
+            None => Ok(true),
+        }
+    }
+
+    // Page Program command: data past the end of the page wraps around to
+    // the start of that page, like it does on the real chip
+    pub fn program_page(&mut self, start: u32, buffer: &[u8]) -> Result<(), SimulatedFlashError> {
+        if start as usize >= self.data.len() {
+            return Err(SimulatedFlashError::OutOfBounds);
+        }
+
+        let completes = self.start_operation()?;
+
+        // An interrupted program leaves half of the bytes programmed
+        let programmed = if completes { buffer.len() } else { buffer.len() / 2 };
+
+        let page_start = start & !(PAGE_SIZE - 1);
+        let mut offset = start - page_start;
+        for byte in buffer[..programmed].iter() {
+            self.data[(page_start + offset) as usize] &= *byte;
+            offset = (offset + 1) % PAGE_SIZE;
+        }
+
+        if completes {
+            Ok(())
+        } else {
+            Err(SimulatedFlashError::PowerLoss)
+        }
+    }
+
+    // Erase the sector (4096 bytes) `address` is in
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), SimulatedFlashError> {
+        if address as usize >= self.data.len() {
+            return Err(SimulatedFlashError::OutOfBounds);
+        }
+
+        let completes = self.start_operation()?;
+
+        // An interrupted erase leaves the sector partially erased
+        let sector_start = (address & !(SECTOR_SIZE - 1)) as usize;
+        let erased = if completes { SECTOR_SIZE } else { SECTOR_SIZE / 2 } as usize;
+        self.data[sector_start..sector_start + erased].fill(0xff);
+        self.erase_counts[sector_start / SECTOR_SIZE as usize] += 1;
+
+        if completes {
+            Ok(())
+        } else {
+            Err(SimulatedFlashError::PowerLoss)
+        }
+    }
+}
+
+impl ErrorType for SimulatedFlash {
+    type Error = SimulatedFlashError;
+}
+
+impl ReadNorFlash for SimulatedFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+
+        if !self.powered {
+            return Err(SimulatedFlashError::PowerLoss);
+        }
+
+        let start = offset as usize;
+        bytes.copy_from_slice(&self.data[start..start + bytes.len()]);
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for SimulatedFlash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+
+        for address in (from..to).step_by(SECTOR_SIZE as usize) {
+            self.erase_sector(address)?;
+        }
+
+        Ok(())
+    }
+
+    // Split in page programs, the same way ExternalFlash::write does
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+
+        let mut address = offset;
+        let mut remaining = bytes;
+        while !remaining.is_empty() {
+            let in_current_page = (PAGE_SIZE - (address % PAGE_SIZE)) as usize;
+            let (page, rest) = remaining.split_at(in_current_page.min(remaining.len()));
+
+            self.program_page(address, page)?;
+
+            address += page.len() as u32;
+            remaining = rest;
+        }
+
+        Ok(())
+    }
+}
+
+// Programming only ever clears bits, so writing the same byte twice is fine
+impl MultiwriteNorFlash for SimulatedFlash {}
+
+impl FlashOrigin for SimulatedFlash {
+    const ORIGIN: u32 = 0x0000_0000;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(flash: &mut SimulatedFlash, offset: u32, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        ReadNorFlash::read(flash, offset, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn starts_erased() {
+        let mut flash = SimulatedFlash::new(2 * SECTOR_SIZE);
+
+        assert_eq!(flash.capacity(), 2 * SECTOR_SIZE as usize);
+        assert!(read(&mut flash, 0, 2 * SECTOR_SIZE as usize).iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn program_only_clears_bits() {
+        let mut flash = SimulatedFlash::new(SECTOR_SIZE);
+
+        NorFlash::write(&mut flash, 10, &[0xf0, 0x12]).unwrap();
+        NorFlash::write(&mut flash, 10, &[0x0f, 0xff]).unwrap();
+
+        assert_eq!(read(&mut flash, 10, 2), [0x00, 0x12]);
+    }
+
+    #[test]
+    fn page_program_wraps_around() {
+        let mut flash = SimulatedFlash::new(SECTOR_SIZE);
+
+        flash.program_page(PAGE_SIZE - 2, &[1, 2, 3, 4]).unwrap();
+
+        assert_eq!(read(&mut flash, PAGE_SIZE - 2, 2), [1, 2]);
+        assert_eq!(read(&mut flash, 0, 2), [3, 4]);
+        assert_eq!(read(&mut flash, PAGE_SIZE, 2), [0xff, 0xff]);
+    }
+
+    #[test]
+    fn write_crosses_pages() {
+        let mut flash = SimulatedFlash::new(SECTOR_SIZE);
+        let data: Vec<u8> = (0..=255).cycle().take(600).collect();
+
+        NorFlash::write(&mut flash, 100, &data).unwrap();
+
+        assert_eq!(read(&mut flash, 100, data.len()), data);
+        assert_eq!(read(&mut flash, 0, 1), [0xff]);
+    }
+
+    #[test]
+    fn erase_sectors() {
+        let mut flash = SimulatedFlash::new(3 * SECTOR_SIZE);
+        NorFlash::write(&mut flash, 0, &[0; 3 * SECTOR_SIZE as usize]).unwrap();
+
+        NorFlash::erase(&mut flash, SECTOR_SIZE, 3 * SECTOR_SIZE).unwrap();
+
+        assert!(read(&mut flash, 0, SECTOR_SIZE as usize).iter().all(|b| *b == 0));
+        assert!(read(&mut flash, SECTOR_SIZE, 2 * SECTOR_SIZE as usize).iter().all(|b| *b == 0xff));
+        assert_eq!(flash.erase_count(0), 0);
+        assert_eq!(flash.erase_count(SECTOR_SIZE), 1);
+        assert_eq!(flash.erase_count(2 * SECTOR_SIZE + 10), 1);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let mut flash = SimulatedFlash::new(SECTOR_SIZE);
+        let mut bytes = [0; 2];
+
+        assert_eq!(ReadNorFlash::read(&mut flash, SECTOR_SIZE - 1, &mut bytes), Err(SimulatedFlashError::OutOfBounds));
+        assert_eq!(NorFlash::write(&mut flash, SECTOR_SIZE, &bytes), Err(SimulatedFlashError::OutOfBounds));
+        assert_eq!(NorFlash::erase(&mut flash, 1, SECTOR_SIZE), Err(SimulatedFlashError::NotAligned));
+        assert_eq!(NorFlash::erase(&mut flash, 0, 2 * SECTOR_SIZE), Err(SimulatedFlashError::OutOfBounds));
+    }
+
+    #[test]
+    fn power_loss_during_program() {
+        let mut flash = SimulatedFlash::new(SECTOR_SIZE);
+        flash.fail_after(1);
+
+        NorFlash::write(&mut flash, 0, &[0x00; 4]).unwrap();
+        assert_eq!(NorFlash::write(&mut flash, 4, &[0x00; 4]), Err(SimulatedFlashError::PowerLoss));
+
+        // Everything fails until power comes back
+        let mut bytes = [0; 8];
+        assert_eq!(ReadNorFlash::read(&mut flash, 0, &mut bytes), Err(SimulatedFlashError::PowerLoss));
+        assert_eq!(NorFlash::erase(&mut flash, 0, SECTOR_SIZE), Err(SimulatedFlashError::PowerLoss));
+
+        flash.power_cycle();
+
+        // Half of the interrupted write made it
+        assert_eq!(read(&mut flash, 0, 8), [0, 0, 0, 0, 0, 0, 0xff, 0xff]);
+        NorFlash::write(&mut flash, 6, &[0x00; 2]).unwrap();
+    }
+
+    #[test]
+    fn power_loss_during_erase() {
+        let mut flash = SimulatedFlash::new(SECTOR_SIZE);
+        NorFlash::write(&mut flash, 0, &[0; SECTOR_SIZE as usize]).unwrap();
+        flash.fail_after(0);
+
+        assert_eq!(NorFlash::erase(&mut flash, 0, SECTOR_SIZE), Err(SimulatedFlashError::PowerLoss));
+        flash.power_cycle();
+
+        let half = SECTOR_SIZE as usize / 2;
+        let contents = read(&mut flash, 0, SECTOR_SIZE as usize);
+        assert!(contents[..half].iter().all(|b| *b == 0xff));
+        assert!(contents[half..].iter().all(|b| *b == 0x00));
+    }
+
+    #[test]
+    fn power_loss_splits_multi_page_write() {
+        let mut flash = SimulatedFlash::new(SECTOR_SIZE);
+        flash.fail_after(1);
+
+        // The first page program completes, the second one is interrupted
+        let data = [0; 2 * PAGE_SIZE as usize];
+        assert_eq!(NorFlash::write(&mut flash, 0, &data), Err(SimulatedFlashError::PowerLoss));
+        flash.power_cycle();
+
+        let contents = read(&mut flash, 0, 2 * PAGE_SIZE as usize);
+        let programmed = contents.iter().take_while(|b| **b == 0).count();
+        assert_eq!(programmed, PAGE_SIZE as usize * 3 / 2);
+    }
+
+    #[test]
+    fn bit_errors() {
+        let mut flash = SimulatedFlash::new(SECTOR_SIZE);
+        NorFlash::write(&mut flash, 5, &[0x00]).unwrap();
+
+        flash.inject_bit_error(5, 3);
+        flash.inject_bit_error(6, 0);
+
+        assert_eq!(read(&mut flash, 5, 2), [0x08, 0xfe]);
+        assert_eq!(flash.contents()[5], 0x08);
+    }
+}
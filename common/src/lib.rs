@@ -0,0 +1,10 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod flash;
+pub mod settings;
+pub mod datalog;
+pub mod filesystem;
+pub mod mcuboot;
+pub mod beacon;
@@ -0,0 +1,151 @@
+use crate::flash::partition::{Partition, PartitionError, FlashOrigin};
+
+use super::signature::SignatureError;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use core::fmt;
+
+// struct image_header from MCUBoot's bootutil/image.h
+
+const IMAGE_MAGIC: u32 = 0x96f3_b83d;
+pub const HEADER_SIZE: usize = 32;
+
+// Flags
+pub const IMAGE_F_PIC: u32 = 0x0000_0001;
+pub const IMAGE_F_ENCRYPTED_AES128: u32 = 0x0000_0004;
+pub const IMAGE_F_ENCRYPTED_AES256: u32 = 0x0000_0008;
+pub const IMAGE_F_NON_BOOTABLE: u32 = 0x0000_0010;
+pub const IMAGE_F_RAM_LOAD: u32 = 0x0000_0020;
+
+#[derive(Debug)]
+pub enum ImageError<E> {
+    Flash(PartitionError<E>),
+    InvalidMagic,           // No image in the slot
+    InvalidTlvMagic,
+    InvalidTlv,             // TLV area doesn't fit in the slot or is malformed
+    MissingTlv,             // A required TLV (like the hash) isn't there
+    HashMismatch,
+    Signature(SignatureError),
+    UnsupportedSignature,   // Only ECDSA-P256 signatures are supported
+    InvalidTrailer,         // Bad magic or flags, see Trailer::state
+}
+
+impl<E> From<SignatureError> for ImageError<E> {
+    fn from(error: SignatureError) -> ImageError<E> {
+        ImageError::Signature(error)
+    }
+}
+
+impl<E> From<PartitionError<E>> for ImageError<E> {
+    fn from(error: PartitionError<E>) -> ImageError<E> {
+        ImageError::Flash(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MCUBootHeaderVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u16,
+    pub build_num: u32,
+}
+
+impl fmt::Display for MCUBootHeaderVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}+{}", self.major, self.minor, self.revision, self.build_num)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MCUBootHeader {
+    pub load_address: u32,
+    pub header_size: u16,           // Offset of the image in the slot
+    pub protected_tlv_size: u16,    // Including the TLV info header, 0 if none
+    pub image_size: u32,            // Without header and TLVs
+    pub flags: u32,
+    pub version: MCUBootHeaderVersion,
+}
+
+impl MCUBootHeader {
+    // Header of the image at the start of `slot`
+    pub fn read<F: NorFlash + FlashOrigin>(flash: &mut F, slot: Partition<F>) -> Result<Self, ImageError<F::Error>> {
+        let mut data = [0; HEADER_SIZE];
+        slot.read(flash, 0, &mut data)?;
+
+        MCUBootHeader::parse(&data).ok_or(ImageError::InvalidMagic)
+    }
+
+    fn parse(data: &[u8; HEADER_SIZE]) -> Option<Self> {
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+
+        if u32_at(0) != IMAGE_MAGIC {
+            return None;
+        }
+
+        Some(MCUBootHeader {
+            load_address: u32_at(4),
+            header_size: u16_at(8),
+            protected_tlv_size: u16_at(10),
+            image_size: u32_at(12),
+            flags: u32_at(16),
+            version: MCUBootHeaderVersion {
+                major: data[20],
+                minor: data[21],
+                revision: u16_at(22),
+                build_num: u32_at(24),
+            },
+        })
+    }
+
+    // Offset of the TLV area (protected TLVs first) in the slot
+    pub fn tlv_offset(&self) -> u32 {
+        self.header_size as u32 + self.image_size
+    }
+
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_bytes() -> [u8; HEADER_SIZE] {
+        let mut data = [0; HEADER_SIZE];
+        data[0..4].copy_from_slice(&IMAGE_MAGIC.to_le_bytes());
+        data[4..8].copy_from_slice(&0x2_0000u32.to_le_bytes());
+        data[8..10].copy_from_slice(&0x20u16.to_le_bytes());
+        data[10..12].copy_from_slice(&0x10u16.to_le_bytes());
+        data[12..16].copy_from_slice(&0x1234u32.to_le_bytes());
+        data[16..20].copy_from_slice(&(IMAGE_F_PIC | IMAGE_F_NON_BOOTABLE).to_le_bytes());
+        data[20..28].copy_from_slice(&[1, 2, 0x03, 0x01, 0x78, 0x56, 0x34, 0x12]);
+        data
+    }
+
+    #[test]
+    fn parse_header() {
+        let header = MCUBootHeader::parse(&header_bytes()).unwrap();
+
+        assert_eq!(header.load_address, 0x2_0000);
+        assert_eq!(header.header_size, 0x20);
+        assert_eq!(header.protected_tlv_size, 0x10);
+        assert_eq!(header.image_size, 0x1234);
+        assert_eq!(header.tlv_offset(), 0x1254);
+        assert!(header.has_flag(IMAGE_F_PIC));
+        assert!(header.has_flag(IMAGE_F_NON_BOOTABLE));
+        assert!(!header.has_flag(IMAGE_F_ENCRYPTED_AES128));
+        assert_eq!(header.version, MCUBootHeaderVersion { major: 1, minor: 2, revision: 0x103, build_num: 0x1234_5678 });
+        assert_eq!(alloc::format!("{}", header.version), "1.2.259+305419896");
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let mut data = header_bytes();
+        data[3] ^= 0x01;
+        assert!(MCUBootHeader::parse(&data).is_none());
+        assert!(MCUBootHeader::parse(&[0xff; HEADER_SIZE]).is_none());
+    }
+}
@@ -0,0 +1,450 @@
+mod header;
+mod trailer;
+mod tlv;
+mod signature;
+
+pub use header::{MCUBootHeader, MCUBootHeaderVersion, ImageError, HEADER_SIZE};
+pub use header::{
+    IMAGE_F_PIC, IMAGE_F_ENCRYPTED_AES128, IMAGE_F_ENCRYPTED_AES256,
+    IMAGE_F_NON_BOOTABLE, IMAGE_F_RAM_LOAD,
+};
+pub use tlv::{Tlv, TlvType};
+pub use signature::{SignatureError, key_hash, verify_ecdsa_p256};
+pub use trailer::{Trailer, ImageState, SwapType, SwapInfo, Magic, Flag, TRAILER_SIZE, swap_request};
+
+use alloc::vec::Vec;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use sha2::{Digest, Sha256};
+
+use crate::flash::partition::{Partition, FlashOrigin};
+
+// Size of a SHA-256 hash, as used for the image hash and KEYHASH TLVs
+pub const DIGEST_SIZE: usize = 32;
+
+// An image in a slot (PRIMARY_SLOT or STANDBY_IMAGE), with its TLVs
+#[derive(Debug)]
+pub struct Image<F> {
+    pub slot: Partition<F>,
+    pub header: MCUBootHeader,
+    pub tlvs: Vec<Tlv>,
+}
+
+impl<F: NorFlash + FlashOrigin> Image<F> {
+    pub fn read(flash: &mut F, slot: Partition<F>) -> Result<Self, ImageError<F::Error>> {
+        let header = MCUBootHeader::read(flash, slot)?;
+        let tlvs = tlv::read_tlvs(flash, slot, &header)?;
+
+        Ok(Image {
+            slot,
+            header,
+            tlvs,
+        })
+    }
+
+    // First TLV of type `kind`
+    pub fn find_tlv(&self, kind: TlvType) -> Option<&Tlv> {
+        self.tlvs.iter().find(|tlv| tlv.kind == kind)
+    }
+
+    // Size of header, image and protected TLVs, which is what the hash covers
+    pub fn protected_size(&self) -> u32 {
+        self.header.tlv_offset() + self.header.protected_tlv_size as u32
+    }
+
+    // SHA-256 of the header, image and protected TLVs. Reading a whole slot
+    // takes a while, so `progress` is called after every chunk to allow
+    // petting the watchdog.
+    pub fn hash(&self, flash: &mut F, mut progress: impl FnMut()) -> Result<[u8; DIGEST_SIZE], ImageError<F::Error>> {
+        let mut sha256 = Sha256::new();
+        let mut buffer = [0; 256];
+        let end = self.protected_size();
+        let mut offset = 0;
+
+        while offset < end {
+            let len = buffer.len().min((end - offset) as usize);
+            self.slot.read(flash, offset, &mut buffer[..len])?;
+            sha256.update(&buffer[..len]);
+            offset += len as u32;
+            progress();
+        }
+
+        Ok(sha256.finalize().into())
+    }
+
+    // Check the hash against the SHA256 TLV (unprotected, it can't cover
+    // itself)
+    pub fn verify_hash(&self, flash: &mut F) -> Result<(), ImageError<F::Error>> {
+        let hash = self.hash(flash, || {})?;
+        self.check_hash(flash, &hash)
+    }
+
+    // Check the signature TLV against `key` (a DER SubjectPublicKeyInfo,
+    // normally SIGNING_KEY). Also checks the hash, as that is what's signed.
+    pub fn verify_signature(&self, flash: &mut F, key: &[u8]) -> Result<(), ImageError<F::Error>> {
+        let hash = self.hash(flash, || {})?;
+        self.check_hash(flash, &hash)?;
+        self.check_signature(flash, key, &hash)
+    }
+
+    // verify_hash with a `hash` from hash(), to check both the hash and the
+    // signature while reading the slot once
+    pub fn check_hash(&self, flash: &mut F, hash: &[u8; DIGEST_SIZE]) -> Result<(), ImageError<F::Error>> {
+        let tlv = self.tlvs.iter()
+            .find(|tlv| tlv.kind == TlvType::Sha256 && !tlv.protected)
+            .ok_or(ImageError::MissingTlv)?;
+
+        if tlv.len as usize != DIGEST_SIZE {
+            return Err(ImageError::InvalidTlv);
+        }
+
+        let expected = tlv.read_value(flash, self.slot)?;
+        if hash[..] != expected[..] {
+            return Err(ImageError::HashMismatch);
+        }
+
+        Ok(())
+    }
+
+    // The signature part of verify_signature, `hash` has to have passed
+    // check_hash
+    pub fn check_signature(&self, flash: &mut F, key: &[u8], hash: &[u8; DIGEST_SIZE]) -> Result<(), ImageError<F::Error>> {
+        let key_hash = match self.find_tlv(TlvType::KeyHash) {
+            Some(tlv) => Some(tlv.read_value(flash, self.slot)?),
+            None => None,
+        };
+
+        let unprotected = |kind: TlvType| self.tlvs.iter()
+            .find(|tlv| tlv.kind == kind && !tlv.protected);
+
+        if let Some(tlv) = unprotected(TlvType::Ecdsa256) {
+            let signature = tlv.read_value(flash, self.slot)?;
+            signature::verify_ecdsa_p256(key, key_hash.as_deref(), hash, &signature)?;
+            Ok(())
+        } else if unprotected(TlvType::Ed25519).is_some() {
+            Err(ImageError::UnsupportedSignature)
+        } else {
+            Err(ImageError::MissingTlv)
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::flash::SimulatedFlash;
+
+    use alloc::vec;
+
+    pub(crate) const SLOT_SIZE: u32 = 0x4000;
+
+    // An image laid out like imgtool creates it: header (padded to
+    // header_size), body, protected TLVs and unprotected TLVs
+    pub(crate) struct TestImage {
+        pub header_size: u16,
+        pub flags: u32,
+        pub body: Vec<u8>,
+        pub protected: Vec<(u16, Vec<u8>)>,
+    }
+
+    impl TestImage {
+        pub fn new(body_len: usize) -> TestImage {
+            TestImage {
+                header_size: HEADER_SIZE as u16,
+                flags: 0,
+                body: (0..body_len).map(|i| (i * 7) as u8).collect(),
+                protected: Vec::new(),
+            }
+        }
+
+        fn tlv_area(magic: u16, tlvs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+            let size = 4 + tlvs.iter().map(|(_, value)| 4 + value.len()).sum::<usize>();
+            let mut area = Vec::new();
+            area.extend_from_slice(&magic.to_le_bytes());
+            area.extend_from_slice(&(size as u16).to_le_bytes());
+            for (kind, value) in tlvs {
+                area.extend_from_slice(&kind.to_le_bytes());
+                area.extend_from_slice(&(value.len() as u16).to_le_bytes());
+                area.extend_from_slice(value);
+            }
+            area
+        }
+
+        // What the hash covers
+        pub fn protected_bytes(&self) -> Vec<u8> {
+            let protected = match self.protected.is_empty() {
+                true => Vec::new(),
+                false => TestImage::tlv_area(0x6908, &self.protected),
+            };
+
+            let mut bytes = vec![0; self.header_size as usize];
+            bytes[0..4].copy_from_slice(&0x96f3_b83du32.to_le_bytes());
+            bytes[8..10].copy_from_slice(&self.header_size.to_le_bytes());
+            bytes[10..12].copy_from_slice(&(protected.len() as u16).to_le_bytes());
+            bytes[12..16].copy_from_slice(&(self.body.len() as u32).to_le_bytes());
+            bytes[16..20].copy_from_slice(&self.flags.to_le_bytes());
+            bytes[20..28].copy_from_slice(&[1, 2, 3, 0, 4, 0, 0, 0]);
+            bytes.extend_from_slice(&self.body);
+            bytes.extend_from_slice(&protected);
+            bytes
+        }
+
+        pub fn hash(&self) -> [u8; DIGEST_SIZE] {
+            Sha256::digest(self.protected_bytes()).into()
+        }
+
+        pub fn bytes(&self, unprotected: &[(u16, Vec<u8>)]) -> Vec<u8> {
+            let mut bytes = self.protected_bytes();
+            bytes.extend_from_slice(&TestImage::tlv_area(0x6907, unprotected));
+            bytes
+        }
+
+        // With just the SHA256 TLV
+        pub fn hashed_bytes(&self) -> Vec<u8> {
+            self.bytes(&[(0x10, self.hash().to_vec())])
+        }
+    }
+
+    // `bytes` at the start of a slot, the rest of it erased
+    pub(crate) fn in_slot(bytes: &[u8]) -> (SimulatedFlash, Partition<SimulatedFlash>) {
+        let mut image = bytes.to_vec();
+        image.resize(SLOT_SIZE as usize, 0xff);
+        (SimulatedFlash::from_image(image), Partition::new("SLOT", 0, SLOT_SIZE))
+    }
+
+    #[test]
+    fn read_image() {
+        let mut image = TestImage::new(100);
+        image.header_size = 0x200;
+        image.protected = vec![(0x50, vec![1, 0, 0, 0])];
+        let (mut flash, slot) = in_slot(&image.hashed_bytes());
+
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert_eq!(read.header.header_size, 0x200);
+        assert_eq!(read.header.image_size, 100);
+        assert_eq!(read.header.protected_tlv_size, 12);
+        assert_eq!(read.protected_size(), 0x200 + 100 + 12);
+
+        let kinds: Vec<_> = read.tlvs.iter().map(|tlv| (tlv.kind, tlv.protected)).collect();
+        assert_eq!(kinds, [(TlvType::SecurityCounter, true), (TlvType::Sha256, false)]);
+
+        let counter = read.find_tlv(TlvType::SecurityCounter).unwrap();
+        assert_eq!(counter.read_value(&mut flash, slot).unwrap(), [1, 0, 0, 0]);
+        assert!(read.find_tlv(TlvType::Ecdsa256).is_none());
+    }
+
+    // Built by testdata/make_test_image.py like imgtool would, see there
+    pub(crate) const SIGNED_IMAGE: &[u8] = include_bytes!("testdata/test_image.bin");
+    pub(crate) const SIGNED_IMAGE_KEY: &[u8] = include_bytes!("testdata/test-key.pub.der");
+
+    #[test]
+    fn signed_image_hash() {
+        let (mut flash, slot) = in_slot(SIGNED_IMAGE);
+        let image = Image::read(&mut flash, slot).unwrap();
+
+        assert_eq!(image.header.image_size, 3001);
+        assert_eq!(image.protected_size(), 3033);
+
+        // From Python's hashlib when the image was made
+        let expected = "b7ba0ce2b690166bc0507936e2c22f7dfae069ecbbe77979d29796bb3a859592";
+        let hash: alloc::string::String = image.hash(&mut flash, || {}).unwrap()
+            .iter()
+            .map(|b| alloc::format!("{:02x}", b))
+            .collect();
+        assert_eq!(hash, expected);
+        image.verify_hash(&mut flash).unwrap();
+
+        // Any change to the header or body is caught
+        for offset in [0x14, 0x20, 3032] {
+            let mut bytes = SIGNED_IMAGE.to_vec();
+            bytes[offset] ^= 0x01;
+            let (mut flash, slot) = in_slot(&bytes);
+            let image = Image::read(&mut flash, slot).unwrap();
+            assert!(matches!(image.verify_hash(&mut flash), Err(ImageError::HashMismatch)));
+        }
+    }
+
+    #[test]
+    fn signed_image_signature() {
+        let (mut flash, slot) = in_slot(SIGNED_IMAGE);
+        let image = Image::read(&mut flash, slot).unwrap();
+        image.verify_signature(&mut flash, SIGNED_IMAGE_KEY).unwrap();
+
+        let kinds: Vec<_> = image.tlvs.iter().map(|tlv| tlv.kind).collect();
+        assert_eq!(kinds, [TlvType::Sha256, TlvType::KeyHash, TlvType::Ecdsa256]);
+
+        // Another key
+        let mut key = SIGNED_IMAGE_KEY.to_vec();
+        key[40] ^= 0x01;
+        assert!(matches!(
+            image.verify_signature(&mut flash, &key),
+            Err(ImageError::Signature(SignatureError::UnknownKey)),
+        ));
+
+        // Changes to the image are caught by the hash, before the signature
+        let mut bytes = SIGNED_IMAGE.to_vec();
+        bytes[100] ^= 0x01;
+        let (mut flash, slot) = in_slot(&bytes);
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(read.verify_signature(&mut flash, SIGNED_IMAGE_KEY), Err(ImageError::HashMismatch)));
+
+        // A signature that doesn't match, in its last byte
+        let signature = image.find_tlv(TlvType::Ecdsa256).unwrap();
+        let mut bytes = SIGNED_IMAGE.to_vec();
+        bytes[(signature.offset + signature.len as u32 - 1) as usize] ^= 0x01;
+        let (mut flash, slot) = in_slot(&bytes);
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(
+            read.verify_signature(&mut flash, SIGNED_IMAGE_KEY),
+            Err(ImageError::Signature(SignatureError::InvalidSignature)),
+        ));
+    }
+
+    #[test]
+    fn hash_once() {
+        let (mut flash, slot) = in_slot(SIGNED_IMAGE);
+        let image = Image::read(&mut flash, slot).unwrap();
+
+        // One call per 256 byte chunk
+        let mut chunks = 0;
+        let hash = image.hash(&mut flash, || chunks += 1).unwrap();
+        assert_eq!(chunks, image.protected_size().div_ceil(256));
+
+        image.check_hash(&mut flash, &hash).unwrap();
+        image.check_signature(&mut flash, SIGNED_IMAGE_KEY, &hash).unwrap();
+
+        let mut other = hash;
+        other[0] ^= 0x01;
+        assert!(matches!(image.check_hash(&mut flash, &other), Err(ImageError::HashMismatch)));
+        assert!(matches!(
+            image.check_signature(&mut flash, SIGNED_IMAGE_KEY, &other),
+            Err(ImageError::Signature(SignatureError::InvalidSignature)),
+        ));
+    }
+
+    #[test]
+    fn signature_tlvs() {
+        let image = TestImage::new(100);
+        let hash = (0x10, image.hash().to_vec());
+
+        // A signature is required
+        let (mut flash, slot) = in_slot(&image.hashed_bytes());
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(read.verify_signature(&mut flash, SIGNED_IMAGE_KEY), Err(ImageError::MissingTlv)));
+
+        // Only ECDSA-P256
+        let (mut flash, slot) = in_slot(&image.bytes(&[hash.clone(), (0x24, vec![0; 64])]));
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(read.verify_signature(&mut flash, SIGNED_IMAGE_KEY), Err(ImageError::UnsupportedSignature)));
+
+        // Without KEYHASH, the signature is checked against the key anyway
+        let (mut flash, slot) = in_slot(&image.bytes(&[hash, (0x22, vec![0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01])]));
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(
+            read.verify_signature(&mut flash, SIGNED_IMAGE_KEY),
+            Err(ImageError::Signature(SignatureError::InvalidSignature)),
+        ));
+    }
+
+    #[test]
+    fn hash_tlv() {
+        let mut image = TestImage::new(100);
+        image.protected = vec![(0x50, vec![1, 0, 0, 0])];
+        let (mut flash, slot) = in_slot(&image.hashed_bytes());
+        Image::read(&mut flash, slot).unwrap().verify_hash(&mut flash).unwrap();
+
+        // The protected TLVs are covered by the hash
+        let mut bytes = image.hashed_bytes();
+        bytes[140] ^= 0x01;
+        let (mut flash, slot) = in_slot(&bytes);
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(read.verify_hash(&mut flash), Err(ImageError::HashMismatch)));
+
+        // A hash TLV is required, and has to be unprotected
+        let (mut flash, slot) = in_slot(&image.bytes(&[]));
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(read.verify_hash(&mut flash), Err(ImageError::MissingTlv)));
+
+        let mut protected_hash = TestImage::new(100);
+        protected_hash.protected = vec![(0x10, vec![0; 32])];
+        let (mut flash, slot) = in_slot(&protected_hash.bytes(&[]));
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(read.verify_hash(&mut flash), Err(ImageError::MissingTlv)));
+
+        let (mut flash, slot) = in_slot(&image.bytes(&[(0x10, image.hash()[..20].to_vec())]));
+        let read = Image::read(&mut flash, slot).unwrap();
+        assert!(matches!(read.verify_hash(&mut flash), Err(ImageError::InvalidTlv)));
+    }
+
+    #[test]
+    fn no_image() {
+        let (mut flash, slot) = in_slot(&[]);
+        assert!(matches!(Image::read(&mut flash, slot), Err(ImageError::InvalidMagic)));
+    }
+
+    fn hex(digest: &str) -> [u8; DIGEST_SIZE] {
+        let bytes: Vec<u8> = (0..digest.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digest[i..i + 2], 16).unwrap())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    fn sha256(data: &[u8]) -> [u8; DIGEST_SIZE] {
+        Sha256::digest(data).into()
+    }
+
+    // 0, 1, 2, ... up to `len`
+    fn counting(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn sha256_known_answers() {
+        // FIPS 180-4 examples
+        assert_eq!(sha256(b""), hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+        assert_eq!(sha256(b"abc"), hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            hex("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+        );
+        assert_eq!(
+            sha256(&[b'a'; 1_000_000]),
+            hex("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"),
+        );
+    }
+
+    #[test]
+    fn sha256_padding_boundaries() {
+        // The length still fits in the last block up to 55 bytes, from 56 it
+        // needs another one. Digests from Python's hashlib.
+        for (len, digest) in [
+            (55, "463eb28e72f82e0a96c0a4cc53690c571281131f672aa229e0d45ae59b598b59"),
+            (56, "da2ae4d6b36748f2a318f23e7ab1dfdf45acdc9d049bd80e59de82a60895f562"),
+            (63, "29af2686fd53374a36b0846694cc342177e428d1647515f078784d69cdb9e488"),
+            (64, "fdeab9acf3710362bd2658cdc9a29e8f9c757fcf9811603a8c447cd1d9151108"),
+            (65, "4bfd2c8b6f1eec7a2afeb48b934ee4b2694182027e6d0fc075074f2fabb31781"),
+            (119, "da18797ed7c3a777f0847f429724a2d8cd5138e6ed2895c3fa1a6d39d18f7ec6"),
+            (120, "f52b23db1fbb6ded89ef42a23ce0c8922c45f25c50b568a93bf1c075420bbb7c"),
+        ] {
+            assert_eq!(sha256(&counting(len)), hex(digest), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn sha256_multi_block_updates() {
+        let data = counting(1000);
+        let digest = hex("a8af099bf2e878609558dbf69d8f88f4a31040a8cf84b549a0cfa912f12ffc3f");
+        assert_eq!(sha256(&data), digest);
+
+        // Split at every size, like reading an image through a buffer
+        for chunk_size in 1..=130 {
+            let mut sha256 = Sha256::new();
+            for chunk in data.chunks(chunk_size) {
+                sha256.update(chunk);
+            }
+            assert_eq!(<[u8; DIGEST_SIZE]>::from(sha256.finalize()), digest, "{} byte chunks", chunk_size);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use p256::ecdsa::{Signature, VerifyingKey};
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+
+use sha2::{Digest, Sha256};
+
+use super::DIGEST_SIZE;
+
+// SubjectPublicKeyInfo for a P-256 key, followed by the uncompressed point
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+    0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    InvalidKey,
+    UnknownKey,         // KEYHASH TLV is for another key
+    InvalidSignature,   // Malformed or doesn't match
+}
+
+pub fn key_hash(key: &[u8]) -> [u8; DIGEST_SIZE] {
+    Sha256::digest(key).into()
+}
+
+// Check an ECDSA-P256 signature TLV (DER encoded, as imgtool writes it) over
+// the image hash. Like MCUBoot, the hash itself is what's signed, it isn't
+// hashed again.
+pub fn verify_ecdsa_p256(
+    key: &[u8],
+    key_hash_tlv: Option<&[u8]>,
+    hash: &[u8; DIGEST_SIZE],
+    signature: &[u8],
+) -> Result<(), SignatureError> {
+    if key.len() != P256_SPKI_PREFIX.len() + 65 || key[..P256_SPKI_PREFIX.len()] != P256_SPKI_PREFIX {
+        return Err(SignatureError::InvalidKey);
+    }
+
+    if let Some(expected) = key_hash_tlv {
+        if key_hash(key)[..] != expected[..] {
+            return Err(SignatureError::UnknownKey);
+        }
+    }
+
+    let verifying_key = VerifyingKey::from_sec1_bytes(&key[P256_SPKI_PREFIX.len()..])
+        .map_err(|_| SignatureError::InvalidKey)?;
+    let signature = Signature::from_der(signature)
+        .map_err(|_| SignatureError::InvalidSignature)?;
+
+    verifying_key.verify_prehash(hash, &signature)
+        .map_err(|_| SignatureError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcuboot::tests::SIGNED_IMAGE_KEY;
+
+    use alloc::vec::Vec;
+
+    // A valid key that didn't sign anything: the P-256 generator point
+    fn other_key() -> Vec<u8> {
+        let mut key = P256_SPKI_PREFIX.to_vec();
+        key.push(0x04);
+        key.extend_from_slice(&[
+            0x6b, 0x17, 0xd1, 0xf2, 0xe1, 0x2c, 0x42, 0x47, 0xf8, 0xbc, 0xe6, 0xe5, 0x63, 0xa4, 0x40, 0xf2,
+            0x77, 0x03, 0x7d, 0x81, 0x2d, 0xeb, 0x33, 0xa0, 0xf4, 0xa1, 0x39, 0x45, 0xd8, 0x98, 0xc2, 0x96,
+            0x4f, 0xe3, 0x42, 0xe2, 0xfe, 0x1a, 0x7f, 0x9b, 0x8e, 0xe7, 0xeb, 0x4a, 0x7c, 0x0f, 0x9e, 0x16,
+            0x2b, 0xce, 0x33, 0x57, 0x6b, 0x31, 0x5e, 0xce, 0xcb, 0xb6, 0x40, 0x68, 0x37, 0xbf, 0x51, 0xf5,
+        ]);
+        key
+    }
+
+    #[test]
+    fn keys() {
+        let hash = [0; DIGEST_SIZE];
+        let signature = [0x30, 0x00];
+
+        // Our key is what testdata/make_test_image.py writes
+        assert!(SIGNED_IMAGE_KEY.starts_with(&P256_SPKI_PREFIX));
+
+        assert_eq!(verify_ecdsa_p256(&[], None, &hash, &signature), Err(SignatureError::InvalidKey));
+        assert_eq!(verify_ecdsa_p256(&SIGNED_IMAGE_KEY[1..], None, &hash, &signature), Err(SignatureError::InvalidKey));
+
+        // The prefix is right, but the point isn't on the curve
+        let mut key = other_key();
+        key[30] ^= 0x01;
+        assert_eq!(verify_ecdsa_p256(&key, None, &hash, &signature), Err(SignatureError::InvalidKey));
+
+        // A KEYHASH TLV for another key
+        let other_hash = key_hash(&other_key());
+        assert_eq!(
+            verify_ecdsa_p256(SIGNED_IMAGE_KEY, Some(&other_hash), &hash, &signature),
+            Err(SignatureError::UnknownKey),
+        );
+    }
+}
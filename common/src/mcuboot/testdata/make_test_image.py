@@ -0,0 +1,53 @@
+#!/usr/bin/env python3
+# Builds test_image.bin for the mcuboot tests, laid out like
+# `imgtool create --header-size 32 --pad-header --key test-key.pem` would
+# (imgtool isn't needed, only the `cryptography` package). The hash comes
+# from hashlib, so it also checks our SHA-256.
+#
+# test-key.pem is only for these tests, generate it with
+#   openssl ecparam -name prime256v1 -genkey -noout -out test-key.pem
+
+import hashlib
+import struct
+from pathlib import Path
+
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec, utils
+
+HERE = Path(__file__).parent
+
+IMAGE_MAGIC = 0x96f3b83d
+TLV_INFO_MAGIC = 0x6907
+TLV_KEYHASH = 0x01
+TLV_SHA256 = 0x10
+TLV_ECDSA256 = 0x22
+HEADER_SIZE = 32
+
+key = serialization.load_pem_private_key((HERE / "test-key.pem").read_bytes(), None)
+public_key = key.public_key().public_bytes(
+    serialization.Encoding.DER,
+    serialization.PublicFormat.SubjectPublicKeyInfo,
+)
+
+# Not a multiple of the SHA-256 block size
+body = bytes((i * 31 + 7) & 0xff for i in range(3001))
+
+# struct image_header: magic, load_addr, hdr_size, protect_tlv_size,
+# img_size, flags, version (major, minor, revision, build_num), pad
+header = struct.pack("<IIHHIIBBHII", IMAGE_MAGIC, 0, HEADER_SIZE, 0, len(body), 0, 1, 2, 3, 4, 0)
+
+digest = hashlib.sha256(header + body).digest()
+signature = key.sign(digest, ec.ECDSA(utils.Prehashed(hashes.SHA256())))
+
+tlvs = b""
+for kind, value in [
+    (TLV_SHA256, digest),
+    (TLV_KEYHASH, hashlib.sha256(public_key).digest()),
+    (TLV_ECDSA256, signature),
+]:
+    tlvs += struct.pack("<HH", kind, len(value)) + value
+
+image = header + body + struct.pack("<HH", TLV_INFO_MAGIC, 4 + len(tlvs)) + tlvs
+
+(HERE / "test_image.bin").write_bytes(image)
+(HERE / "test-key.pub.der").write_bytes(public_key)
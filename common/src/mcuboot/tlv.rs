@@ -0,0 +1,234 @@
+use crate::flash::partition::{Partition, FlashOrigin};
+
+use super::header::{MCUBootHeader, ImageError};
+
+use embedded_storage::nor_flash::NorFlash;
+
+use alloc::vec::Vec;
+use alloc::vec;
+
+// TLV (type-length-value) trailer after the image, see MCUBoot's
+// bootutil/image.h. The protected TLVs (covered by the hash) come first,
+// then the unprotected ones (hash and signatures). Each area starts with an
+// info header: magic (u16) and the total size of the area (u16, including
+// the info header). Each TLV is a type (u16), length (u16) and the value.
+
+const TLV_INFO_MAGIC: u16 = 0x6907;
+const TLV_PROT_INFO_MAGIC: u16 = 0x6908;
+const TLV_INFO_SIZE: u32 = 4;
+const TLV_HEADER_SIZE: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlvType {
+    KeyHash,            // SHA-256 of the public key
+    PublicKey,
+    Sha256,             // Of the header, image and protected TLVs
+    Rsa2048Pss,
+    Ecdsa224,
+    Ecdsa256,
+    Rsa3072Pss,
+    Ed25519,
+    EncRsa2048,
+    EncKw,
+    EncEc256,
+    EncX25519,
+    Dependency,         // Image id (u8), padding (3 bytes), minimum version (8 bytes)
+    SecurityCounter,
+    BootRecord,
+    Other(u16),
+}
+
+impl From<u16> for TlvType {
+    fn from(value: u16) -> TlvType {
+        match value {
+            0x01 => TlvType::KeyHash,
+            0x02 => TlvType::PublicKey,
+            0x10 => TlvType::Sha256,
+            0x20 => TlvType::Rsa2048Pss,
+            0x21 => TlvType::Ecdsa224,
+            0x22 => TlvType::Ecdsa256,
+            0x23 => TlvType::Rsa3072Pss,
+            0x24 => TlvType::Ed25519,
+            0x30 => TlvType::EncRsa2048,
+            0x31 => TlvType::EncKw,
+            0x32 => TlvType::EncEc256,
+            0x33 => TlvType::EncX25519,
+            0x40 => TlvType::Dependency,
+            0x50 => TlvType::SecurityCounter,
+            0x60 => TlvType::BootRecord,
+            other => TlvType::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tlv {
+    pub kind: TlvType,
+    pub protected: bool,
+    pub offset: u32,    // Of the value, in the slot
+    pub len: u16,
+}
+
+impl Tlv {
+    pub fn read_value<F: NorFlash + FlashOrigin>(&self, flash: &mut F, slot: Partition<F>) -> Result<Vec<u8>, ImageError<F::Error>> {
+        let mut value = vec![0; self.len as usize];
+        slot.read(flash, self.offset, &mut value)?;
+        Ok(value)
+    }
+}
+
+// All TLVs of the image in `slot`, protected ones first
+pub fn read_tlvs<F: NorFlash + FlashOrigin>(
+    flash: &mut F,
+    slot: Partition<F>,
+    header: &MCUBootHeader,
+) -> Result<Vec<Tlv>, ImageError<F::Error>> {
+    let mut tlvs = Vec::new();
+    let mut offset = header.tlv_offset();
+
+    if header.protected_tlv_size != 0 {
+        let size = read_area(flash, slot, offset, TLV_PROT_INFO_MAGIC, true, &mut tlvs)?;
+        if size != header.protected_tlv_size as u32 {
+            return Err(ImageError::InvalidTlv);
+        }
+        offset += size;
+    }
+
+    read_area(flash, slot, offset, TLV_INFO_MAGIC, false, &mut tlvs)?;
+
+    Ok(tlvs)
+}
+
+// Read the TLV area at `offset`, returns its total size
+fn read_area<F: NorFlash + FlashOrigin>(
+    flash: &mut F,
+    slot: Partition<F>,
+    offset: u32,
+    magic: u16,
+    protected: bool,
+    tlvs: &mut Vec<Tlv>,
+) -> Result<u32, ImageError<F::Error>> {
+    let mut info = [0; TLV_INFO_SIZE as usize];
+    slot.read(flash, offset, &mut info)?;
+
+    if u16::from_le_bytes([info[0], info[1]]) != magic {
+        return Err(ImageError::InvalidTlvMagic);
+    }
+
+    let size = u16::from_le_bytes([info[2], info[3]]) as u32;
+    let end = offset + size;
+    if size < TLV_INFO_SIZE || end > slot.size {
+        return Err(ImageError::InvalidTlv);
+    }
+
+    let mut address = offset + TLV_INFO_SIZE;
+    while address < end {
+        let mut tlv_header = [0; TLV_HEADER_SIZE as usize];
+        if address + TLV_HEADER_SIZE > end {
+            return Err(ImageError::InvalidTlv);
+        }
+        slot.read(flash, address, &mut tlv_header)?;
+
+        let len = u16::from_le_bytes([tlv_header[2], tlv_header[3]]);
+        let value_offset = address + TLV_HEADER_SIZE;
+        if value_offset + len as u32 > end {
+            return Err(ImageError::InvalidTlv);
+        }
+
+        tlvs.push(Tlv {
+            kind: u16::from_le_bytes([tlv_header[0], tlv_header[1]]).into(),
+            protected,
+            offset: value_offset,
+            len,
+        });
+
+        address = value_offset + len as u32;
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcuboot::tests::{TestImage, in_slot, SLOT_SIZE};
+
+    fn read(bytes: &[u8]) -> Result<Vec<Tlv>, ImageError<crate::flash::SimulatedFlashError>> {
+        let (mut flash, slot) = in_slot(bytes);
+        let header = MCUBootHeader::read(&mut flash, slot)?;
+        read_tlvs(&mut flash, slot, &header)
+    }
+
+    #[test]
+    fn protected_and_unprotected() {
+        let mut image = TestImage::new(10);
+        image.protected = vec![(0x40, vec![0; 12]), (0x50, vec![7, 0, 0, 0])];
+        let bytes = image.bytes(&[(0x01, vec![0xaa; 32]), (0x1234, vec![]), (0x22, vec![0xbb; 70])]);
+
+        let tlvs = read(&bytes).unwrap();
+        let tlvs: Vec<_> = tlvs.iter().map(|tlv| (tlv.kind, tlv.protected, tlv.offset, tlv.len)).collect();
+
+        // Offsets of the values, after the 32 byte header, the body and the
+        // info and TLV headers
+        assert_eq!(tlvs, [
+            (TlvType::Dependency, true, 50, 12),
+            (TlvType::SecurityCounter, true, 66, 4),
+            (TlvType::KeyHash, false, 78, 32),
+            (TlvType::Other(0x1234), false, 114, 0),
+            (TlvType::Ecdsa256, false, 118, 70),
+        ]);
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let image = TestImage::new(10);
+        let mut bytes = image.hashed_bytes();
+        bytes[42] ^= 0x01;
+        assert!(matches!(read(&bytes), Err(ImageError::InvalidTlvMagic)));
+
+        // Protected TLVs announced in the header, but not there
+        let mut image = TestImage::new(10);
+        image.protected = vec![(0x50, vec![1, 0, 0, 0])];
+        let mut bytes = image.hashed_bytes();
+        bytes.drain(42..54);
+        assert!(matches!(read(&bytes), Err(ImageError::InvalidTlvMagic)));
+    }
+
+    #[test]
+    fn malformed_areas() {
+        let image = TestImage::new(10);
+        let area = 42;
+
+        // Area past the end of the slot
+        let mut bytes = image.hashed_bytes();
+        bytes.truncate(SLOT_SIZE as usize - 100);
+        bytes.resize(SLOT_SIZE as usize - 4, 0xff);
+        bytes.extend_from_slice(&[0x07, 0x69, 0x08, 0x00]);
+        bytes[12..16].copy_from_slice(&(SLOT_SIZE - 36).to_le_bytes());
+        assert!(matches!(read(&bytes), Err(ImageError::InvalidTlv)));
+
+        // Smaller than the info header
+        let mut bytes = image.hashed_bytes();
+        bytes[area + 2..area + 4].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(read(&bytes), Err(ImageError::InvalidTlv)));
+
+        // A TLV header that doesn't fit
+        let mut bytes = image.hashed_bytes();
+        bytes[area + 2..area + 4].copy_from_slice(&6u16.to_le_bytes());
+        assert!(matches!(read(&bytes), Err(ImageError::InvalidTlv)));
+
+        // A value that doesn't fit
+        let mut bytes = image.hashed_bytes();
+        bytes[area + 6..area + 8].copy_from_slice(&33u16.to_le_bytes());
+        assert!(matches!(read(&bytes), Err(ImageError::InvalidTlv)));
+    }
+
+    #[test]
+    fn protected_size_mismatch() {
+        let mut image = TestImage::new(10);
+        image.protected = vec![(0x50, vec![1, 0, 0, 0])];
+        let mut bytes = image.hashed_bytes();
+        bytes[10..12].copy_from_slice(&16u16.to_le_bytes());
+        assert!(matches!(read(&bytes), Err(ImageError::InvalidTlv)));
+    }
+}
@@ -0,0 +1,310 @@
+use crate::flash::partition::{Partition, PartitionError, FlashOrigin};
+
+use embedded_storage::nor_flash::NorFlash;
+
+// Trailer at the end of a slot, see MCUBoot's bootutil/bootutil_public.h.
+// With BOOT_MAX_ALIGN = 8 every field takes 8 bytes, except the magic:
+//
+//     swap_size (u32) | swap_info | copy_done | image_ok | magic (16 bytes)
+//
+// Erased fields read as 0xff, MCUBoot only ever programs them, so a trailer
+// without magic (e.g. flashed with a debugger) is valid too.
+
+pub const TRAILER_SIZE: usize = 48;
+const SWAP_SIZE_OFFSET: usize = 0;
+const SWAP_INFO_OFFSET: usize = 8;
+const COPY_DONE_OFFSET: usize = 16;
+const IMAGE_OK_OFFSET: usize = 24;
+const MAGIC_OFFSET: usize = 32;
+const FIELD_SIZE: usize = 8;
+
+const TRAILER_MAGIC: [u8; 16] = [
+    0x77, 0xc2, 0x95, 0xf3,
+    0x60, 0xd2, 0xef, 0x7f,
+    0x35, 0x52, 0x50, 0x0f,
+    0x2c, 0xb6, 0x79, 0x80
+];
+
+const FLAG_SET: u8 = 0x01;
+const ERASED: u8 = 0xff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Magic {
+    Unset,  // Erased
+    Good,
+    Bad,    // Neither, MCUBoot won't touch the slot
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flag {
+    Unset,
+    Set,
+    Bad,    // Not 0x01 or 0xff, can't be changed without an erase
+}
+
+impl From<u8> for Flag {
+    fn from(value: u8) -> Flag {
+        match value {
+            ERASED => Flag::Unset,
+            FLAG_SET => Flag::Set,
+            _ => Flag::Bad,
+        }
+    }
+}
+
+// BOOT_SWAP_TYPE_*, the swap MCUBoot should do (or did) on boot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SwapType {
+    None,
+    Test,       // Swap, and revert on the boot after unless confirmed
+    Permanent,  // Swap, no confirmation needed
+    Revert,
+    Fail,
+    Other(u8),
+}
+
+impl SwapType {
+    fn value(&self) -> u8 {
+        match self {
+            SwapType::None => 0x01,
+            SwapType::Test => 0x02,
+            SwapType::Permanent => 0x03,
+            SwapType::Revert => 0x04,
+            SwapType::Fail => 0x05,
+            SwapType::Other(value) => *value,
+        }
+    }
+}
+
+impl From<u8> for SwapType {
+    fn from(value: u8) -> SwapType {
+        match value {
+            0x01 => SwapType::None,
+            0x02 => SwapType::Test,
+            0x03 => SwapType::Permanent,
+            0x04 => SwapType::Revert,
+            0x05 => SwapType::Fail,
+            other => SwapType::Other(other),
+        }
+    }
+}
+
+// swap_info: image number in the upper nibble, swap type in the lower one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwapInfo {
+    pub image: u8,
+    pub swap_type: SwapType,
+}
+
+// What the trailer means for the image in the slot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageState {
+    Confirmed,
+    Permanent,  // No trailer, MCUBoot never reverts it
+    Testing,    // Reverted on the next reboot unless confirmed
+    Corrupt,    // Can't be confirmed without an erase
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trailer {
+    pub magic: Magic,
+    pub swap_size: Option<u32>,
+    pub swap_info: Option<SwapInfo>,
+    pub copy_done: Flag,
+    pub image_ok: Flag,
+}
+
+impl Trailer {
+    // Trailer of `slot`, read errors aside this never fails, whatever is in
+    // the flash
+    pub fn read<F: NorFlash + FlashOrigin>(flash: &mut F, slot: Partition<F>) -> Result<Self, PartitionError<F::Error>> {
+        let mut data = [0; TRAILER_SIZE];
+        slot.read(flash, slot.size - TRAILER_SIZE as u32, &mut data)?;
+
+        Ok(Trailer::parse(&data))
+    }
+
+    fn parse(data: &[u8; TRAILER_SIZE]) -> Self {
+        let magic = &data[MAGIC_OFFSET..];
+        let magic = if magic == TRAILER_MAGIC {
+            Magic::Good
+        } else if magic.iter().all(|b| *b == ERASED) {
+            Magic::Unset
+        } else {
+            Magic::Bad
+        };
+
+        let swap_size = u32::from_le_bytes(
+            data[SWAP_SIZE_OFFSET..SWAP_SIZE_OFFSET + 4].try_into().unwrap()
+        );
+
+        let swap_info = match data[SWAP_INFO_OFFSET] {
+            ERASED => None,
+            value => Some(SwapInfo {
+                image: value >> 4,
+                swap_type: (value & 0x0f).into(),
+            }),
+        };
+
+        Trailer {
+            magic,
+            swap_size: (swap_size != u32::MAX).then_some(swap_size),
+            swap_info,
+            copy_done: data[COPY_DONE_OFFSET].into(),
+            image_ok: data[IMAGE_OK_OFFSET].into(),
+        }
+    }
+
+    // Like boot_set_confirmed in MCUBoot
+    pub fn state(&self) -> ImageState {
+        match (self.magic, self.image_ok) {
+            (Magic::Unset, _) => ImageState::Permanent,
+            (Magic::Good, Flag::Set) => ImageState::Confirmed,
+            (Magic::Good, Flag::Unset) => ImageState::Testing,
+            _ => ImageState::Corrupt,
+        }
+    }
+
+    // Set image_ok in the trailer of `slot`, only valid when testing
+    pub fn write_image_ok<F: NorFlash + FlashOrigin>(flash: &mut F, slot: Partition<F>) -> Result<(), PartitionError<F::Error>> {
+        let mut field = [ERASED; FIELD_SIZE];
+        field[0] = FLAG_SET;
+
+        // Programming 0xff leaves the other bytes alone, so no erase needed
+        let offset = slot.size - (TRAILER_SIZE - IMAGE_OK_OFFSET) as u32;
+        slot.write(flash, offset, &field)
+    }
+}
+
+// Trailer that has MCUBoot swap in the image of the slot on the next boot,
+// like boot_set_pending in MCUBoot. Only Test and Permanent make sense here.
+pub fn swap_request(swap_type: SwapType) -> [u8; TRAILER_SIZE] {
+    let mut trailer = [ERASED; TRAILER_SIZE];
+
+    // Image number 0 in the upper nibble
+    trailer[SWAP_INFO_OFFSET] = swap_type.value();
+    if swap_type == SwapType::Permanent {
+        trailer[IMAGE_OK_OFFSET] = FLAG_SET;
+    }
+    trailer[MAGIC_OFFSET..].copy_from_slice(&TRAILER_MAGIC);
+
+    trailer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::SimulatedFlash;
+
+    const SLOT_SIZE: u32 = 0x2000;
+
+    fn slot() -> Partition<SimulatedFlash> {
+        Partition::new("SLOT", 0x1000, SLOT_SIZE)
+    }
+
+    // Flash with `trailer` at the end of the slot
+    fn flash(trailer: &[u8; TRAILER_SIZE]) -> SimulatedFlash {
+        let mut flash = SimulatedFlash::new(0x4000);
+        slot().write(&mut flash, SLOT_SIZE - TRAILER_SIZE as u32, trailer).unwrap();
+        flash
+    }
+
+    #[test]
+    fn erased_trailer() {
+        let mut flash = SimulatedFlash::new(0x4000);
+        let trailer = Trailer::read(&mut flash, slot()).unwrap();
+
+        assert_eq!(trailer, Trailer {
+            magic: Magic::Unset,
+            swap_size: None,
+            swap_info: None,
+            copy_done: Flag::Unset,
+            image_ok: Flag::Unset,
+        });
+        assert_eq!(trailer.state(), ImageState::Permanent);
+    }
+
+    #[test]
+    fn swap_requests() {
+        let mut test = flash(&swap_request(SwapType::Test));
+        let trailer = Trailer::read(&mut test, slot()).unwrap();
+        assert_eq!(trailer.magic, Magic::Good);
+        assert_eq!(trailer.swap_info, Some(SwapInfo { image: 0, swap_type: SwapType::Test }));
+        assert_eq!(trailer.image_ok, Flag::Unset);
+        assert_eq!(trailer.state(), ImageState::Testing);
+
+        let mut permanent = flash(&swap_request(SwapType::Permanent));
+        let trailer = Trailer::read(&mut permanent, slot()).unwrap();
+        assert_eq!(trailer.swap_info, Some(SwapInfo { image: 0, swap_type: SwapType::Permanent }));
+        assert_eq!(trailer.image_ok, Flag::Set);
+        assert_eq!(trailer.state(), ImageState::Confirmed);
+
+        // Only the end of the slot is written
+        let trailer_start = (0x1000 + SLOT_SIZE) as usize - TRAILER_SIZE;
+        assert!(test.contents()[..trailer_start].iter().all(|b| *b == ERASED));
+        assert!(test.contents()[trailer_start + TRAILER_SIZE..].iter().all(|b| *b == ERASED));
+    }
+
+    #[test]
+    fn confirm() {
+        let mut flash = flash(&swap_request(SwapType::Test));
+        let before = flash.contents().to_vec();
+
+        Trailer::write_image_ok(&mut flash, slot()).unwrap();
+        let trailer = Trailer::read(&mut flash, slot()).unwrap();
+        assert_eq!(trailer.image_ok, Flag::Set);
+        assert_eq!(trailer.state(), ImageState::Confirmed);
+
+        // Without an erase, and only the image_ok byte changed
+        assert_eq!(flash.erase_count(0x1000 + SLOT_SIZE - 1), 0);
+        let image_ok = (0x1000 + SLOT_SIZE) as usize - TRAILER_SIZE + IMAGE_OK_OFFSET;
+        let changed: Vec<_> = (0..before.len()).filter(|i| before[*i] != flash.contents()[*i]).collect();
+        assert_eq!(changed, [image_ok]);
+    }
+
+    #[test]
+    fn fields_after_a_swap() {
+        // What MCUBoot leaves in the primary slot after swapping in image 1
+        let mut data = swap_request(SwapType::Test);
+        data[SWAP_SIZE_OFFSET..SWAP_SIZE_OFFSET + 4].copy_from_slice(&0x7_4000u32.to_le_bytes());
+        data[SWAP_INFO_OFFSET] = 0x12;
+        data[COPY_DONE_OFFSET] = FLAG_SET;
+
+        let trailer = Trailer::parse(&data);
+        assert_eq!(trailer.swap_size, Some(0x7_4000));
+        assert_eq!(trailer.swap_info, Some(SwapInfo { image: 1, swap_type: SwapType::Test }));
+        assert_eq!(trailer.copy_done, Flag::Set);
+        assert_eq!(trailer.state(), ImageState::Testing);
+
+        data[SWAP_INFO_OFFSET] = 0x0a;
+        assert_eq!(Trailer::parse(&data).swap_info.unwrap().swap_type, SwapType::Other(0x0a));
+    }
+
+    #[test]
+    fn corrupt_trailers() {
+        // A flag that isn't 0x01 or 0xff
+        let mut data = swap_request(SwapType::Test);
+        data[IMAGE_OK_OFFSET] = 0x00;
+        let trailer = Trailer::parse(&data);
+        assert_eq!(trailer.image_ok, Flag::Bad);
+        assert_eq!(trailer.state(), ImageState::Corrupt);
+
+        // Half of the magic, like after an interrupted write
+        let mut data = swap_request(SwapType::Test);
+        data[MAGIC_OFFSET + 8..].fill(ERASED);
+        let trailer = Trailer::parse(&data);
+        assert_eq!(trailer.magic, Magic::Bad);
+        assert_eq!(trailer.state(), ImageState::Corrupt);
+
+        // Random data, never panics
+        let data: [u8; TRAILER_SIZE] = core::array::from_fn(|i| (i * 37) as u8);
+        assert_eq!(Trailer::parse(&data).state(), ImageState::Corrupt);
+    }
+
+    #[test]
+    fn swap_type_values() {
+        for value in 0..=0x0f {
+            assert_eq!(SwapType::from(value).value(), value);
+        }
+    }
+}
@@ -0,0 +1,580 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::flash::crc32;
+use crate::flash::partition::{Partition, PartitionError, FlashOrigin};
+use crate::beacon::BeaconConfig;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
+
+// Persistent settings
+//
+// Changes are appended as key/value entries to one sector of a sector pair.
+// When that sector is full, all current values are compacted into the other
+// sector, which takes over because of its higher generation number. The
+// sector header is written last, so an interrupted compaction leaves the old
+// sector in charge.
+//
+// Sector header (16 bytes): magic ("PTST"), generation (u32), schema version
+// (u32), CRC-32 of the previous 12 bytes.
+// Entry: key (u8), value length (u8), value, padding to 4 bytes, CRC-32 of
+// key, length and value.
+
+const MAGIC: [u8; 4] = *b"PTST";
+const SCHEMA_VERSION: u32 = 1;
+const HEADER_SIZE: u32 = 16;
+const NO_KEY: u8 = 0xff;
+
+// Advertising data is 31 bytes, minus the flags and the name's AD header
+pub const MAX_BLE_NAME_LEN: usize = 26;
+
+// 9999-12-31 23:59:59, chrono can't go much further
+const MAX_TIMESTAMP: i64 = 253_402_300_799;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Setting {
+    Brightness(u8),     // 1 (darkest) to 7
+    DoNotDisturb(bool),
+    BleName(String),
+    TimeCheckpoint(i64),    // Seconds since the epoch
+    Beacon(Option<BeaconConfig>),   // Validated, see BeaconConfig::validate
+    BeaconEnabled(bool),    // Beacon instead of connectable, if there is one
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub brightness: u8,
+    pub do_not_disturb: bool,
+    pub ble_name: String,
+    pub time_checkpoint: i64,
+    pub beacon: Option<BeaconConfig>,
+    pub beacon_enabled: bool,
+}
+
+#[derive(Debug)]
+pub enum SettingsError<E> {
+    Flash(PartitionError<E>),
+    InvalidValue,
+    InvalidPartition,   // Not two erase sectors
+}
+
+impl<E> From<PartitionError<E>> for SettingsError<E> {
+    fn from(error: PartitionError<E>) -> SettingsError<E> {
+        SettingsError::Flash(error)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            brightness: 7,
+            do_not_disturb: false,
+            ble_name: String::from("PineTime-rs"),
+            time_checkpoint: 0,
+            beacon: None,
+            beacon_enabled: false,
+        }
+    }
+}
+
+impl Settings {
+    fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::Brightness(value) => self.brightness = value,
+            Setting::DoNotDisturb(value) => self.do_not_disturb = value,
+            Setting::BleName(value) => self.ble_name = value,
+            Setting::TimeCheckpoint(value) => self.time_checkpoint = value,
+            Setting::Beacon(value) => self.beacon = value,
+            Setting::BeaconEnabled(value) => self.beacon_enabled = value,
+        }
+    }
+
+    fn to_settings(&self) -> [Setting; 6] {
+        [
+            Setting::Brightness(self.brightness),
+            Setting::DoNotDisturb(self.do_not_disturb),
+            Setting::BleName(self.ble_name.clone()),
+            Setting::TimeCheckpoint(self.time_checkpoint),
+            Setting::Beacon(self.beacon.clone()),
+            Setting::BeaconEnabled(self.beacon_enabled),
+        ]
+    }
+
+    // The beacon to send instead of connectable advertising, if any
+    pub fn active_beacon(&self) -> Option<&BeaconConfig> {
+        self.beacon.as_ref().filter(|_| self.beacon_enabled)
+    }
+}
+
+impl Setting {
+    fn key(&self) -> u8 {
+        match self {
+            Setting::Brightness(_) => 0x01,
+            Setting::DoNotDisturb(_) => 0x02,
+            Setting::BleName(_) => 0x03,
+            Setting::TimeCheckpoint(_) => 0x04,
+            Setting::Beacon(_) => 0x05,
+            Setting::BeaconEnabled(_) => 0x06,
+        }
+    }
+
+    fn value(&self) -> Vec<u8> {
+        match self {
+            Setting::Brightness(value) => vec![*value],
+            Setting::DoNotDisturb(value) => vec![*value as u8],
+            Setting::BleName(value) => value.as_bytes().to_vec(),
+            Setting::TimeCheckpoint(value) => value.to_le_bytes().to_vec(),
+            Setting::Beacon(value) => value.as_ref().map_or(Vec::new(), BeaconConfig::to_bytes),
+            Setting::BeaconEnabled(value) => vec![*value as u8],
+        }
+    }
+
+    // Returns None for unknown keys and invalid values
+    fn parse(key: u8, value: &[u8]) -> Option<Setting> {
+        match (key, value) {
+            (0x01, [brightness @ 1..=7]) => Some(Setting::Brightness(*brightness)),
+            (0x02, [dnd @ 0..=1]) => Some(Setting::DoNotDisturb(*dnd == 1)),
+            (0x03, name) if !name.is_empty() && name.len() <= MAX_BLE_NAME_LEN => {
+                String::from_utf8(name.to_vec()).ok().map(Setting::BleName)
+            },
+            (0x04, timestamp) if timestamp.len() == 8 => {
+                let timestamp = i64::from_le_bytes(timestamp.try_into().unwrap());
+                (0..=MAX_TIMESTAMP).contains(&timestamp)
+                    .then_some(Setting::TimeCheckpoint(timestamp))
+            },
+            (0x05, []) => Some(Setting::Beacon(None)),
+            (0x05, config) => BeaconConfig::parse(config).ok().map(|config| Setting::Beacon(Some(config))),
+            (0x06, [enabled @ 0..=1]) => Some(Setting::BeaconEnabled(*enabled == 1)),
+            _ => None,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        Setting::parse(self.key(), &self.value()).as_ref() == Some(self)
+    }
+
+    fn to_entry(&self) -> Vec<u8> {
+        let value = self.value();
+
+        let mut entry = vec![self.key(), value.len() as u8];
+        entry.extend_from_slice(&value);
+        let crc = crc32(&entry);
+        entry.resize(entry_size(value.len() as u8) as usize - 4, 0xff);
+        entry.extend_from_slice(&crc.to_le_bytes());
+
+        entry
+    }
+}
+
+fn entry_size(value_len: u8) -> u32 {
+    (2 + value_len as u32).div_ceil(4) * 4 + 4
+}
+
+// Convert an entry written with schema version `schema` to a current setting
+fn migrate(schema: u32, key: u8, value: &[u8]) -> Option<Setting> {
+    match schema {
+        // Add older versions here when the meaning of a key changes
+        SCHEMA_VERSION => Setting::parse(key, value),
+        // Written by newer firmware, don't guess
+        _ => None,
+    }
+}
+
+pub struct SettingsStore<F> {
+    partition: Partition<F>,
+    settings: Settings,
+    sector: u32,
+    generation: u32,
+    // Where the next entry goes, None if the sector has to be compacted first
+    // (no valid sector, an interrupted write or an old schema)
+    write_offset: Option<u32>,
+    // False if loading failed, then changes only last until the next boot
+    // instead of overwriting settings that couldn't be read
+    persistent: bool,
+}
+
+impl<F: NorFlash + FlashOrigin> SettingsStore<F> {
+    // `partition` has to be two erase sectors. Invalid or corrupted entries
+    // fall back to their default value.
+    pub fn load(flash: &mut F, partition: Partition<F>) -> Result<Self, SettingsError<F::Error>> {
+        if partition.size != 2 * F::ERASE_SIZE as u32 {
+            return Err(SettingsError::InvalidPartition);
+        }
+
+        let mut store = Self::defaults(partition);
+        store.persistent = true;
+
+        let mut newest: Option<(u32, u32, u32)> = None;
+        for sector in 0..2 {
+            let mut header = [0; HEADER_SIZE as usize];
+            partition.read(flash, store.sector_start(sector), &mut header)?;
+
+            if let Some((generation, schema)) = parse_header(&header) {
+                if newest.is_none_or(|(_, newest_generation, _)| generation > newest_generation) {
+                    newest = Some((sector, generation, schema));
+                }
+            }
+        }
+
+        // Nothing written yet if there is no valid sector, use the defaults
+        if let Some((sector, generation, schema)) = newest {
+            store.sector = sector;
+            store.generation = generation;
+
+            let end = store.replay(flash, schema)?;
+            if schema == SCHEMA_VERSION {
+                store.write_offset = end;
+            }
+        }
+
+        Ok(store)
+    }
+
+    // Default settings that are never written to flash, for when load fails
+    pub fn defaults(partition: Partition<F>) -> Self {
+        SettingsStore {
+            partition,
+            settings: Settings::default(),
+            sector: 0,
+            generation: 0,
+            write_offset: None,
+            persistent: false,
+        }
+    }
+
+    pub fn get(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn set(&mut self, flash: &mut F, setting: Setting) -> Result<(), SettingsError<F::Error>> {
+        if !setting.is_valid() {
+            return Err(SettingsError::InvalidValue);
+        }
+
+        let mut settings = self.settings.clone();
+        settings.apply(setting.clone());
+        if settings == self.settings {
+            return Ok(());
+        }
+
+        if !self.persistent {
+            self.settings = settings;
+            return Ok(());
+        }
+
+        let entry = setting.to_entry();
+        match self.write_offset {
+            Some(offset) if offset + entry.len() as u32 <= self.sector_size() => {
+                self.write_offset = None;
+                self.partition.write(flash, self.sector_start(self.sector) + offset, &entry)?;
+                self.write_offset = Some(offset + entry.len() as u32);
+            },
+            _ => self.compact(flash, &settings)?,
+        }
+
+        self.settings = settings;
+
+        Ok(())
+    }
+
+    fn sector_size(&self) -> u32 {
+        F::ERASE_SIZE as u32
+    }
+
+    fn sector_start(&self, sector: u32) -> u32 {
+        sector * self.sector_size()
+    }
+
+    // Apply all entries of the current sector, returns the offset after the
+    // last one or None if the rest of the sector isn't usable
+    fn replay(&mut self, flash: &mut F, schema: u32) -> Result<Option<u32>, PartitionError<F::Error>> {
+        let start = self.sector_start(self.sector);
+        let mut offset = HEADER_SIZE;
+
+        while offset + entry_size(0) <= self.sector_size() {
+            let mut key_len = [0; 2];
+            self.partition.read(flash, start + offset, &mut key_len)?;
+            let [key, len] = key_len;
+
+            if key == NO_KEY {
+                return Ok(self.is_erased(flash, start + offset, start + self.sector_size())?
+                    .then_some(offset));
+            }
+
+            let size = entry_size(len);
+            if offset + size > self.sector_size() {
+                return Ok(None);
+            }
+
+            let mut entry = vec![0; size as usize];
+            self.partition.read(flash, start + offset, &mut entry)?;
+
+            let data = &entry[..2 + len as usize];
+            let crc = u32::from_le_bytes(entry[size as usize - 4..].try_into().unwrap());
+            // Corrupted, the rest of the sector can't be trusted either
+            if crc32(data) != crc {
+                return Ok(None);
+            }
+
+            if let Some(setting) = migrate(schema, key, &data[2..]) {
+                self.settings.apply(setting);
+            }
+
+            offset += size;
+        }
+
+        Ok(Some(offset))
+    }
+
+    fn is_erased(&self, flash: &mut F, from: u32, to: u32) -> Result<bool, PartitionError<F::Error>> {
+        let mut buffer = [0; 64];
+        let mut address = from;
+
+        while address < to {
+            let len = buffer.len().min((to - address) as usize);
+            self.partition.read(flash, address, &mut buffer[..len])?;
+            if buffer[..len].iter().any(|b| *b != 0xff) {
+                return Ok(false);
+            }
+            address += len as u32;
+        }
+
+        Ok(true)
+    }
+
+    // Write `settings` to the other sector and switch to it
+    fn compact(&mut self, flash: &mut F, settings: &Settings) -> Result<(), PartitionError<F::Error>> {
+        let target = 1 - self.sector;
+        let start = self.sector_start(target);
+        let generation = self.generation + 1;
+
+        self.write_offset = None;
+        self.partition.erase(flash, start, start + self.sector_size())?;
+
+        let mut offset = HEADER_SIZE;
+        for setting in settings.to_settings().iter() {
+            let entry = setting.to_entry();
+            self.partition.write(flash, start + offset, &entry)?;
+            offset += entry.len() as u32;
+        }
+
+        self.partition.write(flash, start, &header(generation, SCHEMA_VERSION))?;
+
+        self.sector = target;
+        self.generation = generation;
+        self.write_offset = Some(offset);
+
+        Ok(())
+    }
+}
+
+fn header(generation: u32, schema: u32) -> [u8; HEADER_SIZE as usize] {
+    let mut header = [0; HEADER_SIZE as usize];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4..8].copy_from_slice(&generation.to_le_bytes());
+    header[8..12].copy_from_slice(&schema.to_le_bytes());
+    let crc = crc32(&header[0..12]);
+    header[12..16].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+// Returns the generation and schema version if the header is valid
+fn parse_header(header: &[u8]) -> Option<(u32, u32)> {
+    let crc = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    if header[0..4] != MAGIC || crc32(&header[0..12]) != crc {
+        return None;
+    }
+
+    Some((
+        u32::from_le_bytes(header[4..8].try_into().unwrap()),
+        u32::from_le_bytes(header[8..12].try_into().unwrap()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beacon::BeaconKind;
+    use crate::flash::SimulatedFlash;
+
+    const SECTOR: u32 = 0x1000;
+
+    fn partition() -> Partition<SimulatedFlash> {
+        Partition::new("SETTINGS", SECTOR, 2 * SECTOR)
+    }
+
+    fn load(flash: &mut SimulatedFlash) -> SettingsStore<SimulatedFlash> {
+        SettingsStore::load(flash, partition()).unwrap()
+    }
+
+    #[test]
+    fn defaults_on_empty_flash() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let store = load(&mut flash);
+        assert_eq!(store.get(), &Settings::default());
+        assert!(flash.contents().iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn set_and_reload() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut store = load(&mut flash);
+
+        let beacon = BeaconConfig {
+            kind: BeaconKind::EddystoneUid { namespace: [1; 10], instance: [2; 6], tx_power: -20 },
+            interval_ms: 1000,
+        };
+        store.set(&mut flash, Setting::Brightness(3)).unwrap();
+        store.set(&mut flash, Setting::BleName(String::from("Watch"))).unwrap();
+        store.set(&mut flash, Setting::TimeCheckpoint(1_600_000_000)).unwrap();
+        store.set(&mut flash, Setting::Beacon(Some(beacon.clone()))).unwrap();
+        store.set(&mut flash, Setting::BeaconEnabled(true)).unwrap();
+
+        let store = load(&mut flash);
+        assert_eq!(store.get().brightness, 3);
+        assert_eq!(store.get().ble_name, "Watch");
+        assert_eq!(store.get().time_checkpoint, 1_600_000_000);
+        assert_eq!(store.get().active_beacon(), Some(&beacon));
+        assert!(!store.get().do_not_disturb);
+
+        // Nothing outside of the partition was touched
+        assert!(flash.contents()[..SECTOR as usize].iter().all(|b| *b == 0xff));
+        assert!(flash.contents()[3 * SECTOR as usize..].iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn invalid_values() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut store = load(&mut flash);
+
+        let long_name = String::from_utf8(vec![b'a'; MAX_BLE_NAME_LEN + 1]).unwrap();
+        for setting in [
+            Setting::Brightness(0),
+            Setting::Brightness(8),
+            Setting::BleName(String::new()),
+            Setting::BleName(long_name),
+            Setting::TimeCheckpoint(-1),
+            Setting::TimeCheckpoint(MAX_TIMESTAMP + 1),
+        ] {
+            assert!(matches!(store.set(&mut flash, setting), Err(SettingsError::InvalidValue)));
+        }
+        assert_eq!(store.get(), &Settings::default());
+    }
+
+    #[test]
+    fn invalid_partition() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let partition = Partition::new("SETTINGS", 0, SECTOR);
+        assert!(matches!(
+            SettingsStore::load(&mut flash, partition),
+            Err(SettingsError::InvalidPartition),
+        ));
+    }
+
+    #[test]
+    fn compaction_alternates_sectors() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut store = load(&mut flash);
+
+        for i in 0..2000 {
+            store.set(&mut flash, Setting::TimeCheckpoint(i)).unwrap();
+        }
+        store.set(&mut flash, Setting::Brightness(2)).unwrap();
+
+        let store = load(&mut flash);
+        assert_eq!(store.get().time_checkpoint, 1999);
+        assert_eq!(store.get().brightness, 2);
+
+        let first = flash.erase_count(SECTOR);
+        let second = flash.erase_count(2 * SECTOR);
+        assert!(first > 0 && first.abs_diff(second) <= 1);
+    }
+
+    #[test]
+    fn power_loss_during_set() {
+        // Fill up the sector first, so the interrupted set has to compact
+        for filled in [0, 1000 / 4, 1000] {
+            for operations in 0.. {
+                let mut flash = SimulatedFlash::new(4 * SECTOR);
+                let mut store = load(&mut flash);
+                store.set(&mut flash, Setting::Brightness(5)).unwrap();
+                for i in 0..filled {
+                    store.set(&mut flash, Setting::TimeCheckpoint(i)).unwrap();
+                }
+
+                flash.fail_after(operations);
+                let completed = store.set(&mut flash, Setting::Brightness(1)).is_ok();
+                flash.power_cycle();
+
+                let mut store = load(&mut flash);
+                let brightness = store.get().brightness;
+                assert!(brightness == 5 || brightness == 1);
+                if completed {
+                    assert_eq!(brightness, 1);
+                }
+                assert_eq!(store.get().time_checkpoint, (filled - 1).max(0));
+
+                // Still writable
+                store.set(&mut flash, Setting::DoNotDisturb(true)).unwrap();
+                assert!(load(&mut flash).get().do_not_disturb);
+
+                if completed {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_entry() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut store = load(&mut flash);
+        store.set(&mut flash, Setting::Brightness(4)).unwrap();
+        store.set(&mut flash, Setting::DoNotDisturb(true)).unwrap();
+
+        // The first write compacts into the second sector, flip a bit in the
+        // DoNotDisturb entry at the end
+        let offset = store.write_offset.unwrap();
+        flash.inject_bit_error(2 * SECTOR + offset - 6, 0);
+
+        let mut store = load(&mut flash);
+        assert_eq!(store.get().brightness, 4);
+        assert!(!store.get().do_not_disturb);
+
+        // The rest of the sector can't be trusted, so the next set compacts
+        store.set(&mut flash, Setting::DoNotDisturb(true)).unwrap();
+        let store = load(&mut flash);
+        assert_eq!(store.get().brightness, 4);
+        assert!(store.get().do_not_disturb);
+    }
+
+    #[test]
+    fn newer_schema_is_ignored() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut store = load(&mut flash);
+        store.set(&mut flash, Setting::Brightness(4)).unwrap();
+
+        let newer = header(store.generation + 1, SCHEMA_VERSION + 1);
+        NorFlash::erase(&mut flash, SECTOR, 2 * SECTOR).unwrap();
+        NorFlash::write(&mut flash, SECTOR, &newer).unwrap();
+        NorFlash::write(&mut flash, SECTOR + HEADER_SIZE, &Setting::Brightness(2).to_entry()).unwrap();
+
+        let mut store = load(&mut flash);
+        assert_eq!(store.get(), &Settings::default());
+
+        // Writing compacts into the other sector, with the current schema
+        store.set(&mut flash, Setting::Brightness(3)).unwrap();
+        assert_eq!(load(&mut flash).get().brightness, 3);
+    }
+
+    #[test]
+    fn defaults_are_not_written() {
+        let mut flash = SimulatedFlash::new(4 * SECTOR);
+        let mut store = SettingsStore::defaults(partition());
+
+        store.set(&mut flash, Setting::Brightness(2)).unwrap();
+        assert_eq!(store.get().brightness, 2);
+        assert!(flash.contents().iter().all(|b| *b == 0xff));
+    }
+}
@@ -1,20 +1,27 @@
 MEMORY
 {
- /* ---- INTERNAL FLASH ---- */
+ /* ---- INTERNAL FLASH ---- (keep in sync with src/drivers/flash/partition.rs) */
  /* BOOTLOADER : ORIGIN = 0x00000000, LENGTH = 28K */
  /* REBOOTLOG : ORIGIN =  0x00007000, LENGTH = 4K */
     HEADER : ORIGIN = 0x00008000, LENGTH = 32
-    FLASH : ORIGIN =  0x00008020, LENGTH = 475064 /* 464K - 32 (HEADER) - 40 (FOOTER)*/
-    /*FOOTER: ORIGIN =  0x0007bfd8, LENGTH = 40 <-- Gives syntax error ?!*/
+    FLASH : ORIGIN =  0x00008020, LENGTH = 475056 /* 464K - 32 (HEADER) - 48 (FOOTER)*/
+    /*FOOTER: ORIGIN =  0x0007bfd0, LENGTH = 48 <-- Gives syntax error ?!*/
  /* SCRATCH : ORIGIN =    0x0007c000, LENGTH = 4K */
 
- /* ---- EXTERNAL FLASH ---- */
- /* BOOTLOADERASSETS : ORIGIN = 0x00000000, LENGTH = 4K */
+ /* ---- EXTERNAL FLASH ---- (keep in sync with src/drivers/flash/partition.rs) */
+ /* BOOTLOADERASSETS : ORIGIN = 0x00000000, LENGTH = 256K */
  /* STANDBY_IMAGE :    ORIGIN = 0x00040000, LENGTH = 464K */
- /* USER_FILESYSTEM :  ORIGIN = 0x000b4000, LENGTH = 3376K */
+ /* RESERVED :         ORIGIN = 0x000b4000, LENGTH = 304K */
+ /* BLE_KEYS :         ORIGIN = 0x00100000, LENGTH = 4K */
+ /* SETTINGS :         ORIGIN = 0x00101000, LENGTH = 8K */
+ /* SELF_TEST :        ORIGIN = 0x00103000, LENGTH = 8K */
+ /* DATA_LOG :         ORIGIN = 0x00105000, LENGTH = 232K */
+ /* USER_FILESYSTEM :  ORIGIN = 0x0013f000, LENGTH = 2820K */
 
  /* ---- RAM ---- */
-    RAM : ORIGIN = 0x20000000, LENGTH = 64K
+    RAM : ORIGIN = 0x20000000, LENGTH = 63K
+    /* Not cleared on boot, survives soft resets */
+    NOINIT : ORIGIN = 0x2000fc00, LENGTH = 1K
 }
 
 flash_start = ORIGIN(HEADER);
@@ -25,4 +32,8 @@ SECTIONS {
       FILL(0xAAAAAAAA)
       . = . + 32;
     } > HEADER
+
+    .noinit (NOLOAD) : {
+      *(.noinit .noinit.*)
+    } > NOINIT
 }
@@ -7,21 +7,48 @@ use crate::drivers::battery::{Battery, BatteryState};
 use crate::drivers::clock::Clock;
 
 use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::crashlog;
+use crate::drivers::bluetooth::BeaconConfig;
+use crate::drivers::settings::MAX_BLE_NAME_LEN;
 use crate::pinetimers::ConnectedRtc;
 
 use chrono::{Datelike, Timelike, NaiveDateTime, NaiveDate, NaiveTime};
 
+use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::vec;
 
 use core::ops::BitOr;
 
+// Custom services and characteristics (f3adXXXX-6a9c-4b7e-9d8e-2c5a1f3b7e40)
+// only differ in byte 3. Characteristics count up from their service.
+const CUSTOM_UUID_BASE: [u8; 16] = [
+    0xf3, 0xad, 0x00, 0x00, 0x6a, 0x9c, 0x4b, 0x7e,
+    0x9d, 0x8e, 0x2c, 0x5a, 0x1f, 0x3b, 0x7e, 0x40,
+];
+
+const CRASH_LOG_SERVICE: u8 = 0x01;
+const BEACON_SERVICE: u8 = 0x03;
+
+fn custom_uuid_bytes(id: u8) -> [u8; 16] {
+    let mut uuid = CUSTOM_UUID_BASE;
+    uuid[3] = id;
+    uuid
+}
+
+fn custom_uuid(id: u8) -> Uuid128 {
+    Uuid128::from_bytes(custom_uuid_bytes(id))
+}
+
 #[derive(Debug)]
 pub enum ServiceUUID {
     Battery,
     CurrentTime,
     GenericAccess,
     DeviceInformation,
+    LinkLoss,
+    CrashLog,
+    Beacon,
 }
 
 impl ServiceUUID {
@@ -31,25 +58,36 @@ impl ServiceUUID {
             ServiceUUID::CurrentTime => vec![0x05, 0x18],
             ServiceUUID::GenericAccess => vec![0x00, 0x18],
             ServiceUUID::DeviceInformation => vec![0x0A, 0x18],
+            ServiceUUID::LinkLoss => vec![0x03, 0x18],
+            ServiceUUID::CrashLog => custom_uuid_bytes(CRASH_LOG_SERVICE).iter().rev().copied().collect(),
+            ServiceUUID::Beacon => custom_uuid_bytes(BEACON_SERVICE).iter().rev().copied().collect(),
         }
     }
 }
 
 #[derive(Debug)]
 pub enum CharacteristicUUID {
+    DeviceName,
     BatteryLevel,
     DateTime,
     CurrentTime,
     FirmwareRevisionString,
+    AlertLevel,
+    LatestCrash,
+    BeaconConfig,   // Write-only, see pinetime_common::beacon for the format
 }
 
 impl From<&CharacteristicUUID> for Uuid128 {
     fn from(uuid: &CharacteristicUUID) -> Uuid128 {
         match uuid {
+            CharacteristicUUID::DeviceName => Uuid16(0x2a00).into(),
             CharacteristicUUID::BatteryLevel => Uuid16(0x2a19).into(),
             CharacteristicUUID::DateTime => Uuid16(0x2a08).into(),
             CharacteristicUUID::CurrentTime => Uuid16(0x2a2b).into(),
             CharacteristicUUID::FirmwareRevisionString => Uuid16(0x2a26).into(),
+            CharacteristicUUID::AlertLevel => Uuid16(0x2a06).into(),
+            CharacteristicUUID::LatestCrash => custom_uuid(CRASH_LOG_SERVICE + 1),
+            CharacteristicUUID::BeaconConfig => custom_uuid(BEACON_SERVICE + 1),
         }
     }
 }
@@ -117,6 +155,23 @@ impl BitOr for CharacteristicProperty {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertLevel {
+    NoAlert,
+    MildAlert,
+    HighAlert,
+}
+
+impl From<u8> for AlertLevel {
+    fn from(value: u8) -> AlertLevel {
+        match value {
+            0x01 => AlertLevel::MildAlert,
+            0x02 => AlertLevel::HighAlert,
+            _ => AlertLevel::NoAlert,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum BluetoothAttribute {
     PrimaryService(ServiceUUID),
@@ -181,8 +236,17 @@ pub struct BluetoothAttributeProvider {
 }
 
 impl BluetoothAttributeProvider {
-    pub fn new() -> Self {
+    pub fn new(device_name: &str) -> Self {
         let attributes = vec![
+            BluetoothAttribute::PrimaryService(ServiceUUID::GenericAccess),
+            BluetoothAttribute::Characteristic(
+                CharacteristicProperty::Read | CharacteristicProperty::Write,
+                CharacteristicUUID::DeviceName
+            ),
+            BluetoothAttribute::CharacteristicValue(
+                CharacteristicUUID::DeviceName,
+                device_name.as_bytes().to_vec()
+            ),
             BluetoothAttribute::PrimaryService(ServiceUUID::Battery),
             BluetoothAttribute::Characteristic(
                 CharacteristicProperty::Read,
@@ -218,6 +282,33 @@ impl BluetoothAttributeProvider {
                 CharacteristicUUID::FirmwareRevisionString,
                 "unknown".as_bytes().to_vec()
             ),
+            BluetoothAttribute::PrimaryService(ServiceUUID::LinkLoss),
+            BluetoothAttribute::Characteristic(
+                CharacteristicProperty::Read | CharacteristicProperty::Write,
+                CharacteristicUUID::AlertLevel
+            ),
+            BluetoothAttribute::CharacteristicValue(
+                CharacteristicUUID::AlertLevel,
+                vec![0]
+            ),
+            BluetoothAttribute::PrimaryService(ServiceUUID::CrashLog),
+            BluetoothAttribute::Characteristic(
+                CharacteristicProperty::Read,
+                CharacteristicUUID::LatestCrash
+            ),
+            BluetoothAttribute::CharacteristicValue(
+                CharacteristicUUID::LatestCrash,
+                crashlog::latest_summary()
+            ),
+            BluetoothAttribute::PrimaryService(ServiceUUID::Beacon),
+            BluetoothAttribute::Characteristic(
+                CharacteristicProperty::Write,
+                CharacteristicUUID::BeaconConfig
+            ),
+            BluetoothAttribute::CharacteristicValue(
+                CharacteristicUUID::BeaconConfig,
+                vec![]
+            ),
         ];
         let rubble_attributes = Self::rubble_attributes(&attributes);
         Self {
@@ -237,6 +328,17 @@ impl BluetoothAttributeProvider {
         self.rubble_attributes = Self::rubble_attributes(&self.attributes);
     }
 
+    // Alert level of the Link Loss service, set by the phone
+    pub fn alert_level(&self) -> AlertLevel {
+        self.attributes.iter()
+            .find_map(|attribute| match attribute {
+                BluetoothAttribute::CharacteristicValue(CharacteristicUUID::AlertLevel, value) =>
+                    Some(value[0].into()),
+                _ => None,
+            })
+            .unwrap_or(AlertLevel::NoAlert)
+    }
+
     pub fn update_data(
         &mut self,
         battery: &mut Battery,
@@ -292,6 +394,15 @@ impl BluetoothAttributeProvider {
                                 CharacteristicUUID::FirmwareRevisionString,
                                 mcuboot.version_string().as_bytes().to_vec()
                             ),
+                        CharacteristicUUID::LatestCrash =>
+                            BluetoothAttribute::CharacteristicValue(
+                                CharacteristicUUID::LatestCrash,
+                                crashlog::latest_summary()
+                            ),
+                        // Only written by the phone
+                        CharacteristicUUID::DeviceName
+                            | CharacteristicUUID::AlertLevel
+                            | CharacteristicUUID::BeaconConfig => continue,
                     };
                 },
                 _ => {}
@@ -436,6 +547,37 @@ impl AttributeProvider for BluetoothAttributeProvider {
                     ).unwrap();
                 }
             }
+            // Rubble picks the ATT error code, the spec's Out of Range (0xFF)
+            // can't be returned from here
+            BluetoothAttribute::CharacteristicValue(CharacteristicUUID::AlertLevel, _) => {
+                if data.len() != 1 || data[0] > 2 {
+                    return Err(Error::InvalidValue);
+                }
+                self.attributes[i] = BluetoothAttribute::CharacteristicValue(
+                    CharacteristicUUID::AlertLevel,
+                    data.to_vec()
+                );
+            }
+            // Saved and advertised from the next time advertising starts.
+            // Rubble has no error for a busy server, so a name that can't be
+            // queued is rejected like an invalid one, the phone can retry.
+            BluetoothAttribute::CharacteristicValue(CharacteristicUUID::DeviceName, _) => {
+                if data.is_empty() || data.len() > MAX_BLE_NAME_LEN {
+                    return Err(Error::InvalidValue);
+                }
+                let name = String::from_utf8(data.to_vec()).map_err(|_| Error::InvalidValue)?;
+                crate::tasks::set_ble_name::spawn(name).map_err(|_| Error::InvalidValue)?;
+                self.attributes[i] = BluetoothAttribute::CharacteristicValue(
+                    CharacteristicUUID::DeviceName,
+                    data.to_vec()
+                );
+            }
+            // Validated here, so an invalid beacon is never stored. Rejected
+            // like an invalid one if it can't be queued, so the phone retries.
+            BluetoothAttribute::CharacteristicValue(CharacteristicUUID::BeaconConfig, _) => {
+                let config = BeaconConfig::parse(data).map_err(|_| Error::InvalidValue)?;
+                crate::tasks::set_beacon::spawn(config).map_err(|_| Error::InvalidValue)?;
+            }
             _ => {},
         };
 
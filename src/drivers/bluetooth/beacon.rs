@@ -0,0 +1,40 @@
+use rubble::beacon::Beacon;
+use rubble::link::DeviceAddress;
+use rubble::link::ad_structure::{AdStructure, Flags};
+use rubble::Error;
+
+use pinetime_common::beacon::EDDYSTONE_UUID;
+
+pub use pinetime_common::beacon::{BeaconConfig, BeaconKind};
+
+// Advertising data for `config`. Validated configs always fit, so this only
+// fails if rubble rejects the advertising data.
+pub fn to_beacon(config: &BeaconConfig, device_address: DeviceAddress) -> Result<Beacon, Error> {
+    let frame = config.kind.frame().map_err(|_| Error::InvalidValue)?;
+
+    // LE General Discoverable, BR/EDR not supported
+    let flags = AdStructure::Flags(Flags::from_bits(0b00000110).unwrap());
+
+    match config.kind {
+        BeaconKind::IBeacon { .. } => Beacon::new(device_address, &[
+            flags,
+            AdStructure::Unknown {
+                ty: 0xff, // Manufacturer Specific Data
+                data: &frame,
+            },
+        ]),
+        BeaconKind::EddystoneUid { .. } | BeaconKind::EddystoneUrl { .. } => {
+            Beacon::new(device_address, &[
+                flags,
+                AdStructure::Unknown {
+                    ty: 0x03, // Complete List of 16-bit Service UUIDs
+                    data: &EDDYSTONE_UUID,
+                },
+                AdStructure::Unknown {
+                    ty: 0x16, // Service Data - 16-bit UUID
+                    data: &frame,
+                },
+            ])
+        },
+    }
+}
@@ -1,5 +1,11 @@
 mod config;
 mod attribute_provider;
+mod beacon;
+mod privacy;
+
+pub use beacon::{BeaconConfig, BeaconKind};
+pub use privacy::{IdentityResolvingKey, Privacy};
+pub use attribute_provider::AlertLevel;
 
 use config::BluetoothConfig;
 use attribute_provider::BluetoothAttributeProvider;
@@ -11,27 +17,66 @@ use crate::drivers::clock::Clock;
 
 use crate::pinetimers::ConnectedRtc;
 
-use nrf52832_hal::pac::{RADIO, FICR};
+use nrf52832_hal::pac::{self, RADIO, FICR};
 
-use rubble_nrf5x::utils::get_device_address;
 use rubble_nrf5x::radio::{BleRadio, PacketBuffer};
 use rubble_nrf5x::timer::BleTimer;
-use rubble::link::queue::{SimpleQueue, PacketQueue};
-use rubble::link::{LinkLayer, Responder, Cmd};
+use rubble::link::queue::{SimpleQueue, SimpleProducer, SimpleConsumer, PacketQueue};
+use rubble::link::{LinkLayer, Responder, Cmd, DeviceAddress};
 use rubble::l2cap::{L2CAPState, BleChannelMap};
 use rubble::link::ad_structure::{AdStructure, Flags};
 use rubble::time::Timer;
+use rubble::beacon::Beacon;
 
 use super::mcuboot::MCUBoot;
 
+use alloc::string::String;
+
+pub enum BluetoothMode {
+    // Connectable advertising, exposing the GATT services
+    Connectable,
+    // Non-connectable iBeacon/Eddystone advertising
+    Beacon(BeaconConfig),
+}
+
+// A TX and RX queue. The link layer and the responder each hold one end of
+// both, and only give them back by being dropped, so they are kept as
+// pointers to split them again.
+struct QueuePair(*mut SimpleQueue, *mut SimpleQueue);
+
+// Only used from the task holding the Bluetooth resource
+unsafe impl Send for QueuePair {}
+
+impl QueuePair {
+    fn new(tx: &'static mut SimpleQueue, rx: &'static mut SimpleQueue) -> Self {
+        QueuePair(tx, rx)
+    }
+
+    // Empty queues to split. Nothing may still hold the ends of an earlier
+    // split.
+    unsafe fn reset(&self) -> (&'static mut SimpleQueue, &'static mut SimpleQueue) {
+        let (tx, rx) = (&mut *self.0, &mut *self.1);
+        *tx = SimpleQueue::new();
+        *rx = SimpleQueue::new();
+        (tx, rx)
+    }
+}
+
 pub struct Bluetooth {
     linklayer: LinkLayer<BluetoothConfig>,
     radio: BleRadio,
     responder: Responder<BluetoothConfig>,
+    privacy: Privacy,
+    beacon: Option<(BeaconConfig, Beacon)>,
+    connected: bool,
+    device_name: String,
+    // The link layer can't change its address or advertise again after a
+    // connection, so it is replaced. The new one uses the spare queues, while
+    // the old one still holds the ends of the queues in use.
+    queues: [QueuePair; 2],
+    queues_in_use: usize,
 }
 
-// TODO: add power_on function that re-runs start_advertise and configure_interrupt
-//      --> Problem: tx_cons and rx_prod are owned by start_advertise
 impl Bluetooth {
     pub fn new(
         radio: RADIO,
@@ -39,12 +84,20 @@ impl Bluetooth {
         timer: BluetoothTimer,
         ble_tx_buf: &'static mut PacketBuffer,
         ble_rx_buf: &'static mut PacketBuffer,
-        ble_tx_queue: &'static mut SimpleQueue,
-        ble_rx_queue: &'static mut SimpleQueue,
+        ble_queues: [&'static mut SimpleQueue; 4],
+        mut privacy: Privacy,
+        device_name: &str,
+        mode: BluetoothMode,
     ) -> Bluetooth {
-        let device_address = get_device_address();
+        // The phone can't resolve the address without pairing, which rubble
+        // doesn't do, so it sees a new device after every rotation
+        let device_address = privacy.resolvable_private_address();
 
-        rprintln!("{:#?}", device_address);
+        let [tx_queue, rx_queue, spare_tx_queue, spare_rx_queue] = ble_queues;
+        let queues = [
+            QueuePair::new(tx_queue, rx_queue),
+            QueuePair::new(spare_tx_queue, spare_rx_queue),
+        ];
 
         let mut ble_radio = BleRadio::new(
             radio,
@@ -55,41 +108,102 @@ impl Bluetooth {
 
         let ble_timer = BleTimer::init(timer);
 
-        // Set up queues
-        let (tx_prod, tx_cons) = ble_tx_queue.split();
-        let (rx_prod, rx_cons) = ble_rx_queue.split();
+        // Nothing used the queues yet
+        let (tx_queue, rx_queue) = unsafe { queues[0].reset() };
+        let (mut ble_ll, ble_r, tx_cons, rx_prod) =
+            link_layer(device_address, ble_timer, tx_queue, rx_queue, device_name);
 
-        let mut ble_ll = LinkLayer::<BluetoothConfig>::new(device_address, ble_timer);
+        // Validated when stored, so falling back to connectable mode shouldn't
+        // happen
+        let beacon = match mode {
+            BluetoothMode::Connectable => None,
+            BluetoothMode::Beacon(config) => match beacon::to_beacon(&config, device_address) {
+                Ok(beacon) => Some((config, beacon)),
+                Err(e) => {
+                    rprintln!("Invalid beacon, advertising as connectable: {:?}", e);
+                    None
+                },
+            },
+        };
 
-        let ble_r = Responder::<BluetoothConfig>::new(
-            tx_prod,
-            rx_cons,
-            L2CAPState::new(BleChannelMap::with_attributes(BluetoothAttributeProvider::new())),
-        );
-
-        let next_update = ble_ll
-            .start_advertise(
-                rubble::time::Duration::from_millis(100),
-                &[
-                    AdStructure::CompleteLocalName("PineTime-rs"),
-                    AdStructure::Flags(
-                        Flags::from_bits(0b00000111).unwrap()
-                    ),
-                ],
-                &mut ble_radio,
-                tx_cons,
-                rx_prod,
-            )
-            .unwrap();
-        ble_ll.timer().configure_interrupt(next_update);
+        match beacon {
+            None => advertise(&mut ble_ll, &mut ble_radio, device_name, tx_cons, rx_prod),
+            Some(_) => {
+                // The link layer is never started, so beacons are sent from
+                // the ble_beacon task instead of the BLE timer.
+                crate::tasks::ble_beacon::spawn().unwrap();
+            },
+        }
 
         Bluetooth {
             linklayer: ble_ll,
             radio: ble_radio,
             responder: ble_r,
+            privacy,
+            beacon,
+            connected: false,
+            device_name: String::from(device_name),
+            queues,
+            queues_in_use: 0,
+        }
+    }
+
+    // Called by ble_rotate_address task. While connected, the address is
+    // rotated when the connection ends.
+    pub fn rotate_address(&mut self) {
+        match self.beacon.as_mut() {
+            Some((config, beacon)) => {
+                let device_address = self.privacy.resolvable_private_address();
+
+                // Only the address changed, so this can't fail if the old one
+                // didn't
+                if let Ok(new_beacon) = beacon::to_beacon(config, device_address) {
+                    *beacon = new_beacon;
+                }
+            },
+            None if !self.linklayer.is_connected() => self.restart_advertising(),
+            None => {},
         }
     }
 
+    // Advertise with a new private address, replacing the link layer and the
+    // responder (so the GATT values are back to their defaults until the next
+    // ble_update)
+    fn restart_advertising(&mut self) {
+        let device_address = self.privacy.resolvable_private_address();
+        let spare = 1 - self.queues_in_use;
+
+        // The users of the spare queues were dropped when they were replaced
+        // last time, and the old link layer's timer isn't used after this
+        let (tx_queue, rx_queue) = unsafe { self.queues[spare].reset() };
+        let timer = BleTimer::init(unsafe { pac::Peripherals::steal() }.TIMER2);
+
+        let (mut linklayer, responder, tx_cons, rx_prod) =
+            link_layer(device_address, timer, tx_queue, rx_queue, &self.device_name);
+        advertise(&mut linklayer, &mut self.radio, &self.device_name, tx_cons, rx_prod);
+
+        self.linklayer = linklayer;
+        self.responder = responder;
+        self.queues_in_use = spare;
+        self.connected = false;
+    }
+
+    // Called by set_ble_name task, the name is advertised from the next time
+    // advertising starts (after a rotation or connection)
+    pub fn set_device_name(&mut self, device_name: String) {
+        self.device_name = device_name;
+    }
+
+    // Called by ble_beacon task, returns the interval until the next beacon
+    // in milliseconds (None if not in beacon mode)
+    pub fn broadcast_beacon(&mut self) -> Option<u32> {
+        let (config, beacon) = self.beacon.as_ref()?;
+
+        beacon.broadcast(&mut self.radio);
+
+        Some(config.interval_ms)
+    }
+
     pub fn update_data(
         &mut self,
         battery: &mut Battery,
@@ -110,6 +224,27 @@ impl Bluetooth {
         if cmd.queued_work {
             crate::tasks::ble_worker::spawn().unwrap();
         }
+
+        // Rubble doesn't tell us why a connection ended, so every lost
+        // connection is treated as unexpected
+        let connected = self.linklayer.is_connected();
+        if self.connected && !connected {
+            let alert_level = self.responder.l2cap()
+                .channel_mapper()
+                .attribute_provider()
+                .alert_level();
+
+            // Already alerting if it's still queued
+            if alert_level != AlertLevel::NoAlert {
+                crate::tasks::link_lost::spawn(alert_level).ok();
+            }
+
+            // The link layer stops after a connection, and this is a good
+            // time for a new address anyway
+            self.restart_advertising();
+            return;
+        }
+        self.connected = connected;
     }
 
     // Called on RADIO interrupt using ble_radio task
@@ -141,3 +276,55 @@ impl Bluetooth {
         }
     }
 }
+
+// Link layer and responder on the given queues, with the GATT services. The
+// returned queue ends are for advertising.
+fn link_layer(
+    device_address: DeviceAddress,
+    timer: BleTimer<BluetoothTimer>,
+    tx_queue: &'static mut SimpleQueue,
+    rx_queue: &'static mut SimpleQueue,
+    device_name: &str,
+) -> (
+    LinkLayer<BluetoothConfig>,
+    Responder<BluetoothConfig>,
+    SimpleConsumer<'static>,
+    SimpleProducer<'static>,
+) {
+    let (tx_prod, tx_cons) = tx_queue.split();
+    let (rx_prod, rx_cons) = rx_queue.split();
+
+    let linklayer = LinkLayer::<BluetoothConfig>::new(device_address, timer);
+
+    let responder = Responder::<BluetoothConfig>::new(
+        tx_prod,
+        rx_cons,
+        L2CAPState::new(BleChannelMap::with_attributes(BluetoothAttributeProvider::new(device_name))),
+    );
+
+    (linklayer, responder, tx_cons, rx_prod)
+}
+
+fn advertise(
+    linklayer: &mut LinkLayer<BluetoothConfig>,
+    radio: &mut BleRadio,
+    device_name: &str,
+    tx_cons: SimpleConsumer<'static>,
+    rx_prod: SimpleProducer<'static>,
+) {
+    let next_update = linklayer
+        .start_advertise(
+            rubble::time::Duration::from_millis(100),
+            &[
+                AdStructure::CompleteLocalName(device_name),
+                AdStructure::Flags(
+                    Flags::from_bits(0b00000111).unwrap()
+                ),
+            ],
+            radio,
+            tx_cons,
+            rx_prod,
+        )
+        .unwrap();
+    linklayer.timer().configure_interrupt(next_update);
+}
@@ -0,0 +1,100 @@
+use nrf52832_hal::ecb::Ecb;
+use nrf52832_hal::rng::Rng;
+
+use rubble::link::{DeviceAddress, AddressKind};
+
+use crate::drivers::flash::{ExternalFlash, ExternalFlashError};
+use crate::drivers::flash::partition::{BLE_KEYS, PartitionError};
+
+const IRK_MAGIC: [u8; 4] = *b"IRK0";
+
+// Random part of prand (22 bits) can't be all zeros or all ones
+const PRAND_RANDOM_MASK: u32 = 0x3f_ffff;
+
+pub struct IdentityResolvingKey([u8; 16]);
+
+pub struct Privacy {
+    irk: IdentityResolvingKey,
+    ecb: Ecb,
+    rng: Rng,
+}
+
+impl IdentityResolvingKey {
+    // Read the IRK from external flash, generating (and storing) a new one
+    // on first boot
+    pub fn load_or_generate(
+        external_flash: &mut ExternalFlash,
+        rng: &mut Rng,
+    ) -> Result<Self, PartitionError<ExternalFlashError>> {
+        let mut stored = [0; 20];
+        BLE_KEYS.read(external_flash, 0, &mut stored)?;
+
+        let mut key = [0; 16];
+        if stored[0..4] == IRK_MAGIC {
+            key.copy_from_slice(&stored[4..20]);
+        } else {
+            key = Self::generate(rng).0;
+
+            stored[0..4].copy_from_slice(&IRK_MAGIC);
+            stored[4..20].copy_from_slice(&key);
+
+            BLE_KEYS.erase_all(external_flash)?;
+            BLE_KEYS.write(external_flash, 0, &stored)?;
+        }
+
+        Ok(IdentityResolvingKey(key))
+    }
+
+    // New random key, only used until the next boot if it isn't stored (like
+    // when the external flash doesn't work)
+    pub fn generate(rng: &mut Rng) -> Self {
+        let mut key = [0; 16];
+        rng.random(&mut key);
+        IdentityResolvingKey(key)
+    }
+
+    // Random address hash function ah (see Core spec, Vol 3, Part H, 2.2.2),
+    // prand and the result are most significant byte first
+    fn hash(&self, ecb: &mut Ecb, prand: [u8; 3]) -> [u8; 3] {
+        let mut plaintext = [0; 16];
+        plaintext[13..16].copy_from_slice(&prand);
+
+        let ciphertext = ecb.encrypt_block(plaintext, self.0).unwrap();
+
+        [ciphertext[13], ciphertext[14], ciphertext[15]]
+    }
+}
+
+impl Privacy {
+    pub fn new(irk: IdentityResolvingKey, ecb: Ecb, rng: Rng) -> Privacy {
+        Privacy {
+            irk,
+            ecb,
+            rng,
+        }
+    }
+
+    // Generate a new resolvable private address (prand || hash)
+    pub fn resolvable_private_address(&mut self) -> DeviceAddress {
+        let mut prand = [0; 3];
+        loop {
+            self.rng.random(&mut prand);
+            let random_part = u32::from_be_bytes([0, prand[0], prand[1], prand[2]])
+                & PRAND_RANDOM_MASK;
+            if random_part != 0 && random_part != PRAND_RANDOM_MASK {
+                break;
+            }
+        }
+
+        // The two most significant bits are 0b01 for resolvable addresses
+        prand[0] = (prand[0] & 0b0011_1111) | 0b0100_0000;
+
+        let hash = self.irk.hash(&mut self.ecb, prand);
+
+        // DeviceAddress is least significant byte first
+        DeviceAddress::new(
+            [hash[2], hash[1], hash[0], prand[2], prand[1], prand[0]],
+            AddressKind::Random,
+        )
+    }
+}
@@ -1,22 +1,54 @@
+mod retained;
+
 use nrf52832_hal::rtc::{self, Rtc};
 
 use chrono::{NaiveDateTime, Duration};
 
+use rtt_target::rprintln;
+
 pub struct Clock<RTC> {
     rtc: Rtc<RTC>,
     pub datetime: NaiveDateTime,
+    // Restored from a flash checkpoint (or never set), instead of set by the
+    // phone or kept across a soft reset
+    pub approximate: bool,
+    // Set since the last checkpoint, which then has to be saved even if it is
+    // earlier than the previous one
+    pub checkpoint_pending: bool,
     prev_counter: u32,
 }
 
 impl<RTC: rtc::Instance> Clock<RTC> {
-    pub fn new(rtc: Rtc<RTC>) -> Self {
+    // `checkpoint` is the last time saved to flash (seconds since the epoch),
+    // used when the time didn't survive the reset
+    pub fn new(rtc: Rtc<RTC>, checkpoint: i64) -> Self {
+        let (datetime, approximate) = match retained::load() {
+            Some((seconds, millis, approximate)) => {
+                rprintln!("Restored time from RAM");
+                (NaiveDateTime::from_timestamp(seconds, millis * 1_000_000), approximate)
+            },
+            None => {
+                rprintln!("Restored approximate time from flash");
+                (NaiveDateTime::from_timestamp(checkpoint, 0), true)
+            },
+        };
+
         Clock {
             rtc,
-            datetime: NaiveDateTime::from_timestamp(0, 0),
+            datetime,
+            approximate,
+            checkpoint_pending: false,
             prev_counter: 0,
         }
     }
 
+    pub fn set(&mut self, datetime: NaiveDateTime) {
+        self.datetime = datetime;
+        self.approximate = false;
+        self.checkpoint_pending = true;
+        self.retain();
+    }
+
     pub fn tick(&mut self) {
         let new_counter = self.rtc.get_counter();
 
@@ -24,5 +56,15 @@ impl<RTC: rtc::Instance> Clock<RTC> {
             ((new_counter - self.prev_counter) * 125).into()
         );
         self.prev_counter = new_counter;
+
+        self.retain();
+    }
+
+    fn retain(&self) {
+        retained::store(
+            self.datetime.timestamp(),
+            self.datetime.timestamp_subsec_millis(),
+            self.approximate,
+        );
     }
 }
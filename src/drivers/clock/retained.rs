@@ -0,0 +1,65 @@
+use crate::drivers::flash::crc32;
+
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+
+// Time kept in RAM that isn't initialized on boot (see .noinit in memory.x),
+// so it survives soft resets. After a power loss it contains garbage, which
+// the magic and checksum catch.
+
+const MAGIC: u32 = 0x5449_4d45; // "TIME"
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RetainedTime {
+    magic: u32,
+    millis: u32,
+    seconds: i64,
+    approximate: u32,
+    checksum: u32,
+}
+
+#[link_section = ".noinit.clock"]
+static mut RETAINED_TIME: MaybeUninit<RetainedTime> = MaybeUninit::uninit();
+
+impl RetainedTime {
+    fn checksum(&self) -> u32 {
+        let mut data = [0; 20];
+        data[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        data[4..8].copy_from_slice(&self.millis.to_le_bytes());
+        data[8..16].copy_from_slice(&self.seconds.to_le_bytes());
+        data[16..20].copy_from_slice(&self.approximate.to_le_bytes());
+        crc32(&data)
+    }
+}
+
+pub fn store(seconds: i64, millis: u32, approximate: bool) {
+    let mut retained = RetainedTime {
+        magic: MAGIC,
+        millis,
+        seconds,
+        approximate: approximate as u32,
+        checksum: 0,
+    };
+    retained.checksum = retained.checksum();
+
+    unsafe {
+        addr_of_mut!(RETAINED_TIME).write_volatile(MaybeUninit::new(retained));
+    }
+}
+
+// Returns the seconds and milliseconds since the epoch and whether that time
+// was approximate, if they survived
+pub fn load() -> Option<(i64, u32, bool)> {
+    // Every bit pattern is a valid RetainedTime, so reading uninitialized
+    // memory is fine here
+    let retained = unsafe {
+        addr_of!(RETAINED_TIME).read_volatile().assume_init()
+    };
+
+    if retained.magic != MAGIC || retained.checksum != retained.checksum() {
+        return None;
+    }
+
+    Some((retained.seconds, retained.millis, retained.approximate != 0))
+}
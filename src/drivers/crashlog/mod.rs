@@ -0,0 +1,355 @@
+use crate::drivers::flash::{crc32, ExternalFlash, ExternalFlashError};
+use crate::drivers::filesystem::{Filesystem, FilesystemError, SeekFrom};
+
+use core::fmt::{self, Write};
+use core::mem::{MaybeUninit, size_of};
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::alloc::Layout;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::format;
+
+// Crash log
+//
+// A small ring of crash records (panics, HardFaults, OOM and unexpected
+// resets) in RAM that isn't initialized on boot (see .noinit in memory.x), so
+// it survives the reset after a crash. Writing to flash from a fault handler
+// isn't safe, so the log is lost on power loss. A magic and checksum over the
+// whole log detect garbage after power-on. Once the filesystem is mounted,
+// new records are appended to CRASH_FILE so they survive power loss too.
+//
+// Crashes before the boot is marked stable are counted, after
+// SAFE_MODE_CRASHES of them in a row the watch boots in safe mode. The
+// watchdog can't be stopped, so just not rebooting isn't an option.
+
+const MAGIC: u32 = 0x4352_5348; // "CRSH"
+const RECORD_COUNT: usize = 6;
+const MESSAGE_LEN: usize = 64;
+
+// Crashes in a row (without a stable boot in between) before safe mode
+const SAFE_MODE_CRASHES: u32 = 3;
+
+const CRASH_FILE: &str = "crashes.txt";
+// Start over when the file gets bigger than this
+const CRASH_FILE_MAX_SIZE: u32 = 16 * 1024;
+
+// RESETREAS bits (see nRF52832 product specification, 18.8.3)
+const RESETREAS_RESETPIN: u32 = 1 << 0;
+const RESETREAS_DOG: u32 = 1 << 1;
+const RESETREAS_SREQ: u32 = 1 << 2;
+const RESETREAS_LOCKUP: u32 = 1 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrashKind {
+    Panic,
+    HardFault,
+    OutOfMemory,
+    Reset,
+}
+
+impl CrashKind {
+    fn to_u32(self) -> u32 {
+        match self {
+            CrashKind::Panic => 1,
+            CrashKind::HardFault => 2,
+            CrashKind::OutOfMemory => 3,
+            CrashKind::Reset => 4,
+        }
+    }
+
+    fn from_u32(value: u32) -> Option<CrashKind> {
+        match value {
+            1 => Some(CrashKind::Panic),
+            2 => Some(CrashKind::HardFault),
+            3 => Some(CrashKind::OutOfMemory),
+            4 => Some(CrashKind::Reset),
+            _ => None,
+        }
+    }
+}
+
+// Only u32s and u8 arrays of a multiple of 4, so there is no padding
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    kind: u32,
+    pub boot: u32,
+    // HardFault: r0, r1, r2, r3, r12, lr, pc, xpsr, CFSR, HFSR
+    // OutOfMemory: size, align
+    // Reset: RESETREAS
+    pub data: [u32; 10],
+    message_len: u32,
+    message: [u8; MESSAGE_LEN],
+}
+
+#[repr(C)]
+struct CrashLog {
+    magic: u32,
+    boot: u32,
+    next: u32,      // Index of the next record to write
+    count: u32,
+    unsaved: u32,   // Newest records that aren't in CRASH_FILE yet
+    consecutive: u32,   // Crashes since the last stable boot
+    records: [CrashRecord; RECORD_COUNT],
+    checksum: u32,  // Has to be the last field
+}
+
+#[link_section = ".noinit.crashlog"]
+static mut CRASH_LOG: MaybeUninit<CrashLog> = MaybeUninit::uninit();
+
+// Decided once in init, so it doesn't change when the boot is marked stable
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+// Writes as much as fits, the rest is cut off
+struct MessageWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for MessageWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+impl CrashRecord {
+    pub fn kind(&self) -> Option<CrashKind> {
+        CrashKind::from_u32(self.kind)
+    }
+
+    pub fn message(&self) -> &str {
+        let message = &self.message[..(self.message_len as usize).min(MESSAGE_LEN)];
+
+        // The message may have been cut off in the middle of a character
+        match core::str::from_utf8(message) {
+            Ok(message) => message,
+            Err(e) => core::str::from_utf8(&message[..e.valid_up_to()]).unwrap(),
+        }
+    }
+
+    // Short description, for the diagnostics screen
+    pub fn summary(&self) -> String {
+        match self.kind() {
+            Some(CrashKind::Panic) => format!("#{} panic", self.boot),
+            Some(CrashKind::HardFault) => format!("#{} fault {:08x}", self.boot, self.data[6]),
+            Some(CrashKind::OutOfMemory) => format!("#{} OOM {}B", self.boot, self.data[0]),
+            Some(CrashKind::Reset) => format!("#{} {}", self.boot, reset_reason(self.data[0])),
+            None => format!("#{} ?", self.boot),
+        }
+    }
+}
+
+pub fn reset_reason(resetreas: u32) -> &'static str {
+    if resetreas & RESETREAS_DOG != 0 {
+        "watchdog"
+    } else if resetreas & RESETREAS_LOCKUP != 0 {
+        "lockup"
+    } else if resetreas & RESETREAS_SREQ != 0 {
+        "soft reset"
+    } else if resetreas & RESETREAS_RESETPIN != 0 {
+        "reset pin"
+    } else if resetreas == 0 {
+        "power on"
+    } else {
+        "wakeup"
+    }
+}
+
+impl CrashLog {
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self as *const CrashLog as *const u8,
+                size_of::<CrashLog>() - size_of::<u32>(),
+            )
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC
+            && (self.next as usize) < RECORD_COUNT
+            && (self.count as usize) <= RECORD_COUNT
+            && self.unsaved <= self.count
+            && self.checksum == crc32(self.bytes())
+    }
+
+    fn update_checksum(&mut self) {
+        self.checksum = crc32(self.bytes());
+    }
+
+    fn push(&mut self, record: CrashRecord) {
+        self.records[self.next as usize] = record;
+        self.next = (self.next + 1) % RECORD_COUNT as u32;
+        self.count = (self.count + 1).min(RECORD_COUNT as u32);
+        self.unsaved = (self.unsaved + 1).min(RECORD_COUNT as u32);
+        self.consecutive = self.consecutive.saturating_add(1);
+        self.update_checksum();
+    }
+}
+
+fn with_log<R>(f: impl FnOnce(&mut CrashLog) -> R) -> R {
+    cortex_m::interrupt::free(|_| {
+        // Every bit pattern is a valid CrashLog, validity is checked in init
+        let log = unsafe { &mut *(*addr_of_mut!(CRASH_LOG)).as_mut_ptr() };
+        f(log)
+    })
+}
+
+fn new_record(kind: CrashKind, boot: u32) -> CrashRecord {
+    CrashRecord {
+        kind: kind.to_u32(),
+        boot,
+        data: [0; 10],
+        message_len: 0,
+        message: [0; MESSAGE_LEN],
+    }
+}
+
+// Call once on boot, before anything can crash. `resetreas` is the (already
+// cleared) RESETREAS register, which is logged if the reset was unexpected.
+pub fn init(resetreas: u32) {
+    with_log(|log| {
+        if !log.is_valid() {
+            log.magic = MAGIC;
+            log.boot = 0;
+            log.next = 0;
+            log.count = 0;
+            log.unsaved = 0;
+            log.consecutive = 0;
+        }
+        log.boot = log.boot.wrapping_add(1);
+        log.update_checksum();
+
+        // Soft resets are either the reboot button or a crash that was
+        // already logged
+        if resetreas & (RESETREAS_DOG | RESETREAS_LOCKUP) != 0 {
+            let mut record = new_record(CrashKind::Reset, log.boot);
+            record.data[0] = resetreas;
+            log.push(record);
+        }
+
+        SAFE_MODE.store(log.consecutive >= SAFE_MODE_CRASHES, Ordering::Relaxed);
+    });
+}
+
+// Skip everything that isn't needed to read the crash log and install a
+// fixed update, because the last boots crashed
+pub fn safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
+
+// Call once the watch has been running for a while, so crashes after this
+// aren't counted towards safe mode anymore
+pub fn mark_stable() {
+    with_log(|log| {
+        log.consecutive = 0;
+        log.update_checksum();
+    });
+}
+
+pub fn record_panic(info: &PanicInfo) {
+    with_log(|log| {
+        let mut record = new_record(CrashKind::Panic, log.boot);
+
+        let mut writer = MessageWriter {
+            buffer: &mut record.message,
+            len: 0,
+        };
+        write!(writer, "{}", info).ok();
+        record.message_len = writer.len as u32;
+
+        log.push(record);
+    });
+}
+
+// `registers` are r0, r1, r2, r3, r12, lr, pc and xpsr from the exception frame
+pub fn record_hard_fault(registers: [u32; 8], cfsr: u32, hfsr: u32) {
+    with_log(|log| {
+        let mut record = new_record(CrashKind::HardFault, log.boot);
+        record.data[..8].copy_from_slice(&registers);
+        record.data[8] = cfsr;
+        record.data[9] = hfsr;
+
+        log.push(record);
+    });
+}
+
+pub fn record_oom(layout: Layout) {
+    with_log(|log| {
+        let mut record = new_record(CrashKind::OutOfMemory, log.boot);
+        record.data[0] = layout.size() as u32;
+        record.data[1] = layout.align() as u32;
+
+        log.push(record);
+    });
+}
+
+// All records, newest first
+pub fn records() -> Vec<CrashRecord> {
+    with_log(|log| {
+        (0..log.count as usize)
+            .map(|i| log.records[(log.next as usize + RECORD_COUNT - 1 - i) % RECORD_COUNT])
+            .collect()
+    })
+}
+
+// Newest record for the BLE crash log characteristic: kind (u8), boot
+// (u32 LE) and 3 data words (u32 LE), all zeros if there is none. For
+// HardFaults the words are pc, lr and CFSR, for the others the start of data.
+pub fn latest_summary() -> Vec<u8> {
+    let mut summary = alloc::vec![0; 17];
+
+    if let Some(record) = records().first() {
+        let words = match record.kind() {
+            Some(CrashKind::HardFault) => [record.data[6], record.data[5], record.data[8]],
+            _ => [record.data[0], record.data[1], record.data[2]],
+        };
+
+        summary[0] = record.kind as u8;
+        summary[1..5].copy_from_slice(&record.boot.to_le_bytes());
+        for (i, word) in words.iter().enumerate() {
+            summary[5 + 4 * i..9 + 4 * i].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    summary
+}
+
+// Append the records that aren't in CRASH_FILE yet, oldest first
+pub fn save(
+    filesystem: &mut Filesystem<ExternalFlash>,
+    external_flash: &mut ExternalFlash,
+) -> Result<(), FilesystemError<ExternalFlashError>> {
+    let unsaved = with_log(|log| log.unsaved as usize);
+    if unsaved == 0 {
+        return Ok(());
+    }
+
+    let mut file = match filesystem.open(CRASH_FILE) {
+        Ok(file) if filesystem.size(&file)? < CRASH_FILE_MAX_SIZE => file,
+        _ => filesystem.create(external_flash, CRASH_FILE)?,
+    };
+    filesystem.seek(&mut file, SeekFrom::End(0))?;
+
+    for record in records().iter().take(unsaved).rev() {
+        let line = match record.message() {
+            "" => format!("{}\n", record.summary()),
+            message => format!("{}: {}\n", record.summary(), message),
+        };
+        filesystem.write(external_flash, &mut file, line.as_bytes())?;
+    }
+
+    // Nothing can be logged in between, crashes reset the watch
+    with_log(|log| {
+        log.unsaved = 0;
+        log.update_checksum();
+    });
+
+    Ok(())
+}
@@ -134,7 +134,8 @@ where
         self.send(DisplayCommand::NormalModeOn);
     }
 
-    fn set_brightness(&mut self, value: u8) {
+    // 0 (off) to 7
+    pub fn set_brightness(&mut self, value: u8) {
         if (value & 0b001) != 0 {
             self.pin_backlight_low.set_low().unwrap();
         } else {
@@ -189,7 +190,7 @@ where
         self.send(DisplayCommand::RowAddressSet(start_row, end_row));
     }
 
-    pub fn init(&mut self) {
+    pub fn init(&mut self, brightness: u8) {
         self.pin_reset.set_high().unwrap();
         
         self.hard_reset();
@@ -210,7 +211,7 @@ where
 
         self.set_display_on(true);
 
-        self.set_brightness(0x7);
+        self.set_brightness(brightness);
 
     }
 
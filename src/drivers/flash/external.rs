@@ -1,42 +1,129 @@
-use nrf52832_hal::spim::Spim;
+use nrf52832_hal::prelude::OutputPin;
+use nrf52832_hal::prelude::_embedded_hal_blocking_spi_Write as Write;
+use nrf52832_hal::prelude::_embedded_hal_blocking_spi_Transfer as Transfer;
+use nrf52832_hal::spim::{self, Spim};
 use nrf52832_hal::pac::SPIM0;
 use nrf52832_hal::gpio::{Pin, Output, PushPull};
 
-use alloc::string::String;
-use alloc::vec::Vec;
-use alloc::{vec, format};
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash, NorFlash,
+    MultiwriteNorFlash, check_read, check_write, check_erase,
+};
 
-use rtt_target::rprintln;
+use alloc::vec;
+
+use super::partition::{BOOTLOADER_ASSETS, STANDBY_IMAGE, RESERVED};
+use pinetime_common::flash::sfdp::{self, FlashGeometry};
 
 use spin::Mutex;
 
+// Erase granularity, the partitions and NorFlash::ERASE_SIZE depend on it, so
+// the smallest erase of the flash has to be this. The capacity and page size
+// come from the FlashGeometry.
+const SECTOR_SIZE: u32 = 0x1000;
+
+// Compare chunk size in write_buffered
+const COMPARE_SIZE: usize = 0x100;
+
+// Maximum length of a single SPIM transfer on the nRF52832 (MAXCNT is 8 bits)
+const EASY_DMA_SIZE: usize = 255;
+
+// Opcode + 3 address bytes + dummy byte
+const MAX_HEADER_SIZE: usize = 5;
+
+// Block protect bits (BP4..BP0), SEC = 0 (blocks), TB = 1 (from the bottom):
+// BP = 011 protects the lowest 256K, which is BOOTLOADER_ASSETS, and BP = 101
+// the lowest 1MB, which adds STANDBY_IMAGE and RESERVED
+const PROTECT_BOTTOM_256K: u8 = 0b01011;
+const PROTECT_BOTTOM_1M: u8 = 0b01101;
+
+// Time after Release from Deep Power-Down before the flash accepts commands
+// (tRES1 is 20µs max, at 64MHz)
+const WAKE_UP_CYCLES: u32 = 20 * 64;
+
+// Chip select pins on port 0, for wake_up_from_fault
+const PIN_FLASH_CS: u32 = 5;
+const PIN_DISPLAY_CS: u32 = 25;
+
+// Polls before giving up on SPIM0 in wake_up_from_fault, a 1 byte transfer
+// at 8MHz takes far less
+const FAULT_SPI_TIMEOUT: u32 = 10_000;
+
 pub struct ExternalFlash {
     // Spi can be 'static because it is accessible as long as the device is
     // powered on.
     spi: &'static Mutex<Option<Spim<SPIM0>>>,
 
     pin_chip_select: Pin<Output<PushPull>>,
+
+    // In Deep Power-Down, only Release from Deep Power-Down is accepted
+    powered_down: bool,
+    // Used since the last power_down_if_idle
+    accessed: bool,
+    // Only unlocked while an OTA update is being written
+    standby_image_locked: bool,
+
+    geometry: FlashGeometry,
+}
+
+#[derive(Debug)]
+pub enum ExternalFlashError {
+    Spi(spim::Error),       // The SPI transfer itself failed
+    SpiUnavailable,         // SPI is in use (probably by the display)
+    OutOfBounds,
+    NotAligned,
+    Protected,              // Write/erase to a protected range, or protecting failed
+    UnsupportedGeometry,    // The smallest erase isn't 4K
+}
+
+impl NorFlashError for ExternalFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            ExternalFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            ExternalFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for ExternalFlashError {
+    fn from(kind: NorFlashErrorKind) -> ExternalFlashError {
+        match kind {
+            NorFlashErrorKind::NotAligned => ExternalFlashError::NotAligned,
+            _ => ExternalFlashError::OutOfBounds,
+        }
+    }
+}
+
+impl From<spim::Error> for ExternalFlashError {
+    fn from(error: spim::Error) -> ExternalFlashError {
+        ExternalFlashError::Spi(error)
+    }
 }
 
 enum FlashCommand {
     WriteEnable,
     WriteDisable,
-    Read(u32),
-    Write(u32, Vec<u8>),
+    FastRead(u32),
+    ReadSfdp(u32),
+    Write(u32),
     ReadIdentification,
     ReadStatusRegister0,
     ReadStatusRegister1,
     ChipErase,
     ResetEnable,
     Reset,
-    SectorErase(u32),
+    Erase(u8, u32),         // Opcode from the FlashGeometry
+    DeepPowerDown,
+    ReleaseDeepPowerDown,
+    WriteStatusRegister,
 }
 
 #[derive(Debug)]
 pub struct FlashIdentification {
     pub manufacturer: u8,   // = 0x0b (XTX)
-    pub memory_type: u8,    // = 0x15 (?)
-    pub capacity: u8,       // = 0x40 (4MB?)
+    pub memory_type: u8,    // = 0x40
+    pub capacity: u8,       // = 0x16 (2^22 bytes = 4MiB)
 }
 
 #[derive(Debug)]
@@ -61,6 +148,17 @@ pub enum FlashStatusRegisterProtection {
     OneTimeProgram,         // Status Register is permanently protected
 }
 
+impl From<&FlashStatusRegisterProtection> for u16 {
+    fn from(protection: &FlashStatusRegisterProtection) -> u16 {
+        match protection {
+            FlashStatusRegisterProtection::SoftwareProtected => 0b00,
+            FlashStatusRegisterProtection::HardwareProtected => 0b01,
+            FlashStatusRegisterProtection::PowerSupplyLockDown => 0b10,
+            FlashStatusRegisterProtection::OneTimeProgram => 0b11,
+        }
+    }
+}
+
 impl From<u16> for FlashStatusRegisterProtection {
     fn from(value: u16) -> FlashStatusRegisterProtection {
         match value {
@@ -73,39 +171,51 @@ impl From<u16> for FlashStatusRegisterProtection {
     }
 }
 
-impl From<FlashCommand> for Vec<u8> {
-    fn from(command: FlashCommand) -> Vec<u8> {
-        match command {
-            FlashCommand::WriteEnable => vec![0x06],
-            FlashCommand::WriteDisable => vec![0x04],
-            FlashCommand::Read(a) => vec![
-                0x03,
-                ((a >> 16) & 0xff).try_into().unwrap(),
-                ((a >>  8) & 0xff).try_into().unwrap(),
-                ((a      ) & 0xff).try_into().unwrap(),
-            ],
-            FlashCommand::Write(a, v) => {
-                let mut ret = vec![
-                    0x02,
-                    ((a >> 16) & 0xff).try_into().unwrap(),
-                    ((a >>  8) & 0xff).try_into().unwrap(),
-                    ((a      ) & 0xff).try_into().unwrap(),
-                ];
-                ret.extend(v);
-                ret
-            },
-            FlashCommand::ReadIdentification => vec![0x9f],
-            FlashCommand::ReadStatusRegister0 => vec![0x05],
-            FlashCommand::ReadStatusRegister1 => vec![0x35],
-            FlashCommand::ChipErase => vec![0xc7],
-            FlashCommand::ResetEnable => vec![0x66],
-            FlashCommand::Reset => vec![0x99],
-            FlashCommand::SectorErase(a) => vec![
-                0x20,
-                ((a >> 16) & 0xff).try_into().unwrap(),
-                ((a >>  8) & 0xff).try_into().unwrap(),
-                ((a      ) & 0xff).try_into().unwrap(),
-            ],}
+// Value for Write Status Register, WIP and WEL are read-only
+impl From<&FlashStatusRegisters> for u16 {
+    fn from(registers: &FlashStatusRegisters) -> u16 {
+        ((registers.block_protect_bits as u16) << 2)
+            | (u16::from(&registers.status_register_protect) << 7)
+            | ((registers.quad_enabled as u16) << 9)
+            | ((registers.one_time_program as u16) << 10)
+            | ((registers.cmp as u16) << 14)
+    }
+}
+
+// Opcode followed by a 24-bit address
+fn with_address(opcode: u8, address: u32) -> [u8; MAX_HEADER_SIZE] {
+    [
+        opcode,
+        ((address >> 16) & 0xff).try_into().unwrap(),
+        ((address >>  8) & 0xff).try_into().unwrap(),
+        ((address      ) & 0xff).try_into().unwrap(),
+        0,
+    ]
+}
+
+impl FlashCommand {
+    // The bytes to send before any data, and how many of them are used
+    fn header(&self) -> ([u8; MAX_HEADER_SIZE], usize) {
+        match self {
+            FlashCommand::WriteEnable => ([0x06, 0, 0, 0, 0], 1),
+            FlashCommand::WriteDisable => ([0x04, 0, 0, 0, 0], 1),
+            // Followed by a dummy byte, but it can run at the full SPI clock
+            FlashCommand::FastRead(a) => (with_address(0x0b, *a), 5),
+            // Also followed by a dummy byte
+            FlashCommand::ReadSfdp(a) => (with_address(0x5a, *a), 5),
+            FlashCommand::Write(a) => (with_address(0x02, *a), 4),
+            FlashCommand::ReadIdentification => ([0x9f, 0, 0, 0, 0], 1),
+            FlashCommand::ReadStatusRegister0 => ([0x05, 0, 0, 0, 0], 1),
+            FlashCommand::ReadStatusRegister1 => ([0x35, 0, 0, 0, 0], 1),
+            FlashCommand::ChipErase => ([0xc7, 0, 0, 0, 0], 1),
+            FlashCommand::ResetEnable => ([0x66, 0, 0, 0, 0], 1),
+            FlashCommand::Reset => ([0x99, 0, 0, 0, 0], 1),
+            FlashCommand::Erase(opcode, a) => (with_address(*opcode, *a), 4),
+            FlashCommand::DeepPowerDown => ([0xb9, 0, 0, 0, 0], 1),
+            FlashCommand::ReleaseDeepPowerDown => ([0xab, 0, 0, 0, 0], 1),
+            // Followed by status register 0 and 1
+            FlashCommand::WriteStatusRegister => ([0x01, 0, 0, 0, 0], 1),
+        }
     }
 }
 
@@ -116,63 +226,235 @@ impl ExternalFlash {
     ) -> ExternalFlash {
         ExternalFlash {
             spi,
-            pin_chip_select
+            pin_chip_select,
+            // We don't know the state after a soft reset, releasing is
+            // harmless if it wasn't powered down
+            powered_down: true,
+            accessed: false,
+            standby_image_locked: true,
+            geometry: FlashGeometry::default(),
+        }
+    }
+
+    pub fn geometry(&self) -> FlashGeometry {
+        self.geometry
+    }
+
+    // Use the capacity, page size and erase types from the SFDP tables, the
+    // defaults (XT25F32B) are kept if there are none
+    pub fn detect_geometry(&mut self) -> Result<FlashGeometry, ExternalFlashError> {
+        let geometry = sfdp::parse(|address, buffer| {
+            self.transfer(FlashCommand::ReadSfdp(address), buffer)
+        })?;
+
+        if let Some(geometry) = geometry {
+            match geometry.smallest_erase() {
+                Some(erase) if erase.size == SECTOR_SIZE => self.geometry = geometry,
+                _ => return Err(ExternalFlashError::UnsupportedGeometry),
+            }
+        }
+
+        Ok(self.geometry)
+    }
+
+    // Protect BOOTLOADER_ASSETS, and STANDBY_IMAGE if `standby_image` is
+    // set, in hardware, on top of the checks in software. Call once at boot.
+    // MCUBoot writes STANDBY_IMAGE when it swaps or reverts, so it can't be
+    // protected in hardware while one is pending.
+    pub fn protect(&mut self, standby_image: bool) -> Result<(), ExternalFlashError> {
+        self.standby_image_locked = true;
+        match standby_image {
+            true => self.set_block_protection(PROTECT_BOTTOM_1M),
+            false => self.set_block_protection(PROTECT_BOTTOM_256K),
         }
     }
 
-    fn transfer(&mut self, command: FlashCommand, rx_size: u32) -> Vec<u8> {
-        // Using try_lock instead of lock() to avoid deadlocks
+    // Set the block protect bits (with CMP cleared). They are non-volatile, so
+    // the status register is only written when they change.
+    fn set_block_protection(&mut self, bits: u8) -> Result<(), ExternalFlashError> {
+        let mut registers = self.read_status_registers()?;
+
+        if registers.block_protect_bits == bits && !registers.cmp {
+            return Ok(());
+        }
+
+        match registers.status_register_protect {
+            FlashStatusRegisterProtection::SoftwareProtected |
+            FlashStatusRegisterProtection::HardwareProtected => {},
+            _ => return Err(ExternalFlashError::Protected),
+        }
+
+        registers.block_protect_bits = bits;
+        registers.cmp = false;
+        self.write_status_registers(&registers)?;
+
+        // Writes are ignored when WP# is low and SRP is HardwareProtected
+        let registers = self.read_status_registers()?;
+        if registers.block_protect_bits != bits || registers.cmp {
+            return Err(ExternalFlashError::Protected);
+        }
+
+        Ok(())
+    }
+
+    pub fn write_status_registers(&mut self, registers: &FlashStatusRegisters) -> Result<(), ExternalFlashError> {
+        let value = u16::from(registers);
+
+        self.set_write_enable(true)?;
+        self.send_data(FlashCommand::WriteStatusRegister, &value.to_le_bytes())?;
+
+        // Write Enable gets reset automatically
+        self.wait_while_busy()
+    }
+
+    // Allow writing STANDBY_IMAGE, for OTA updates. Lock it again when done.
+    pub fn unlock_standby_image(&mut self) -> Result<(), ExternalFlashError> {
+        self.set_block_protection(PROTECT_BOTTOM_256K)?;
+        self.standby_image_locked = false;
+
+        Ok(())
+    }
 
-        // If this panics, you probably used both flash and display
-        // at the same time
-        let mut spi_lock = self.spi.try_lock().unwrap();
-        let spi = (*spi_lock).as_mut().unwrap();
+    // Only in software: MCUBoot still has to write STANDBY_IMAGE for the
+    // swap, protect() locks it in hardware again once that is done
+    pub fn lock_standby_image(&mut self) {
+        self.standby_image_locked = true;
+    }
 
-        let tx_buffer: Vec<u8> = command.into();
-        let mut rx_buffer = vec![0; tx_buffer.len() + (rx_size as usize)];
+    // Reject writes/erases to [from, to) if it overlaps a protected range,
+    // the flash would silently ignore them
+    fn check_protected(&self, from: u32, to: u32) -> Result<(), ExternalFlashError> {
+        let standby_locked = self.standby_image_locked
+            && (STANDBY_IMAGE.overlaps(from, to) || RESERVED.overlaps(from, to));
 
-        spi.transfer_split_uneven(
-            &mut self.pin_chip_select,
-            &tx_buffer,
-            &mut rx_buffer,
-        ).unwrap();
+        if BOOTLOADER_ASSETS.overlaps(from, to) || standby_locked {
+            return Err(ExternalFlashError::Protected);
+        }
 
-        rx_buffer.split_off(tx_buffer.len())
+        Ok(())
     }
 
-    fn send(&mut self, command: FlashCommand) {
-        // Using try_lock instead of lock() to avoid deadlocks
+    // A reset doesn't wake the flash from Deep Power-Down, and the
+    // bootloader can't read the standby image if it's asleep. Call this
+    // right before resetting, it also waits for a pending write.
+    pub fn prepare_for_reset(&mut self) -> Result<(), ExternalFlashError> {
+        // Every access wakes the flash up first
+        self.wait_while_busy()
+    }
 
-        // If this panics, you probably used both flash and display
-        // at the same time
-        let mut spi_lock = self.spi.try_lock().unwrap();
-        let spi = (*spi_lock).as_mut().unwrap();
+    // Enter Deep Power-Down if the flash wasn't used since the last call,
+    // the next access wakes it up again
+    pub fn power_down_if_idle(&mut self) -> Result<(), ExternalFlashError> {
+        if self.accessed || self.powered_down {
+            self.accessed = false;
+            return Ok(());
+        }
 
-        let tx_buffer: Vec<u8> = command.into();
+        self.send(FlashCommand::DeepPowerDown)?;
+        self.powered_down = true;
+        self.accessed = false;
 
-        spi.write(
-            &mut self.pin_chip_select,
-            &tx_buffer,
-        ).unwrap();
+        Ok(())
     }
 
-    fn set_write_enable(&mut self, value: bool) {
-        while self.read_status_registers().write_enable != value {
+    // Run `f` with the SPI locked and chip select low. Nothing here
+    // allocates, so large reads don't fragment the heap.
+    fn with_spi<F>(&mut self, f: F) -> Result<(), ExternalFlashError>
+    where
+        F: FnOnce(&mut Spim<SPIM0>) -> Result<(), spim::Error>
+    {
+        // Using try_lock instead of lock() to avoid deadlocks, if this fails
+        // you probably used both flash and display at the same time
+        let mut spi_lock = self.spi.try_lock()
+            .ok_or(ExternalFlashError::SpiUnavailable)?;
+        let spi = (*spi_lock).as_mut()
+            .ok_or(ExternalFlashError::SpiUnavailable)?;
+
+        if self.powered_down {
+            let (header, header_len) = FlashCommand::ReleaseDeepPowerDown.header();
+
+            self.pin_chip_select.set_low().unwrap();
+            let result = <Spim<SPIM0> as Write<u8>>::write(spi, &header[..header_len]);
+            self.pin_chip_select.set_high().unwrap();
+            result?;
+
+            cortex_m::asm::delay(WAKE_UP_CYCLES);
+            self.powered_down = false;
+        }
+        self.accessed = true;
+
+        self.pin_chip_select.set_low().unwrap();
+        let result = f(spi);
+        self.pin_chip_select.set_high().unwrap();
+
+        Ok(result?)
+    }
+
+    // Send `command`, then read `rx.len()` bytes into `rx`
+    fn transfer(&mut self, command: FlashCommand, rx: &mut [u8]) -> Result<(), ExternalFlashError> {
+        let (header, header_len) = command.header();
+
+        self.with_spi(|spi| {
+            <Spim<SPIM0> as Write<u8>>::write(spi, &header[..header_len])?;
+
+            for chunk in rx.chunks_mut(EASY_DMA_SIZE) {
+                chunk.fill(0);
+                <Spim<SPIM0> as Transfer<u8>>::transfer(spi, chunk)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    // Send `command`, followed by `data`
+    fn send_data(&mut self, command: FlashCommand, data: &[u8]) -> Result<(), ExternalFlashError> {
+        let (header, header_len) = command.header();
+
+        self.with_spi(|spi| {
+            <Spim<SPIM0> as Write<u8>>::write(spi, &header[..header_len])?;
+
+            // EasyDMA can only read from RAM, and `data` might be in flash
+            let mut buffer = [0; EASY_DMA_SIZE];
+            for chunk in data.chunks(EASY_DMA_SIZE) {
+                buffer[..chunk.len()].copy_from_slice(chunk);
+                <Spim<SPIM0> as Write<u8>>::write(spi, &buffer[..chunk.len()])?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn send(&mut self, command: FlashCommand) -> Result<(), ExternalFlashError> {
+        self.send_data(command, &[])
+    }
+
+    fn set_write_enable(&mut self, value: bool) -> Result<(), ExternalFlashError> {
+        while self.read_status_registers()?.write_enable != value {
             if value {
-                self.send(FlashCommand::WriteEnable);
+                self.send(FlashCommand::WriteEnable)?;
             } else {
-                self.send(FlashCommand::WriteDisable);
+                self.send(FlashCommand::WriteDisable)?;
             }
         }
+
+        Ok(())
+    }
+
+    fn wait_while_busy(&mut self) -> Result<(), ExternalFlashError> {
+        while self.read_status_registers()?.write_in_progress {}
+
+        Ok(())
     }
 
-    pub fn read_status_registers(&mut self) -> FlashStatusRegisters {
-        let buffer0 = self.transfer(FlashCommand::ReadStatusRegister0, 1);
-        let buffer1 = self.transfer(FlashCommand::ReadStatusRegister1, 1);
+    pub fn read_status_registers(&mut self) -> Result<FlashStatusRegisters, ExternalFlashError> {
+        let mut buffer0 = [0; 1];
+        let mut buffer1 = [0; 1];
+        self.transfer(FlashCommand::ReadStatusRegister0, &mut buffer0)?;
+        self.transfer(FlashCommand::ReadStatusRegister1, &mut buffer1)?;
 
         let value: u16 = ((buffer1[0] as u16) << 8) | (buffer0[0] as u16);
 
-        FlashStatusRegisters{
+        Ok(FlashStatusRegisters{
             write_in_progress:       ((value      ) & 0b00001) == 1,
             write_enable:            ((value >>  1) & 0b00001) == 1,
             block_protect_bits:      ((value >>  2) & 0b11111).try_into().unwrap(),
@@ -180,101 +462,257 @@ impl ExternalFlash {
             quad_enabled:            ((value >>  9) & 0b00001) == 1,
             one_time_program:        ((value >> 10) & 0b00001) == 1,
             cmp:                     ((value >> 14) & 0b00001) == 1,
-        }
+        })
     }
 
-    pub fn full_reset(&mut self) {
-        self.send(FlashCommand::ResetEnable);
-        self.send(FlashCommand::Reset);
+    pub fn full_reset(&mut self) -> Result<(), ExternalFlashError> {
+        self.send(FlashCommand::ResetEnable)?;
+        self.send(FlashCommand::Reset)
     }
 
-    pub fn read_identification(&mut self) -> FlashIdentification {
-        let buffer = self.transfer(FlashCommand::ReadIdentification, 3);
+    pub fn read_identification(&mut self) -> Result<FlashIdentification, ExternalFlashError> {
+        let mut buffer = [0; 3];
+        self.transfer(FlashCommand::ReadIdentification, &mut buffer)?;
 
-        FlashIdentification {
+        Ok(FlashIdentification {
             manufacturer: buffer[0],
             memory_type: buffer[1],
             capacity: buffer[2],
-        }
+        })
     }
 
-    pub fn chip_erase(&mut self) {
-        self.set_write_enable(true);
+    // Erase everything, including BOOTLOADER_ASSETS and STANDBY_IMAGE, so
+    // only for recovering the flash from a debugger. The block protect bits
+    // are cleared for the erase and restored afterwards.
+    pub fn chip_erase(&mut self) -> Result<(), ExternalFlashError> {
+        let block_protect_bits = self.read_status_registers()?.block_protect_bits;
+        self.set_block_protection(0)?;
 
-        self.send(FlashCommand::ChipErase);
+        let result = self.set_write_enable(true)
+            .and_then(|_| self.send(FlashCommand::ChipErase))
+            // Write Enable gets reset automatically
+            .and_then(|_| self.wait_while_busy());
 
-        while self.read_status_registers().write_in_progress {}
+        self.set_block_protection(block_protect_bits)?;
 
-        // Write Enable gets reset automatically
+        result
     }
 
     // Erase the sector (4096 bytes) `address` is in
-    pub fn erase_sector(&mut self, address: u32) {
-        self.set_write_enable(true);
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), ExternalFlashError> {
+        let sector_start = address & !(SECTOR_SIZE - 1);
+        let opcode = self.geometry.smallest_erase()
+            .ok_or(ExternalFlashError::UnsupportedGeometry)?
+            .opcode;
+
+        self.erase_block(opcode, sector_start, SECTOR_SIZE)
+    }
 
-        self.send(FlashCommand::SectorErase(address));
+    fn erase_block(&mut self, opcode: u8, start: u32, size: u32) -> Result<(), ExternalFlashError> {
+        self.check_protected(start, start + size)?;
 
-        while self.read_status_registers().write_in_progress {}
+        self.set_write_enable(true)?;
+
+        self.send(FlashCommand::Erase(opcode, start))?;
+
+        // Write Enable gets reset automatically
+        self.wait_while_busy()
     }
 
-    // Read flash memory starting from address `start` with length `len`
-    pub fn read(&mut self, start: u32, len: u32) -> Vec<u8> {
-        self.transfer(FlashCommand::Read(start), len)
+    // Erase [from, to) (sector aligned), using the largest erases that fit
+    pub fn erase_range(&mut self, from: u32, to: u32) -> Result<(), ExternalFlashError> {
+        let mut address = from;
+
+        while address < to {
+            let erase = self.geometry.largest_erase(address, to)
+                .ok_or(ExternalFlashError::NotAligned)?;
+
+            self.erase_block(erase.opcode, address, erase.size)?;
+            address += erase.size;
+        }
+
+        Ok(())
+    }
+
+    // Fill `buffer` with flash memory starting from address `start`
+    pub fn read_into(&mut self, start: u32, buffer: &mut [u8]) -> Result<(), ExternalFlashError> {
+        self.transfer(FlashCommand::FastRead(start), buffer)
     }
 
-    fn write_same_page(&mut self, start: u32, buffer: Vec<u8>) {
-        self.set_write_enable(true);
-        self.send(FlashCommand::Write(start, buffer));
-        while self.read_status_registers().write_in_progress {}
+    fn write_same_page(&mut self, start: u32, buffer: &[u8]) -> Result<(), ExternalFlashError> {
+        self.set_write_enable(true)?;
+        self.send_data(FlashCommand::Write(start), buffer)?;
 
         // Write Enable gets reset automatically
+        self.wait_while_busy()
     }
 
     // Write contents of `buffer` to address `start` (blocking)
-    pub fn write(&mut self, start: u32, buffer: Vec<u8>) {
+    pub fn write_from(&mut self, start: u32, buffer: &[u8]) -> Result<(), ExternalFlashError> {
+        self.check_protected(start, start + buffer.len() as u32)?;
 
-        let page_start = start & 0x00ffff00;
-        let mut in_current_page = 0x100 - (start - page_start);
+        let mut address = start;
         let mut remaining = buffer;
-        let mut i = start;
 
-        while remaining.len() > (in_current_page as usize) {
-            let new_remaining = remaining.split_off(in_current_page as usize);
+        while !remaining.is_empty() {
+            let page_size = self.geometry.page_size;
+            let in_current_page = (page_size - (address % page_size)) as usize;
+            let (page, rest) = remaining.split_at(remaining.len().min(in_current_page));
 
-            self.write_same_page(i, remaining);
+            self.write_same_page(address, page)?;
 
-            remaining = new_remaining;
-            i += in_current_page;
-            in_current_page = 0x100;
+            address += page.len() as u32;
+            remaining = rest;
         }
 
-        self.write_same_page(i, remaining);
+        Ok(())
     }
 
-    pub fn self_test(&mut self) -> Result<(), String> {
-        let address = 0x0001_2345;
-        for byte_amount in [1, 10, 300, 1000] {
-            self.erase_sector(address);
-            let mut buffer = self.read(address, byte_amount);
-            for i in 0..buffer.len() {
-                buffer[i] = (i + (byte_amount as usize)) as u8;
+    // Write contents of `buffer` to address `start` (blocking), unlike write
+    // this also works when bits need to be set from 0 to 1. The affected
+    // sectors are only erased (and the rest of their data restored) when the
+    // new data can't be programmed over the old data.
+    pub fn write_buffered(&mut self, start: u32, buffer: &[u8]) -> Result<(), ExternalFlashError> {
+        let mut address = start;
+        let mut remaining = buffer;
+
+        while !remaining.is_empty() {
+            let sector_start = address & !(SECTOR_SIZE - 1);
+            let offset = (address - sector_start) as usize;
+            let len = remaining.len().min(SECTOR_SIZE as usize - offset);
+            let (data, rest) = remaining.split_at(len);
+
+            // Compare with the current contents a chunk at a time
+            let mut same = true;
+            let mut compatible = true;
+            let mut current = [0; COMPARE_SIZE];
+            for (i, chunk) in data.chunks(COMPARE_SIZE).enumerate() {
+                let current = &mut current[..chunk.len()];
+                self.read_into(address + (i * COMPARE_SIZE) as u32, current)?;
+
+                same &= current == chunk;
+                // Programming can only change bits from 1 to 0
+                compatible &= current.iter()
+                    .zip(chunk)
+                    .all(|(old, new)| old & new == *new);
             }
-            let before = buffer.clone();
-            self.write(address, buffer);
-            buffer = self.read(address, byte_amount);
-            if buffer != before {
-                for i in 0..buffer.len() {
-                    if buffer[i] != before[i] {
-                        rprintln!("{}: {} <-> {}", i, before[i], buffer[i]);
-                    }
+
+            if !same {
+                if compatible {
+                    self.write_from(address, data)?;
+                } else {
+                    // The only allocation left, a sector doesn't fit on the
+                    // stack
+                    let mut sector = vec![0; SECTOR_SIZE as usize];
+                    self.read_into(sector_start, &mut sector)?;
+                    sector[offset..offset + len].copy_from_slice(data);
+
+                    self.erase_sector(sector_start)?;
+                    self.write_erased_sector(sector_start, &sector)?;
                 }
-                return Err(format!(
-                    "Reading/writing {} byte(s) failed",
-                    byte_amount,
-                ));
+            }
+
+            address += len as u32;
+            remaining = rest;
+        }
+
+        Ok(())
+    }
+
+    // Write a full sector that was just erased, skipping pages that would
+    // stay erased
+    fn write_erased_sector(&mut self, sector_start: u32, sector: &[u8]) -> Result<(), ExternalFlashError> {
+        let page_size = self.geometry.page_size;
+        for (i, page) in sector.chunks(page_size as usize).enumerate() {
+            if page.iter().any(|b| *b != 0xff) {
+                self.write_same_page(sector_start + (i as u32) * page_size, page)?;
             }
         }
 
         Ok(())
     }
 }
+
+impl ErrorType for ExternalFlash {
+    type Error = ExternalFlashError;
+}
+
+// Release from Deep Power-Down for the panic and fault handlers, which can't
+// use ExternalFlash because the crash may have happened while the SPI was
+// locked. Uses SPIM0 as set up in init directly.
+//
+// Safety: only call right before resetting, it takes over SPIM0 and the chip
+// select pins.
+pub unsafe fn wake_up_from_fault() {
+    let peripherals = nrf52832_hal::pac::Peripherals::steal();
+    let spim = &peripherals.SPIM0;
+    let p0 = &peripherals.P0;
+
+    // Not set up yet, so the flash wasn't put to sleep since the reset
+    if spim.enable.read().bits() != 7 {
+        return;
+    }
+
+    // Abort a transfer that was interrupted by the crash
+    spim.events_stopped.write(|w| w.bits(0));
+    spim.tasks_stop.write(|w| w.bits(1));
+    for _ in 0..FAULT_SPI_TIMEOUT {
+        if spim.events_stopped.read().bits() != 0 {
+            break;
+        }
+    }
+
+    // EasyDMA can only read from RAM, the stack is
+    let command = [0xab_u8];
+
+    p0.outset.write(|w| w.bits(1 << PIN_DISPLAY_CS));
+    p0.outclr.write(|w| w.bits(1 << PIN_FLASH_CS));
+
+    spim.txd.ptr.write(|w| w.bits(command.as_ptr() as u32));
+    spim.txd.maxcnt.write(|w| w.bits(command.len() as u32));
+    spim.rxd.maxcnt.write(|w| w.bits(0));
+    spim.events_end.write(|w| w.bits(0));
+    spim.tasks_start.write(|w| w.bits(1));
+    for _ in 0..FAULT_SPI_TIMEOUT {
+        if spim.events_end.read().bits() != 0 {
+            break;
+        }
+    }
+
+    p0.outset.write(|w| w.bits(1 << PIN_FLASH_CS));
+    cortex_m::asm::delay(WAKE_UP_CYCLES);
+}
+
+impl ReadNorFlash for ExternalFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+
+        self.read_into(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.geometry.capacity as usize
+    }
+}
+
+impl NorFlash for ExternalFlash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+
+        self.erase_range(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+
+        self.write_from(offset, bytes)
+    }
+}
+
+// Programming only ever clears bits, so writing the same byte twice is fine
+impl MultiwriteNorFlash for ExternalFlash {}
@@ -1,7 +1,7 @@
 use nrf52832_hal::nvmc::{Nvmc, NvmcError};
 use nrf52832_hal::pac::NVMC;
 
-use embedded_storage::nor_flash::{ReadNorFlash, NorFlash};
+use embedded_storage::nor_flash::{ErrorType, ReadNorFlash, NorFlash};
 
 pub struct InternalFlash {
     nvmc: Nvmc<NVMC>,
@@ -35,3 +35,32 @@ impl InternalFlash {
         self.nvmc.erase(start, end)
     }
 }
+
+impl ErrorType for InternalFlash {
+    type Error = NvmcError;
+}
+
+impl ReadNorFlash for InternalFlash {
+    const READ_SIZE: usize = <Nvmc<NVMC> as ReadNorFlash>::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.nvmc.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.nvmc.capacity()
+    }
+}
+
+impl NorFlash for InternalFlash {
+    const WRITE_SIZE: usize = <Nvmc<NVMC> as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <Nvmc<NVMC> as NorFlash>::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.nvmc.erase(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.nvmc.write(offset, bytes)
+    }
+}
@@ -1,5 +1,7 @@
 mod external;
 mod internal;
+pub mod partition;
 
-pub use external::ExternalFlash;
+pub use external::{ExternalFlash, ExternalFlashError, wake_up_from_fault};
 pub use internal::InternalFlash;
+pub use pinetime_common::flash::{crc32, FlashGeometry, EraseType};
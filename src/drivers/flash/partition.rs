@@ -0,0 +1,67 @@
+use super::{InternalFlash, ExternalFlash};
+
+pub use pinetime_common::flash::partition::{Partition, PartitionError, FlashOrigin, is_valid_layout};
+
+// InternalFlash only covers the primary slot
+impl FlashOrigin for InternalFlash {
+    const ORIGIN: u32 = 0x0000_8000;
+}
+
+impl FlashOrigin for ExternalFlash {
+    const ORIGIN: u32 = 0x0000_0000;
+}
+
+// Flash partition table, keep in sync with memory.x
+
+// ---- INTERNAL FLASH ----
+pub const BOOTLOADER: Partition<InternalFlash> =
+    Partition::new("BOOTLOADER", 0x0000_0000, 28 * 1024);
+pub const REBOOTLOG: Partition<InternalFlash> =
+    Partition::new("REBOOTLOG", 0x0000_7000, 4 * 1024);
+// HEADER, application and FOOTER
+pub const PRIMARY_SLOT: Partition<InternalFlash> =
+    Partition::new("PRIMARY_SLOT", 0x0000_8000, 464 * 1024);
+pub const MCUBOOT_HEADER: Partition<InternalFlash> =
+    Partition::new("HEADER", 0x0000_8000, 32);
+// Image trailer, see mcuboot::Trailer
+pub const MCUBOOT_FOOTER: Partition<InternalFlash> =
+    Partition::new("FOOTER", 0x0007_bfd0, 48);
+pub const SCRATCH: Partition<InternalFlash> =
+    Partition::new("SCRATCH", 0x0007_c000, 4 * 1024);
+
+// ---- EXTERNAL FLASH ----
+// Boot logo and recovery firmware of the InfiniTime bootloader
+pub const BOOTLOADER_ASSETS: Partition<ExternalFlash> =
+    Partition::new("BOOTLOADERASSETS", 0x0000_0000, 256 * 1024);
+pub const STANDBY_IMAGE: Partition<ExternalFlash> =
+    Partition::new("STANDBY_IMAGE", 0x0004_0000, 464 * 1024);
+// Unused: the block protect bits can only cover 256K, 512K, 1M, ... from the
+// bottom, so the smallest range that includes STANDBY_IMAGE is the lowest
+// 1MB. Writable partitions can't be in there while the standby image is
+// locked (see ExternalFlash::protect), so they start at 1MB.
+pub const RESERVED: Partition<ExternalFlash> =
+    Partition::new("RESERVED", 0x000b_4000, 304 * 1024);
+pub const BLE_KEYS: Partition<ExternalFlash> =
+    Partition::new("BLE_KEYS", 0x0010_0000, 4 * 1024);
+// Sector pair
+pub const SETTINGS: Partition<ExternalFlash> =
+    Partition::new("SETTINGS", 0x0010_1000, 8 * 1024);
+// Two sectors, to test writes across a sector boundary
+pub const SELF_TEST: Partition<ExternalFlash> =
+    Partition::new("SELF_TEST", 0x0010_3000, 8 * 1024);
+// Ring of time-series records, see drivers::datalog
+pub const DATA_LOG: Partition<ExternalFlash> =
+    Partition::new("DATA_LOG", 0x0010_5000, 232 * 1024);
+pub const USER_FILESYSTEM: Partition<ExternalFlash> =
+    Partition::new("USER_FILESYSTEM", 0x0013_f000, 2820 * 1024);
+
+// MCUBOOT_HEADER and MCUBOOT_FOOTER are part of PRIMARY_SLOT
+const _: () = assert!(is_valid_layout(
+    &[BOOTLOADER, REBOOTLOG, PRIMARY_SLOT, SCRATCH],
+    512 * 1024,
+));
+
+const _: () = assert!(is_valid_layout(
+    &[BOOTLOADER_ASSETS, STANDBY_IMAGE, RESERVED, BLE_KEYS, SETTINGS, SELF_TEST, DATA_LOG, USER_FILESYSTEM],
+    4 * 1024 * 1024,
+));
@@ -1,40 +1,168 @@
-mod header;
-mod footer;
+use pinetime_common::mcuboot::swap_request;
 
-use header::MCUBootHeader;
-use footer::MCUBootFooter;
+pub use pinetime_common::mcuboot::{
+    Image, MCUBootHeader, MCUBootHeaderVersion, ImageError, Tlv, TlvType,
+    SignatureError, Trailer, ImageState, SwapType, SwapInfo, Magic, Flag,
+};
 
 use alloc::format;
 use alloc::string::String;
 
-use crate::drivers::flash::InternalFlash;
+use crate::drivers::flash::{InternalFlash, ExternalFlash, ExternalFlashError};
+use crate::drivers::flash::partition::{PartitionError, PRIMARY_SLOT, STANDBY_IMAGE};
 
+use nrf52832_hal::nvmc::NvmcError;
+
+// Public key that images have to be signed with (see keys/README.md), as the
+// DER SubjectPublicKeyInfo that imgtool hashes for the KEYHASH TLV. The path
+// comes from build.rs.
+pub const SIGNING_KEY: &[u8] = include_bytes!(env!("PINETIME_SIGNING_KEY"));
+
+// What's in STANDBY_IMAGE, the image MCUBoot can swap to
+#[derive(Debug, Clone, Copy)]
+pub struct StandbyImage {
+    pub version: Option<MCUBootHeaderVersion>,  // None if there is no image
+    pub intact: bool,                           // Hash checks out
+    pub signed: bool,                           // Signature checks out too
+}
+
+// The running image
 #[derive(Debug)]
 pub struct MCUBoot {
-    pub header: MCUBootHeader,
-    pub footer: MCUBootFooter,
+    pub header: Option<MCUBootHeader>,  // None when not flashed as an MCUBoot image
+    pub trailer: Trailer,
+    pub standby_image: Option<StandbyImage>,  // None until checked
 }
 
 impl MCUBoot {
+    // Doesn't fail on a missing header or trailer, like when flashed with a
+    // debugger
     pub fn get(internal_flash: &mut InternalFlash) -> Self {
+        let header = MCUBootHeader::read(internal_flash, PRIMARY_SLOT).ok();
+        // Only fails if the trailer isn't in PRIMARY_SLOT
+        let trailer = Trailer::read(internal_flash, PRIMARY_SLOT).unwrap();
+
         MCUBoot {
-            header: MCUBootHeader::get(internal_flash),
-            footer: MCUBootFooter::get(internal_flash),
+            header,
+            trailer,
+            standby_image: None,
         }
     }
 
-    pub fn mark_valid(&mut self, internal_flash: &mut InternalFlash) {
-        self.footer.is_valid = true;
-        self.footer.write(internal_flash);
+    // Reads all of STANDBY_IMAGE, so this takes a while. `progress` is
+    // called while reading, to pet the watchdog.
+    pub fn check_standby_image(
+        &mut self,
+        external_flash: &mut ExternalFlash,
+        mut progress: impl FnMut(),
+    ) -> StandbyImage {
+        let version = MCUBootHeader::read(external_flash, STANDBY_IMAGE)
+            .ok()
+            .map(|header| header.version);
+
+        // The slot is only read once, for both checks
+        let (intact, signed) = Image::read(external_flash, STANDBY_IMAGE)
+            .and_then(|image| {
+                let hash = image.hash(external_flash, &mut progress)?;
+                let intact = image.check_hash(external_flash, &hash).is_ok();
+                progress();
+                let signed = intact
+                    && image.check_signature(external_flash, SIGNING_KEY, &hash).is_ok();
+                Ok((intact, signed))
+            })
+            .unwrap_or((false, false));
+
+        let standby_image = StandbyImage {
+            version,
+            intact,
+            signed,
+        };
+        self.standby_image = Some(standby_image);
+        standby_image
+    }
+
+    // Keep the running image, like boot_set_confirmed in MCUBoot
+    pub fn confirm(&mut self, internal_flash: &mut InternalFlash) -> Result<(), ImageError<NvmcError>> {
+        match self.trailer.state() {
+            ImageState::Confirmed | ImageState::Permanent => return Ok(()),
+            ImageState::Corrupt => return Err(ImageError::InvalidTrailer),
+            ImageState::Testing => {},
+        }
+
+        Trailer::write_image_ok(internal_flash, PRIMARY_SLOT)?;
+        self.trailer = Trailer::read(internal_flash, PRIMARY_SLOT)?;
+
+        if self.trailer.state() != ImageState::Confirmed {
+            return Err(ImageError::InvalidTrailer);
+        }
+
+        Ok(())
+    }
+
+    // Go back to the image in STANDBY_IMAGE, which ran before the last
+    // update, if it is signed with SIGNING_KEY. A test swap, so MCUBoot
+    // comes back to the running image if the previous one doesn't confirm
+    // itself. `progress` is called like for check_standby_image.
+    pub fn request_revert(
+        external_flash: &mut ExternalFlash,
+        mut progress: impl FnMut(),
+    ) -> Result<MCUBootHeaderVersion, ImageError<ExternalFlashError>> {
+        let image = Image::read(external_flash, STANDBY_IMAGE)?;
+        let hash = image.hash(external_flash, &mut progress)?;
+        image.check_hash(external_flash, &hash)?;
+        progress();
+        image.check_signature(external_flash, SIGNING_KEY, &hash)?;
+
+        request_swap(external_flash, SwapType::Test)?;
+
+        Ok(image.header.version)
+    }
+
+    // Whether MCUBoot writes STANDBY_IMAGE on the next boot: to revert an
+    // unconfirmed image, or to swap in a requested update. Assumes it does if
+    // the trailer can't be read.
+    pub fn bootloader_writes_standby_image(&self, external_flash: &mut ExternalFlash) -> bool {
+        let swap_requested = !matches!(
+            Trailer::read(external_flash, STANDBY_IMAGE),
+            Ok(trailer) if trailer.magic == Magic::Unset
+        );
+
+        self.trailer.state() == ImageState::Testing || swap_requested
+    }
+
+    // Read the trailer back, it should match and allow confirming the image
+    pub fn verify_trailer(&self, internal_flash: &mut InternalFlash) -> Result<(), String> {
+        let trailer = Trailer::read(internal_flash, PRIMARY_SLOT)
+            .map_err(|e| format!("Reading failed: {:?}", e))?;
+
+        if trailer != self.trailer {
+            return Err(format!("Changed from {:?} to {:?}", self.trailer, trailer));
+        }
+
+        if trailer.state() == ImageState::Corrupt {
+            return Err(format!("Corrupt: {:?}", trailer));
+        }
+
+        Ok(())
     }
 
     pub fn version_string(&self) -> String {
-        format!(
-                "v{}.{}.{}+{}",
-               self.header.version.major,
-               self.header.version.minor,
-               self.header.version.revision,
-               self.header.version.build_num,
-        )
+        match &self.header {
+            Some(header) => format!("v{}", header.version),
+            None => String::from("unversioned"),
+        }
     }
 }
+
+// Write the STANDBY_IMAGE trailer so MCUBoot swaps it in on the next boot
+fn request_swap(external_flash: &mut ExternalFlash, swap_type: SwapType) -> Result<(), ImageError<ExternalFlashError>> {
+    let trailer = swap_request(swap_type);
+
+    // The trailer of an earlier update may still be there, which needs an
+    // erase of the sector
+    let result = external_flash.unlock_standby_image()
+        .and_then(|_| external_flash.write_buffered(STANDBY_IMAGE.end() - trailer.len() as u32, &trailer));
+    external_flash.lock_standby_image();
+
+    result.map_err(|e| ImageError::Flash(PartitionError::Flash(e)))
+}
@@ -6,3 +6,9 @@ pub mod flash;
 pub mod bluetooth;
 pub mod clock;
 pub mod mcuboot;
+pub mod motor;
+pub mod crashlog;
+pub mod selftest;
+
+// Hardware independent, in pinetime-common so they can be tested on the host
+pub use pinetime_common::{settings, datalog, filesystem};
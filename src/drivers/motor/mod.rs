@@ -0,0 +1,23 @@
+use nrf52832_hal::gpio::{Pin, Output, PushPull};
+use nrf52832_hal::prelude::OutputPin;
+
+pub struct Motor {
+    // Low = vibrating, High = off
+    pin: Pin<Output<PushPull>>,
+}
+
+impl Motor {
+    pub fn new(pin: Pin<Output<PushPull>>) -> Motor {
+        Motor {
+            pin,
+        }
+    }
+
+    pub fn set_vibrating(&mut self, value: bool) {
+        if value {
+            self.pin.set_low().unwrap();
+        } else {
+            self.pin.set_high().unwrap();
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::drivers::flash::{InternalFlash, ExternalFlash};
+use crate::drivers::flash::partition::{SELF_TEST, PRIMARY_SLOT};
+use crate::drivers::mcuboot::{MCUBoot, Image};
+
+use embedded_storage::nor_flash::NorFlash;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{vec, format};
+
+use rtt_target::rprintln;
+
+// Hardware self-test, run before confirming a new image. Every test runs,
+// even if an earlier one failed, so the report shows everything that's wrong.
+
+#[derive(Debug)]
+pub struct TestResult {
+    pub name: &'static str,
+    pub result: Result<(), String>,
+}
+
+#[derive(Debug)]
+pub struct SelfTestReport {
+    pub results: Vec<TestResult>,
+}
+
+impl SelfTestReport {
+    fn run(&mut self, name: &'static str, test: impl FnOnce() -> Result<(), String>) {
+        let result = test();
+        self.results.push(TestResult { name, result });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|test| test.result.is_ok())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &TestResult> {
+        self.results.iter().filter(|test| test.result.is_err())
+    }
+
+    pub fn print(&self) {
+        for test in self.results.iter() {
+            match &test.result {
+                Ok(()) => rprintln!("[PASS] {}", test.name),
+                Err(e) => rprintln!("[FAIL] {}: {}", test.name, e),
+            }
+        }
+    }
+}
+
+pub fn run(
+    internal_flash: &mut InternalFlash,
+    external_flash: &mut ExternalFlash,
+    mcuboot: &MCUBoot,
+) -> SelfTestReport {
+    let mut report = SelfTestReport {
+        results: Vec::new(),
+    };
+
+    report.run("JEDEC ID", || test_jedec_id(external_flash));
+    report.run("Erase", || test_erase(external_flash));
+    report.run("Read/write sizes", || test_sizes(external_flash));
+    report.run("Page boundary", || {
+        // Starts 16 bytes before a page boundary and crosses the next one too
+        let page_size = external_flash.geometry().page_size;
+        test_write_read(external_flash, page_size - 16, page_size as usize + 32)
+    });
+    report.run("Sector boundary", || {
+        let sector_size = ExternalFlash::ERASE_SIZE as u32;
+        test_write_read(external_flash, sector_size - 100, 200)
+    });
+    report.run("MCUBoot trailer", || mcuboot.verify_trailer(internal_flash));
+    report.run("Image hash", || {
+        Image::read(internal_flash, PRIMARY_SLOT)
+            .and_then(|image| image.verify_hash(internal_flash))
+            .map_err(|e| format!("{:?}", e))
+    });
+
+    report
+}
+
+fn test_jedec_id(external_flash: &mut ExternalFlash) -> Result<(), String> {
+    let id = external_flash.read_identification()
+        .map_err(|e| format!("Reading failed: {:?}", e))?;
+
+    // A missing or unpowered flash reads as all 0s or all 1s
+    if id.manufacturer == 0x00 || id.manufacturer == 0xff {
+        return Err(format!("No flash responding ({:?})", id));
+    }
+
+    // The capacity is 2^N bytes
+    let capacity = external_flash.geometry().capacity;
+    if 1u32.checked_shl(id.capacity.into()) != Some(capacity) {
+        return Err(format!("{:?} doesn't match capacity {:#x}", id, capacity));
+    }
+
+    Ok(())
+}
+
+fn test_erase(external_flash: &mut ExternalFlash) -> Result<(), String> {
+    // Make sure there is something to erase
+    SELF_TEST.write(external_flash, 0, &[0; 16])
+        .map_err(|e| format!("Writing failed: {:?}", e))?;
+    SELF_TEST.erase_all(external_flash)
+        .map_err(|e| format!("Erasing failed: {:?}", e))?;
+
+    let mut buffer = [0; 256];
+    for offset in (0..SELF_TEST.size).step_by(buffer.len()) {
+        SELF_TEST.read(external_flash, offset, &mut buffer)
+            .map_err(|e| format!("Reading failed: {:?}", e))?;
+
+        if let Some(i) = buffer.iter().position(|b| *b != 0xff) {
+            return Err(format!("{:#x} not erased", offset + i as u32));
+        }
+    }
+
+    Ok(())
+}
+
+fn test_sizes(external_flash: &mut ExternalFlash) -> Result<(), String> {
+    // Not page aligned on purpose
+    for byte_amount in [1, 10, 300, 1000] {
+        test_write_read(external_flash, 0x345, byte_amount)
+            .map_err(|e| format!("{} byte(s): {}", byte_amount, e))?;
+    }
+
+    Ok(())
+}
+
+// Erase SELF_TEST, write `len` bytes at `offset` and read them back
+fn test_write_read(external_flash: &mut ExternalFlash, offset: u32, len: usize) -> Result<(), String> {
+    SELF_TEST.erase_all(external_flash)
+        .map_err(|e| format!("Erasing failed: {:?}", e))?;
+
+    let before: Vec<u8> = (0..len)
+        .map(|i| (i + len) as u8)
+        .collect();
+    SELF_TEST.write(external_flash, offset, &before)
+        .map_err(|e| format!("Writing failed: {:?}", e))?;
+
+    let mut buffer = vec![0; len];
+    SELF_TEST.read(external_flash, offset, &mut buffer)
+        .map_err(|e| format!("Reading failed: {:?}", e))?;
+
+    match buffer.iter().zip(before.iter()).position(|(after, before)| after != before) {
+        Some(i) => Err(format!(
+            "{:#x}: wrote {:#04x}, read {:#04x}",
+            offset + i as u32,
+            before[i],
+            buffer[i],
+        )),
+        None => Ok(()),
+    }
+}
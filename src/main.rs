@@ -20,6 +20,11 @@ mod tasks {
     use crate::drivers::battery::Battery;
     use crate::drivers::clock::Clock;
     use crate::drivers::mcuboot::MCUBoot;
+    use crate::drivers::motor::Motor;
+    use crate::drivers::filesystem::Filesystem;
+    use crate::drivers::settings::SettingsStore;
+    use crate::drivers::datalog::DataLog;
+    use crate::drivers::bluetooth::{AlertLevel, BeaconConfig};
 
     use crate::ui::screen::Screen;
 
@@ -38,6 +43,7 @@ mod tasks {
     use chrono::NaiveDateTime;
 
     use alloc::boxed::Box;
+    use alloc::string::String;
 
     use spin::Mutex;
 
@@ -53,10 +59,14 @@ mod tasks {
         touchpanel: TouchPanel,
         internal_flash: InternalFlash,
         external_flash: ExternalFlash,
+        filesystem: Option<Filesystem<ExternalFlash>>,
         bluetooth: Bluetooth,
         battery: Battery,
         clock: Clock<ConnectedRtc>,
         mcuboot: MCUBoot,
+        motor: Motor,
+        settings: SettingsStore<ExternalFlash>,
+        datalog: Option<DataLog<ExternalFlash>>,
 
         current_screen: Box<dyn Screen<Display<PixelType, ConnectedSpim>>>,
     }
@@ -76,10 +86,14 @@ mod tasks {
                 touchpanel: init_shared.touchpanel,
                 internal_flash: init_shared.internal_flash,
                 external_flash: init_shared.external_flash,
+                filesystem: init_shared.filesystem,
                 bluetooth: init_shared.bluetooth,
                 battery: init_shared.battery,
                 clock: init_shared.clock,
                 mcuboot: init_shared.mcuboot,
+                motor: init_shared.motor,
+                settings: init_shared.settings,
+                datalog: init_shared.datalog,
 
                 current_screen: init_shared.current_screen,
             }
@@ -99,6 +113,8 @@ mod tasks {
             ble_rx_buf: PacketBuffer = [0; MIN_PDU_BUF],
             ble_tx_queue: SimpleQueue = SimpleQueue::new(),
             ble_rx_queue: SimpleQueue = SimpleQueue::new(),
+            ble_tx_queue_spare: SimpleQueue = SimpleQueue::new(),
+            ble_rx_queue_spare: SimpleQueue = SimpleQueue::new(),
     ])]
     fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
         let (shared, local, mono) = crate::pinetimers::tasks_impl::init(ctx);
@@ -111,7 +127,7 @@ mod tasks {
         crate::pinetimers::tasks_impl::idle(ctx)
     }
 
-    #[task(shared = [display])]
+    #[task(shared = [display, settings])]
     fn display_init(ctx: display_init::Context) {
         crate::pinetimers::tasks_impl::display_init(ctx)
     }
@@ -126,12 +142,12 @@ mod tasks {
         crate::pinetimers::tasks_impl::periodic_update_device_state(ctx)
     }
 
-    #[task(shared = [display, current_screen, clock, mcuboot])]
+    #[task(shared = [display, current_screen, clock, mcuboot, settings])]
     fn redraw_screen(ctx: redraw_screen::Context) {
         crate::pinetimers::tasks_impl::redraw_screen(ctx)
     }
 
-    #[task(shared = [display, current_screen, clock, mcuboot])]
+    #[task(shared = [display, current_screen, clock, mcuboot, settings])]
     fn init_screen(ctx: init_screen::Context) {
         crate::pinetimers::tasks_impl::init_screen(ctx)
     }
@@ -161,12 +177,22 @@ mod tasks {
         crate::pinetimers::tasks_impl::ble_timer(ctx)
     }
 
+    #[task(shared = [bluetooth], priority = 2)]
+    fn ble_beacon(ctx: ble_beacon::Context) {
+        crate::pinetimers::tasks_impl::ble_beacon(ctx)
+    }
+
+    #[task(shared = [bluetooth])]
+    fn ble_rotate_address(ctx: ble_rotate_address::Context) {
+        crate::pinetimers::tasks_impl::ble_rotate_address(ctx)
+    }
+
     #[task(shared = [bluetooth, battery, clock, mcuboot])]
     fn ble_update(ctx: ble_update::Context) {
         crate::pinetimers::tasks_impl::ble_update(ctx)
     }
 
-    #[task(shared = [clock])]
+    #[task(shared = [clock, settings, external_flash])]
     fn set_time(ctx: set_time::Context, time: NaiveDateTime) {
         crate::pinetimers::tasks_impl::set_time(ctx, time);
     }
@@ -181,27 +207,150 @@ mod tasks {
         crate::pinetimers::tasks_impl::validate(ctx);
     }
 
-    #[task]
+    #[task(shared = [clock, external_flash])]
     fn reboot(ctx: reboot::Context) {
         crate::pinetimers::tasks_impl::reboot(ctx);
     }
+
+    #[task(shared = [motor, settings])]
+    fn link_lost(ctx: link_lost::Context, alert_level: AlertLevel) {
+        crate::pinetimers::tasks_impl::link_lost(ctx, alert_level);
+    }
+
+    #[task(shared = [motor])]
+    fn stop_vibration(ctx: stop_vibration::Context) {
+        crate::pinetimers::tasks_impl::stop_vibration(ctx);
+    }
+
+    #[task(shared = [settings, external_flash])]
+    fn toggle_do_not_disturb(ctx: toggle_do_not_disturb::Context) {
+        crate::pinetimers::tasks_impl::toggle_do_not_disturb(ctx);
+    }
+
+    #[task(shared = [display, settings, external_flash])]
+    fn change_brightness(ctx: change_brightness::Context, brighter: bool) {
+        crate::pinetimers::tasks_impl::change_brightness(ctx, brighter);
+    }
+
+    #[task(shared = [clock, settings, external_flash])]
+    fn checkpoint_time(ctx: checkpoint_time::Context) {
+        crate::pinetimers::tasks_impl::checkpoint_time(ctx);
+    }
+
+    #[task(shared = [external_flash])]
+    fn power_down_flash(ctx: power_down_flash::Context) {
+        crate::pinetimers::tasks_impl::power_down_flash(ctx);
+    }
+
+    #[task(shared = [battery, clock, datalog, external_flash])]
+    fn log_battery(ctx: log_battery::Context) {
+        crate::pinetimers::tasks_impl::log_battery(ctx);
+    }
+
+    #[task(shared = [external_flash, mcuboot, watchdog_handles])]
+    fn check_standby_image(ctx: check_standby_image::Context) {
+        crate::pinetimers::tasks_impl::check_standby_image(ctx);
+    }
+
+    #[task(shared = [internal_flash, mcuboot])]
+    fn confirm_image(ctx: confirm_image::Context) {
+        crate::pinetimers::tasks_impl::confirm_image(ctx);
+    }
+
+    #[task(shared = [settings, external_flash])]
+    fn set_beacon(ctx: set_beacon::Context, config: BeaconConfig) {
+        crate::pinetimers::tasks_impl::set_beacon(ctx, config);
+    }
+
+    #[task(shared = [bluetooth, settings, external_flash])]
+    fn set_ble_name(ctx: set_ble_name::Context, name: String) {
+        crate::pinetimers::tasks_impl::set_ble_name(ctx, name);
+    }
+
+    #[task(shared = [external_flash, filesystem, watchdog_handles])]
+    fn mount_filesystem(ctx: mount_filesystem::Context) {
+        crate::pinetimers::tasks_impl::mount_filesystem(ctx);
+    }
+
+    #[task]
+    fn mark_stable(ctx: mark_stable::Context) {
+        crate::pinetimers::tasks_impl::mark_stable(ctx);
+    }
+
+    #[task(shared = [settings, external_flash])]
+    fn toggle_beacon(ctx: toggle_beacon::Context) {
+        crate::pinetimers::tasks_impl::toggle_beacon(ctx);
+    }
+
+    #[task(shared = [clock, datalog, external_flash])]
+    fn show_battery_history(ctx: show_battery_history::Context) {
+        crate::pinetimers::tasks_impl::show_battery_history(ctx);
+    }
+
+    #[task(shared = [external_flash, watchdog_handles])]
+    fn revert_image(ctx: revert_image::Context) {
+        crate::pinetimers::tasks_impl::revert_image(ctx);
+    }
 }
 
+use crate::drivers::crashlog;
+use crate::drivers::flash;
+
 use rtt_target::rprintln;
 
 use core::panic::PanicInfo;
 
+use cortex_m::peripheral::{DCB, SCB};
+use cortex_m_rt::{exception, ExceptionFrame};
+
 use linked_list_allocator::LockedHeap;
 
 use alloc::alloc::Layout;
 
+// Break into the debugger if there is one, otherwise reboot so the watch
+// keeps working. The crash log survives the reboot.
+fn crashed() -> ! {
+    // C_DEBUGEN in DHCSR
+    let debugger_attached = unsafe { (*DCB::ptr()).dhcsr.read() & 1 != 0 };
+
+    // The bootloader needs the external flash, which may be asleep
+    if !debugger_attached {
+        unsafe { flash::wake_up_from_fault() };
+    }
+
+    loop {
+        if debugger_attached {
+            cortex_m::asm::bkpt();
+        } else {
+            SCB::sys_reset();
+        }
+    }
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     rprintln!("----- PANIC -----");
     rprintln!("{:#?}", info);
-    loop {
-        cortex_m::asm::bkpt();
-    }
+    crashlog::record_panic(info);
+    crashed();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    let (cfsr, hfsr) = unsafe {
+        let scb = &*SCB::ptr();
+        (scb.cfsr.read(), scb.hfsr.read())
+    };
+
+    rprintln!("----- HARDFAULT -----");
+    rprintln!("{:#?}", ef);
+    rprintln!("CFSR: {:#010x}, HFSR: {:#010x}", cfsr, hfsr);
+    crashlog::record_hard_fault(
+        [ef.r0, ef.r1, ef.r2, ef.r3, ef.r12, ef.lr, ef.pc, ef.xpsr],
+        cfsr,
+        hfsr,
+    );
+    crashed();
 }
 
 #[global_allocator]
@@ -211,7 +360,6 @@ static HEAP: LockedHeap = LockedHeap::empty();
 fn on_oom(layout: Layout) -> ! {
     rprintln!("----- OOM -----");
     rprintln!("{:#?}", layout);
-    loop {
-        cortex_m::asm::bkpt();
-    }
+    crashlog::record_oom(layout);
+    crashed();
 }
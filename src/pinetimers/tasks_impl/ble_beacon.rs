@@ -0,0 +1,13 @@
+use rtic::Mutex;
+
+use fugit::ExtU32;
+
+pub fn ble_beacon(mut ctx: crate::tasks::ble_beacon::Context) {
+    let interval = ctx.shared.bluetooth.lock(|bluetooth| {
+        bluetooth.broadcast_beacon()
+    });
+
+    if let Some(interval_ms) = interval {
+        crate::tasks::ble_beacon::spawn_after(interval_ms.millis()).unwrap();
+    }
+}
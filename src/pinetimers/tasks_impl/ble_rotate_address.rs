@@ -0,0 +1,12 @@
+use rtic::Mutex;
+
+use fugit::ExtU32;
+
+// Default private address timeout (TGAP(private_addr_int))
+pub fn ble_rotate_address(mut ctx: crate::tasks::ble_rotate_address::Context) {
+    crate::tasks::ble_rotate_address::spawn_after(15.minutes()).unwrap();
+
+    ctx.shared.bluetooth.lock(|bluetooth| {
+        bluetooth.rotate_address();
+    });
+}
@@ -0,0 +1,27 @@
+use rtic::mutex_prelude::TupleExt03;
+
+use rtt_target::rprintln;
+
+use crate::drivers::settings::Setting;
+
+// One step brighter or darker, from the brightness screen
+pub fn change_brightness(ctx: crate::tasks::change_brightness::Context, brighter: bool) {
+    (
+        ctx.shared.display,
+        ctx.shared.settings,
+        ctx.shared.external_flash,
+    ).lock(|display, settings, external_flash| {
+        let brightness = match brighter {
+            true => (settings.get().brightness + 1).min(7),
+            false => settings.get().brightness.saturating_sub(1).max(1),
+        };
+        if let Err(e) = settings.set(external_flash, Setting::Brightness(brightness)) {
+            rprintln!("Could not save brightness: {:?}", e);
+            return;
+        }
+        display.set_brightness(brightness);
+    });
+
+    // Update the level on the screen
+    crate::tasks::init_screen::spawn().ok();
+}
@@ -0,0 +1,24 @@
+use rtic::mutex_prelude::TupleExt03;
+
+use rtt_target::rprintln;
+
+// Check the version and signature of the image in STANDBY_IMAGE for the
+// firmware screen. Reads the whole slot, so it isn't done during boot, and
+// the watchdog is petted while reading.
+pub fn check_standby_image(ctx: crate::tasks::check_standby_image::Context) {
+    (
+        ctx.shared.external_flash,
+        ctx.shared.mcuboot,
+        ctx.shared.watchdog_handles,
+    ).lock(|external_flash, mcuboot, watchdog_handles| {
+        let standby_image = mcuboot.check_standby_image(external_flash, || {
+            for watchdog_handle in watchdog_handles.iter_mut() {
+                watchdog_handle.pet();
+            }
+        });
+        rprintln!("Standby image: {:?}", standby_image);
+    });
+
+    // Update the firmware screen if it is open
+    crate::tasks::init_screen::spawn().ok();
+}
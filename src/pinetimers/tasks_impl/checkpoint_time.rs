@@ -0,0 +1,32 @@
+use rtic::mutex_prelude::TupleExt03;
+
+use fugit::ExtU32;
+
+use rtt_target::rprintln;
+
+use crate::drivers::settings::Setting;
+
+// Save the time to flash, so it can be restored (approximately) after losing
+// power. The RAM copy only survives soft resets.
+pub fn checkpoint_time(ctx: crate::tasks::checkpoint_time::Context) {
+    crate::tasks::checkpoint_time::spawn_after(10.minutes()).unwrap();
+
+    (
+        ctx.shared.clock,
+        ctx.shared.settings,
+        ctx.shared.external_flash,
+    ).lock(|clock, settings, external_flash| {
+        let timestamp = clock.datetime.timestamp();
+
+        // Don't overwrite a checkpoint with a clock that was never set, but
+        // do save a clock that was set back
+        if !clock.checkpoint_pending && timestamp <= settings.get().time_checkpoint {
+            return;
+        }
+
+        match settings.set(external_flash, Setting::TimeCheckpoint(timestamp)) {
+            Ok(()) => clock.checkpoint_pending = false,
+            Err(e) => rprintln!("Could not save the time: {:?}", e),
+        }
+    });
+}
@@ -0,0 +1,19 @@
+use rtic::mutex_prelude::TupleExt02;
+
+use rtt_target::rprintln;
+
+// Confirm the running image without running the self-test, from the firmware
+// screen
+pub fn confirm_image(ctx: crate::tasks::confirm_image::Context) {
+    (
+        ctx.shared.internal_flash,
+        ctx.shared.mcuboot,
+    ).lock(|internal_flash, mcuboot| {
+        match mcuboot.confirm(internal_flash) {
+            Ok(()) => rprintln!("Image confirmed"),
+            Err(e) => rprintln!("Confirming image failed: {:?}", e),
+        }
+    });
+
+    crate::tasks::init_screen::spawn().ok();
+}
@@ -1,10 +1,13 @@
 use embedded_graphics::pixelcolor::RgbColor;
 use embedded_graphics_core::draw_target::DrawTarget;
-use rtic::Mutex;
+use rtic::mutex_prelude::TupleExt02;
 
-pub fn display_init(mut ctx: crate::tasks::display_init::Context) {
-    ctx.shared.display.lock(|display| {
-        display.init();
+pub fn display_init(ctx: crate::tasks::display_init::Context) {
+    (
+        ctx.shared.display,
+        ctx.shared.settings,
+    ).lock(|display, settings| {
+        display.init(settings.get().brightness);
         display.clear(RgbColor::BLACK).unwrap();
     });
     crate::tasks::init_screen::spawn().unwrap();
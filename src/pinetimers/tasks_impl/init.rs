@@ -11,9 +11,13 @@ use nrf52832_hal::pac::TIMER0;
 use nrf52832_hal::rtc::Rtc;
 use nrf52832_hal::wdt::{Watchdog, count, WatchdogHandle};
 use nrf52832_hal::wdt::handles::HdlN;
+use nrf52832_hal::rng::Rng;
+use nrf52832_hal::ecb::Ecb;
 
 use alloc::boxed::Box;
 
+use fugit::ExtU32;
+
 use spin::Mutex;
 
 use crate::drivers::display::Display;
@@ -22,10 +26,16 @@ use crate::drivers::touchpanel::TouchPanel;
 use crate::ui::screen::{Screen, ScreenMain};
 use crate::drivers::battery::Battery;
 use crate::drivers::flash::{InternalFlash, ExternalFlash};
-use crate::drivers::bluetooth::Bluetooth;
+use crate::drivers::flash::partition::{SETTINGS, DATA_LOG};
+use crate::drivers::bluetooth::{Bluetooth, BluetoothMode, IdentityResolvingKey, Privacy};
 use crate::pinetimers::{PixelType, ConnectedSpim, ConnectedRtc};
 use crate::drivers::clock::Clock;
 use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::motor::Motor;
+use crate::drivers::filesystem::Filesystem;
+use crate::drivers::settings::SettingsStore;
+use crate::drivers::datalog::DataLog;
+use crate::drivers::crashlog;
 
 pub struct Shared {
     pub gpiote: Gpiote,
@@ -35,10 +45,14 @@ pub struct Shared {
     pub touchpanel: TouchPanel,
     pub external_flash: ExternalFlash,
     pub internal_flash: InternalFlash,
+    pub filesystem: Option<Filesystem<ExternalFlash>>,
     pub bluetooth: Bluetooth,
     pub battery: Battery,
     pub clock: Clock<ConnectedRtc>,
     pub mcuboot: MCUBoot,
+    pub motor: Motor,
+    pub settings: SettingsStore<ExternalFlash>,
+    pub datalog: Option<DataLog<ExternalFlash>>,
 
     pub current_screen: Box<dyn Screen<Display<PixelType, ConnectedSpim>>>,
 }
@@ -49,10 +63,20 @@ pub fn init(mut ctx: crate::tasks::init::Context) -> (Shared, Local, crate::task
         rtt_init_print!();
         rprintln!("Pijn tijd");
 
+        // Find out why we reset (the register is cumulative, so clear it)
+        let resetreas = ctx.device.POWER.resetreas.read().bits();
+        ctx.device.POWER.resetreas.write(|w| unsafe { w.bits(resetreas) });
+        rprintln!("Reset reason: {}", crashlog::reset_reason(resetreas));
+        crashlog::init(resetreas);
+        let safe_mode = crashlog::safe_mode();
+        if safe_mode {
+            rprintln!("Crashed too often, starting in safe mode");
+        }
+
         unsafe {
-            // Set up heap
+            // Set up heap (up to NOINIT, see memory.x)
             let heap_start = 0x2000_1000;
-            let heap_end = 0x2001_0000;
+            let heap_end = 0x2000_fc00;
             crate::HEAP.lock().init(heap_start, heap_end - heap_start);
         }
 
@@ -82,6 +106,9 @@ pub fn init(mut ctx: crate::tasks::init::Context) -> (Shared, Local, crate::task
             .lo_to_hi()
             .enable_interrupt();
 
+        // Set up vibration motor
+        let motor = Motor::new(gpio.p0_16.into_push_pull_output(Level::High).degrade());
+
         // Set up charging
         let charging_input_pin = gpio.p0_19.into_floating_input().degrade();
 
@@ -158,11 +185,16 @@ pub fn init(mut ctx: crate::tasks::init::Context) -> (Shared, Local, crate::task
         );
 
         // Set up external flash
-        let external_flash = ExternalFlash::new(
+        let mut external_flash = ExternalFlash::new(
             ctx.local.spi_lock,
             gpio.p0_05.into_push_pull_output(Level::High).degrade(),
         );
 
+        match external_flash.detect_geometry() {
+            Ok(geometry) => rprintln!("External flash: {:?}", geometry),
+            Err(e) => rprintln!("Could not detect external flash geometry: {:?}", e),
+        }
+
         // Set up internal flash
         let mut internal_flash = InternalFlash::new(
             ctx.device.NVMC
@@ -170,6 +202,34 @@ pub fn init(mut ctx: crate::tasks::init::Context) -> (Shared, Local, crate::task
 
         let mcuboot = MCUBoot::get(&mut internal_flash);
 
+        // Keep the bootloader assets and standby image safe from bugs in our
+        // flash code, unless MCUBoot still needs to write the standby image
+        let lock_standby_image = !mcuboot.bootloader_writes_standby_image(&mut external_flash);
+        if let Err(e) = external_flash.protect(lock_standby_image) {
+            rprintln!("Could not protect external flash: {:?}", e);
+        }
+
+        // Load settings
+        let settings = match SettingsStore::load(&mut external_flash, SETTINGS) {
+            Ok(settings) => settings,
+            Err(e) => {
+                rprintln!("Could not load settings, using the defaults: {:?}", e);
+                SettingsStore::defaults(SETTINGS)
+            },
+        };
+
+        // Set up the data log
+        let datalog = match safe_mode {
+            true => None,
+            false => match DataLog::mount(&mut external_flash, DATA_LOG) {
+                Ok(datalog) => Some(datalog),
+                Err(e) => {
+                    rprintln!("Could not mount the data log, not logging: {:?}", e);
+                    None
+                },
+            },
+        };
+
         // Enable LFCLK
         Clocks::new(ctx.device.CLOCK)
             .start_lfclk()
@@ -179,25 +239,55 @@ pub fn init(mut ctx: crate::tasks::init::Context) -> (Shared, Local, crate::task
         // Prescaler value for 8Hz (125ms period)
         let rtc = Rtc::new(ctx.device.RTC1, 4095).unwrap();
         rtc.enable_counter();
-        let clock = Clock::new(rtc);
+        let clock = Clock::new(rtc, settings.get().time_checkpoint);
 
         // Set up Bluetooth
         ctx.core.DCB.enable_trace();
         ctx.core.DWT.enable_cycle_counter();
+        let mut rng = Rng::new(ctx.device.RNG);
+        let irk = match IdentityResolvingKey::load_or_generate(&mut external_flash, &mut rng) {
+            Ok(irk) => irk,
+            Err(e) => {
+                rprintln!("Could not load IRK, using a temporary one: {:?}", e);
+                IdentityResolvingKey::generate(&mut rng)
+            },
+        };
+        let privacy = Privacy::new(irk, Ecb::init(ctx.device.ECB), rng);
+        // Safe mode has to stay connectable for updates
+        let mode = match settings.get().active_beacon() {
+            Some(config) if !safe_mode => BluetoothMode::Beacon(config.clone()),
+            _ => BluetoothMode::Connectable,
+        };
         let bluetooth = Bluetooth::new(
             ctx.device.RADIO,
             ctx.device.FICR,
             ctx.device.TIMER2,
             ctx.local.ble_tx_buf,
             ctx.local.ble_rx_buf,
-            ctx.local.ble_tx_queue,
-            ctx.local.ble_rx_queue,
+            [
+                ctx.local.ble_tx_queue,
+                ctx.local.ble_rx_queue,
+                ctx.local.ble_tx_queue_spare,
+                ctx.local.ble_rx_queue_spare,
+            ],
+            privacy,
+            &settings.get().ble_name,
+            mode,
         );
 
         // Set up the UI
         let screen = Box::new(ScreenMain::new());
 
         crate::tasks::pet_watchdog::spawn().unwrap();
+        crate::tasks::mark_stable::spawn_after(1.minutes()).unwrap();
+        crate::tasks::ble_rotate_address::spawn_after(15.minutes()).unwrap();
+        crate::tasks::checkpoint_time::spawn_after(10.minutes()).unwrap();
+        crate::tasks::power_down_flash::spawn_after(5.secs()).unwrap();
+        if !safe_mode {
+            crate::tasks::mount_filesystem::spawn().unwrap();
+            crate::tasks::log_battery::spawn_after(1.minutes()).unwrap();
+        }
+        crate::tasks::check_standby_image::spawn_after(30.secs()).unwrap();
         crate::tasks::validate::spawn().unwrap();
 
         (Shared {
@@ -208,10 +298,14 @@ pub fn init(mut ctx: crate::tasks::init::Context) -> (Shared, Local, crate::task
             touchpanel,
             external_flash,
             internal_flash,
+            filesystem: None,
             bluetooth,
             battery,
             clock,
             mcuboot,
+            motor,
+            settings,
+            datalog,
 
             current_screen: screen,
         }, Local {}, crate::tasks::init::Monotonics(timer0))
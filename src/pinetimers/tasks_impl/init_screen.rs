@@ -1,12 +1,13 @@
-use rtic::mutex_prelude::TupleExt04;
+use rtic::mutex_prelude::TupleExt05;
 
 pub fn init_screen(ctx: crate::tasks::init_screen::Context) {
     (
         ctx.shared.display,
         ctx.shared.current_screen,
         ctx.shared.clock,
-        ctx.shared.mcuboot
-    ).lock(|display, current_screen, clock, mcuboot| {
-        current_screen.draw_init(display, clock, mcuboot);
+        ctx.shared.mcuboot,
+        ctx.shared.settings,
+    ).lock(|display, current_screen, clock, mcuboot, settings| {
+        current_screen.draw_init(display, clock, mcuboot, settings.get());
     });
 }
@@ -0,0 +1,28 @@
+use rtic::mutex_prelude::TupleExt02;
+
+use fugit::ExtU32;
+
+use alloc::boxed::Box;
+
+use crate::drivers::bluetooth::AlertLevel;
+use crate::ui::screen::{Screen, ScreenLinkLost};
+
+pub fn link_lost(ctx: crate::tasks::link_lost::Context, alert_level: AlertLevel) {
+    (
+        ctx.shared.motor,
+        ctx.shared.settings,
+    ).lock(|motor, settings| {
+        if !settings.get().do_not_disturb {
+            let duration_ms: u32 = match alert_level {
+                AlertLevel::HighAlert => 1000,
+                _ => 200,
+            };
+
+            motor.set_vibrating(true);
+            // A stop may already be scheduled, which is fine
+            crate::tasks::stop_vibration::spawn_after(duration_ms.millis()).ok();
+        }
+    });
+
+    crate::tasks::transition::spawn(Box::new(ScreenLinkLost::new())).ok();
+}
@@ -0,0 +1,35 @@
+use rtic::mutex_prelude::TupleExt04;
+
+use fugit::ExtU32;
+
+use rtt_target::rprintln;
+
+use crate::drivers::datalog::{DataPoint, Record};
+
+pub fn log_battery(ctx: crate::tasks::log_battery::Context) {
+    crate::tasks::log_battery::spawn_after(10.minutes()).unwrap();
+
+    (
+        ctx.shared.battery,
+        ctx.shared.clock,
+        ctx.shared.datalog,
+        ctx.shared.external_flash,
+    ).lock(|battery, clock, datalog, external_flash| {
+        // Not mounted
+        let datalog = match datalog {
+            Some(datalog) => datalog,
+            None => return,
+        };
+
+        let millivolts = (battery.get_voltage() * 1000.0) as u32;
+
+        // Samples from before the phone set the time are kept, but flagged
+        if let Err(e) = datalog.append(external_flash, Record {
+            timestamp: clock.datetime.timestamp(),
+            approximate: clock.approximate,
+            point: DataPoint::BatteryVoltage(millivolts),
+        }) {
+            rprintln!("Could not log the battery voltage: {:?}", e);
+        }
+    });
+}
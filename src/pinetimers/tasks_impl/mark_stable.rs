@@ -0,0 +1,7 @@
+use crate::drivers::crashlog;
+
+// Running for a while without crashing, so crashes from here on aren't a
+// reason for safe mode on the next boot
+pub fn mark_stable(_ctx: crate::tasks::mark_stable::Context) {
+    crashlog::mark_stable();
+}
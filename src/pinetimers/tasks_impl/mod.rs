@@ -11,10 +11,28 @@ mod ble_radio;
 mod ble_worker;
 mod ble_timer;
 mod ble_update;
+mod ble_beacon;
+mod ble_rotate_address;
 mod set_time;
 mod pet_watchdog;
 mod validate;
 mod reboot;
+mod link_lost;
+mod stop_vibration;
+mod toggle_do_not_disturb;
+mod checkpoint_time;
+mod power_down_flash;
+mod log_battery;
+mod check_standby_image;
+mod confirm_image;
+mod set_beacon;
+mod toggle_beacon;
+mod mount_filesystem;
+mod mark_stable;
+mod show_battery_history;
+mod revert_image;
+mod change_brightness;
+mod set_ble_name;
 
 pub use init::init;
 pub use idle::idle;
@@ -29,7 +47,25 @@ pub use ble_radio::ble_radio;
 pub use ble_worker::ble_worker;
 pub use ble_timer::ble_timer;
 pub use ble_update::ble_update;
+pub use ble_beacon::ble_beacon;
+pub use ble_rotate_address::ble_rotate_address;
 pub use set_time::set_time;
 pub use pet_watchdog::pet_watchdog;
 pub use validate::validate;
 pub use reboot::reboot;
+pub use link_lost::link_lost;
+pub use stop_vibration::stop_vibration;
+pub use toggle_do_not_disturb::toggle_do_not_disturb;
+pub use checkpoint_time::checkpoint_time;
+pub use power_down_flash::power_down_flash;
+pub use log_battery::log_battery;
+pub use check_standby_image::check_standby_image;
+pub use confirm_image::confirm_image;
+pub use set_beacon::set_beacon;
+pub use toggle_beacon::toggle_beacon;
+pub use mount_filesystem::mount_filesystem;
+pub use mark_stable::mark_stable;
+pub use show_battery_history::show_battery_history;
+pub use revert_image::revert_image;
+pub use change_brightness::change_brightness;
+pub use set_ble_name::set_ble_name;
@@ -0,0 +1,39 @@
+use rtic::mutex_prelude::TupleExt03;
+
+use rtt_target::rprintln;
+
+use crate::drivers::filesystem::Filesystem;
+use crate::drivers::flash::partition::USER_FILESYSTEM;
+use crate::drivers::crashlog;
+
+// Mounting reads every record header in USER_FILESYSTEM, which can take
+// longer than the watchdog allows, so it is done here instead of in init and
+// the watchdog is petted after every block. The filesystem stays None if it
+// can't be mounted.
+pub fn mount_filesystem(ctx: crate::tasks::mount_filesystem::Context) {
+    (
+        ctx.shared.external_flash,
+        ctx.shared.filesystem,
+        ctx.shared.watchdog_handles,
+    ).lock(|external_flash, filesystem, watchdog_handles| {
+        let mounted = Filesystem::mount_or_format(
+            external_flash,
+            USER_FILESYSTEM,
+            || {
+                for watchdog_handle in watchdog_handles.iter_mut() {
+                    watchdog_handle.pet();
+                }
+            },
+        );
+
+        match mounted {
+            Ok(mut mounted) => {
+                if let Err(e) = crashlog::save(&mut mounted, external_flash) {
+                    rprintln!("Could not save the crash log: {:?}", e);
+                }
+                *filesystem = Some(mounted);
+            },
+            Err(e) => rprintln!("Could not mount the filesystem: {:?}", e),
+        }
+    });
+}
@@ -0,0 +1,13 @@
+use rtic::Mutex;
+
+use fugit::ExtU32;
+
+// Put the external flash in Deep Power-Down when it hasn't been used for a
+// while, it wakes up again on the next access
+pub fn power_down_flash(mut ctx: crate::tasks::power_down_flash::Context) {
+    crate::tasks::power_down_flash::spawn_after(5.secs()).unwrap();
+    ctx.shared.external_flash.lock(|external_flash| {
+        // If the SPI is busy (display), just try again next time
+        external_flash.power_down_if_idle().ok();
+    });
+}
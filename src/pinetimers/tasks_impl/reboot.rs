@@ -1,5 +1,20 @@
 use nrf52832_hal::pac::SCB;
 
-pub fn reboot(_ctx: crate::tasks::reboot::Context) {
+use rtic::Mutex;
+
+use rtt_target::rprintln;
+
+pub fn reboot(mut ctx: crate::tasks::reboot::Context) {
+    // Keep the time as up to date as possible across the reset
+    ctx.shared.clock.lock(|clock| {
+        clock.tick();
+    });
+
+    ctx.shared.external_flash.lock(|external_flash| {
+        if let Err(e) = external_flash.prepare_for_reset() {
+            rprintln!("Could not wake up the external flash: {:?}", e);
+        }
+    });
+
     SCB::sys_reset();
 }
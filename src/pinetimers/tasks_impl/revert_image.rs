@@ -0,0 +1,36 @@
+use rtic::mutex_prelude::TupleExt02;
+
+use rtt_target::rprintln;
+
+use crate::drivers::mcuboot::MCUBoot;
+
+// Go back to the previous image in STANDBY_IMAGE and reboot into it, from
+// the firmware screen. Checking it reads the whole slot, so the watchdog is
+// petted while reading.
+pub fn revert_image(ctx: crate::tasks::revert_image::Context) {
+    let queued = (
+        ctx.shared.external_flash,
+        ctx.shared.watchdog_handles,
+    ).lock(|external_flash, watchdog_handles| {
+        let reverted = MCUBoot::request_revert(external_flash, || {
+            for watchdog_handle in watchdog_handles.iter_mut() {
+                watchdog_handle.pet();
+            }
+        });
+
+        match reverted {
+            Ok(version) => {
+                rprintln!("Reverting to v{}", version);
+                true
+            },
+            Err(e) => {
+                rprintln!("Not reverting: {:?}", e);
+                false
+            },
+        }
+    });
+
+    if queued {
+        crate::tasks::reboot::spawn().ok();
+    }
+}
@@ -1,14 +1,30 @@
 use rtic::mutex_prelude::TupleExt03;
 
+use rtt_target::rprintln;
+
+use crate::drivers::selftest;
+
 pub fn self_test(ctx: crate::tasks::self_test::Context) {
     (
         ctx.shared.internal_flash,
         ctx.shared.external_flash,
         ctx.shared.mcuboot,
     ).lock(|internal_flash, external_flash, mcuboot| {
-        external_flash.self_test().unwrap();
+        let report = selftest::run(internal_flash, external_flash, mcuboot);
+        report.print();
+
+        if !report.passed() {
+            // MCUBoot reverts to the previous image on the next reboot
+            rprintln!("Selftest failed, not marking image as valid:");
+            for failure in report.failures() {
+                rprintln!("    {}", failure.name);
+            }
+            return;
+        }
 
-        rtt_target::rprintln!("Selftest succeeded, marking image as valid");
-        mcuboot.mark_valid(internal_flash)
+        rprintln!("Selftest succeeded, marking image as valid");
+        if let Err(e) = mcuboot.confirm(internal_flash) {
+            rprintln!("Marking image as valid failed: {:?}", e);
+        }
     });
 }
@@ -0,0 +1,29 @@
+use rtic::mutex_prelude::TupleExt02;
+
+use rtt_target::rprintln;
+
+use fugit::ExtU32;
+
+use crate::drivers::bluetooth::BeaconConfig;
+use crate::drivers::settings::Setting;
+
+// Store a beacon written over BLE and switch to it. Bluetooth is only set up
+// at boot, so this reboots (after a second, so the write still gets its
+// response).
+pub fn set_beacon(ctx: crate::tasks::set_beacon::Context, config: BeaconConfig) {
+    let saved = (
+        ctx.shared.settings,
+        ctx.shared.external_flash,
+    ).lock(|settings, external_flash| {
+        settings.set(external_flash, Setting::Beacon(Some(config)))
+            .and_then(|_| settings.set(external_flash, Setting::BeaconEnabled(true)))
+    });
+
+    match saved {
+        Ok(()) => {
+            rprintln!("Beacon saved, rebooting");
+            crate::tasks::reboot::spawn_after(1.secs()).ok();
+        },
+        Err(e) => rprintln!("Could not save beacon: {:?}", e),
+    }
+}
@@ -0,0 +1,22 @@
+use rtic::mutex_prelude::TupleExt03;
+
+use rtt_target::rprintln;
+
+use crate::drivers::settings::Setting;
+
+use alloc::string::String;
+
+// Store a device name written over BLE. Advertising picks it up when it
+// restarts, so it doesn't change while connected.
+pub fn set_ble_name(ctx: crate::tasks::set_ble_name::Context, name: String) {
+    (
+        ctx.shared.bluetooth,
+        ctx.shared.settings,
+        ctx.shared.external_flash,
+    ).lock(|bluetooth, settings, external_flash| {
+        if let Err(e) = settings.set(external_flash, Setting::BleName(name.clone())) {
+            rprintln!("Could not save the BLE name: {:?}", e);
+        }
+        bluetooth.set_device_name(name);
+    });
+}
@@ -1,9 +1,27 @@
-use rtic::Mutex;
+use rtic::mutex_prelude::TupleExt03;
 
 use chrono::NaiveDateTime;
 
-pub fn set_time(mut ctx: crate::tasks::set_time::Context, time: NaiveDateTime) {
-    ctx.shared.clock.lock(|clock| {
-        clock.datetime = time;
+use rtt_target::rprintln;
+
+use crate::drivers::settings::Setting;
+
+pub fn set_time(ctx: crate::tasks::set_time::Context, time: NaiveDateTime) {
+    (
+        ctx.shared.clock,
+        ctx.shared.settings,
+        ctx.shared.external_flash,
+    ).lock(|clock, settings, external_flash| {
+        clock.set(time);
+
+        // Checkpoint right away, the time may have been set back. If this
+        // fails, checkpoint_time tries again.
+        match settings.set(external_flash, Setting::TimeCheckpoint(time.timestamp())) {
+            Ok(()) => clock.checkpoint_pending = false,
+            Err(e) => rprintln!("Could not save the time: {:?}", e),
+        }
     });
+
+    // The time is no longer approximate
+    crate::tasks::init_screen::spawn().ok();
 }
@@ -0,0 +1,33 @@
+use rtic::mutex_prelude::TupleExt03;
+
+use rtt_target::rprintln;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::ui::screen::{ScreenBattery, BATTERY_HISTORY_SECONDS};
+
+// Read the battery samples of the last day from the data log and show them
+pub fn show_battery_history(ctx: crate::tasks::show_battery_history::Context) {
+    let (records, now) = (
+        ctx.shared.clock,
+        ctx.shared.datalog,
+        ctx.shared.external_flash,
+    ).lock(|clock, datalog, external_flash| {
+        let now = clock.datetime.timestamp();
+
+        // Not mounted, shows up as no samples
+        let records = match datalog {
+            Some(datalog) => datalog.query(external_flash, now - BATTERY_HISTORY_SECONDS, now + 1)
+                .unwrap_or_else(|e| {
+                    rprintln!("Could not read the data log: {:?}", e);
+                    Vec::new()
+                }),
+            None => Vec::new(),
+        };
+
+        (records, now)
+    });
+
+    crate::tasks::transition::spawn(Box::new(ScreenBattery::with_records(&records, now))).ok();
+}
@@ -0,0 +1,7 @@
+use rtic::Mutex;
+
+pub fn stop_vibration(mut ctx: crate::tasks::stop_vibration::Context) {
+    ctx.shared.motor.lock(|motor| {
+        motor.set_vibrating(false);
+    });
+}
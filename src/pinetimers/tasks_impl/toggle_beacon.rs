@@ -0,0 +1,31 @@
+use rtic::mutex_prelude::TupleExt02;
+
+use rtt_target::rprintln;
+
+use crate::drivers::settings::Setting;
+
+// Switch between the stored beacon and connectable advertising, from the
+// beacon screen. Takes effect after a reboot, like set_beacon.
+pub fn toggle_beacon(ctx: crate::tasks::toggle_beacon::Context) {
+    let saved = (
+        ctx.shared.settings,
+        ctx.shared.external_flash,
+    ).lock(|settings, external_flash| {
+        if settings.get().beacon.is_none() {
+            return false;
+        }
+
+        let enabled = !settings.get().beacon_enabled;
+        match settings.set(external_flash, Setting::BeaconEnabled(enabled)) {
+            Ok(()) => true,
+            Err(e) => {
+                rprintln!("Could not save beacon mode: {:?}", e);
+                false
+            },
+        }
+    });
+
+    if saved {
+        crate::tasks::reboot::spawn().ok();
+    }
+}
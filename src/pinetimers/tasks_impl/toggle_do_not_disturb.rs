@@ -0,0 +1,21 @@
+use rtic::mutex_prelude::TupleExt02;
+
+use rtt_target::rprintln;
+
+use crate::drivers::settings::Setting;
+
+pub fn toggle_do_not_disturb(ctx: crate::tasks::toggle_do_not_disturb::Context) {
+    (
+        ctx.shared.settings,
+        ctx.shared.external_flash,
+    ).lock(|settings, external_flash| {
+        let do_not_disturb = !settings.get().do_not_disturb;
+        if let Err(e) = settings.set(external_flash, Setting::DoNotDisturb(do_not_disturb)) {
+            rprintln!("Could not save do not disturb: {:?}", e);
+        }
+        rprintln!("Do not disturb: {}", settings.get().do_not_disturb);
+    });
+
+    // Update the indicator on the current screen
+    crate::tasks::init_screen::spawn().ok();
+}
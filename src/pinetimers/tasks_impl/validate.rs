@@ -1,9 +1,18 @@
 use rtic::Mutex;
 
+use rtt_target::rprintln;
+
+use crate::drivers::mcuboot::ImageState;
+
 pub fn validate(mut ctx: crate::tasks::validate::Context) {
     ctx.shared.mcuboot.lock(|mcuboot| {
-        if !mcuboot.footer.is_valid {
-            crate::tasks::self_test::spawn().unwrap();
+        match mcuboot.trailer.state() {
+            // MCUBoot reverts unless the image confirms itself
+            ImageState::Testing => crate::tasks::self_test::spawn().unwrap(),
+            ImageState::Corrupt => {
+                rprintln!("Invalid MCUBoot trailer, can't confirm the image: {:?}", mcuboot.trailer);
+            },
+            ImageState::Confirmed | ImageState::Permanent => {},
         }
     });
 
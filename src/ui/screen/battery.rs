@@ -0,0 +1,136 @@
+use crate::ui::screen::{Screen, ScreenMain};
+use crate::drivers::touchpanel::{TouchPanelEventHandler, TouchPoint};
+use crate::drivers::display::DisplaySupported;
+use crate::drivers::clock::Clock;
+use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::settings::Settings;
+use crate::drivers::datalog::{Record, DataPoint};
+
+use crate::pinetimers::ConnectedRtc;
+
+use embedded_graphics::prelude::{DrawTarget, Point, Drawable};
+use embedded_graphics::pixelcolor::RgbColor;
+use embedded_graphics::primitives::{Line, Primitive, PrimitiveStyle};
+use embedded_graphics::text::{Text, Baseline};
+use embedded_graphics::mono_font::ascii::{FONT_10X20, FONT_6X10};
+use embedded_graphics::mono_font::MonoTextStyle;
+
+use core::marker::PhantomData;
+use core::fmt::Debug;
+
+use alloc::sync::Arc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::format;
+
+// Samples of the last 24 hours, from 3.3 V (bottom) to 4.2 V (top)
+pub const BATTERY_HISTORY_SECONDS: i64 = 24 * 60 * 60;
+const MIN_MILLIVOLTS: u32 = 3300;
+const MAX_MILLIVOLTS: u32 = 4200;
+const CHART_TOP: i32 = 100;
+const CHART_BOTTOM: i32 = 230;
+
+#[derive(Debug)]
+pub struct ScreenBattery<COLOR> {
+    event_handler: Arc<ScreenBatteryEventHandler>,
+    // Battery voltage samples (mV) oldest first, with the time before now
+    samples: Vec<(i64, u32, bool)>,
+    _marker: PhantomData<COLOR>
+}
+
+#[derive(Debug)]
+pub struct ScreenBatteryEventHandler {}
+
+impl TouchPanelEventHandler for ScreenBatteryEventHandler {
+    fn on_event(&self, _point: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenMain::new())).unwrap();
+    }
+}
+
+impl<COLOR> ScreenBattery<COLOR> {
+    // `records` from the data log, `now` is the timestamp they end at
+    pub fn with_records(records: &[Record], now: i64) -> ScreenBattery<COLOR> {
+        let samples = records.iter()
+            .filter_map(|record| match record.point {
+                DataPoint::BatteryVoltage(millivolts) => Some((now - record.timestamp, millivolts, record.approximate)),
+                _ => None,
+            })
+            .collect();
+
+        ScreenBattery {
+            event_handler: Arc::new(ScreenBatteryEventHandler {}),
+            samples,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<DISPLAY, COLOR> Screen<DISPLAY> for ScreenBattery<DISPLAY>
+where
+    DISPLAY: DisplaySupported<COLOR> + DrawTarget<Color = COLOR> + Send + Debug,
+    <DISPLAY as DrawTarget>::Error: Debug,
+    COLOR: RgbColor
+{
+    fn new() -> ScreenBattery<DISPLAY> {
+        ScreenBattery::with_records(&[], 0)
+    }
+
+    fn get_event_handler(&self) -> Arc<dyn TouchPanelEventHandler> {
+        return self.event_handler.clone();
+    }
+
+    fn draw_init(&mut self, display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {
+        display.clear(COLOR::BLACK).unwrap();
+
+        let title_style = MonoTextStyle::new(&FONT_10X20, COLOR::WHITE);
+        let warning_style = MonoTextStyle::new(&FONT_10X20, COLOR::YELLOW);
+        let note_style = MonoTextStyle::new(&FONT_6X10, COLOR::YELLOW);
+
+        Text::with_baseline("Battery (24h)", Point::new(0, 0), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        let (latest, min, max) = match (
+            self.samples.last(),
+            self.samples.iter().map(|(_, millivolts, _)| *millivolts).min(),
+            self.samples.iter().map(|(_, millivolts, _)| *millivolts).max(),
+        ) {
+            (Some((_, latest, _)), Some(min), Some(max)) => (*latest, min, max),
+            _ => {
+                Text::with_baseline("No samples", Point::new(0, 30), warning_style, Baseline::Top)
+                    .draw(display)
+                    .unwrap();
+                return;
+            },
+        };
+
+        Text::with_baseline(&format!("Last {} mV", latest), Point::new(0, 30), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+        Text::with_baseline(&format!("{}-{} mV", min, max), Point::new(0, 55), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        if self.samples.iter().any(|(_, _, approximate)| *approximate) {
+            Text::with_baseline("Yellow: time was not set", Point::new(0, 82), note_style, Baseline::Top)
+                .draw(display)
+                .unwrap();
+        }
+
+        // Each sample as a bar from the bottom of the chart, now is on the
+        // right
+        for (age, millivolts, approximate) in self.samples.iter() {
+            let x = 239 - (age * 239 / BATTERY_HISTORY_SECONDS) as i32;
+            let level = (*millivolts).clamp(MIN_MILLIVOLTS, MAX_MILLIVOLTS) - MIN_MILLIVOLTS;
+            let y = CHART_BOTTOM - (level * (CHART_BOTTOM - CHART_TOP) as u32 / (MAX_MILLIVOLTS - MIN_MILLIVOLTS)) as i32;
+            let color = if *approximate { COLOR::YELLOW } else { COLOR::GREEN };
+
+            Line::new(Point::new(x, CHART_BOTTOM), Point::new(x, y))
+                .into_styled(PrimitiveStyle::with_stroke(color, 1))
+                .draw(display)
+                .unwrap();
+        }
+    }
+
+    fn draw_update(&mut self, _display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {}
+}
@@ -0,0 +1,126 @@
+use crate::ui::screen::{Screen, ScreenMain};
+use crate::drivers::touchpanel::{TouchPanelEventHandler, TouchPoint};
+use crate::drivers::display::DisplaySupported;
+use crate::drivers::clock::Clock;
+use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::settings::Settings;
+use crate::drivers::bluetooth::BeaconKind;
+
+use crate::pinetimers::ConnectedRtc;
+
+use embedded_graphics::prelude::{DrawTarget, Point, Drawable};
+use embedded_graphics::pixelcolor::RgbColor;
+use embedded_graphics::text::{Text, Alignment, Baseline, TextStyleBuilder};
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyle;
+
+use core::marker::PhantomData;
+use core::fmt::Debug;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::sync::Arc;
+use alloc::boxed::Box;
+use alloc::format;
+
+#[derive(Debug)]
+pub struct ScreenBeacon<COLOR> {
+    event_handler: Arc<ScreenBeaconEventHandler>,
+    _marker: PhantomData<COLOR>
+}
+
+#[derive(Debug)]
+pub struct ScreenBeaconEventHandler {
+    // Whether there is a beacon to switch to, set when drawing
+    configured: AtomicBool,
+}
+
+impl TouchPanelEventHandler for ScreenBeaconEventHandler {
+    fn on_slide(&self, _point: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenMain::new())).unwrap();
+    }
+
+    // Switches mode and reboots, so it needs a long press
+    fn on_click_long(&self, _point: TouchPoint) {
+        if self.configured.load(Ordering::Relaxed) {
+            crate::tasks::toggle_beacon::spawn().ok();
+        }
+    }
+}
+
+impl<DISPLAY, COLOR> Screen<DISPLAY> for ScreenBeacon<DISPLAY>
+where
+    DISPLAY: DisplaySupported<COLOR> + DrawTarget<Color = COLOR> + Send + Debug,
+    <DISPLAY as DrawTarget>::Error: Debug,
+    COLOR: RgbColor
+{
+    fn new() -> ScreenBeacon<DISPLAY> {
+        ScreenBeacon {
+            event_handler: Arc::new(ScreenBeaconEventHandler {
+                configured: AtomicBool::new(false),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    fn get_event_handler(&self) -> Arc<dyn TouchPanelEventHandler> {
+        return self.event_handler.clone();
+    }
+
+    fn draw_init(&mut self, display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, settings: &Settings) {
+        display.clear(COLOR::BLACK).unwrap();
+
+        let title_style = MonoTextStyle::new(&FONT_10X20, COLOR::WHITE);
+        let good_style = MonoTextStyle::new(&FONT_10X20, COLOR::GREEN);
+        let warning_style = MonoTextStyle::new(&FONT_10X20, COLOR::YELLOW);
+
+        Text::with_baseline("Beacon", Point::new(0, 0), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        self.event_handler.configured.store(settings.beacon.is_some(), Ordering::Relaxed);
+
+        let config = match &settings.beacon {
+            Some(config) => config,
+            None => {
+                Text::with_baseline("Not configured", Point::new(0, 30), warning_style, Baseline::Top)
+                    .draw(display)
+                    .unwrap();
+                return;
+            },
+        };
+
+        let kind = match config.kind {
+            BeaconKind::IBeacon { .. } => "iBeacon",
+            BeaconKind::EddystoneUid { .. } => "Eddystone-UID",
+            BeaconKind::EddystoneUrl { .. } => "Eddystone-URL",
+        };
+        Text::with_baseline(kind, Point::new(0, 30), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+        Text::with_baseline(&format!("Every {} ms", config.interval_ms), Point::new(0, 55), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        let (state, state_style) = match settings.beacon_enabled {
+            true => ("On", good_style),
+            false => ("Off", warning_style),
+        };
+        Text::with_baseline(state, Point::new(0, 80), state_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        let action_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Bottom)
+            .build();
+        let action = match settings.beacon_enabled {
+            true => "Hold to turn off",
+            false => "Hold to turn on",
+        };
+        Text::with_text_style(action, Point::new(120, 240), warning_style, action_style)
+            .draw(display)
+            .unwrap();
+    }
+
+    fn draw_update(&mut self, _display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {}
+}
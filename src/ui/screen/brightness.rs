@@ -0,0 +1,107 @@
+use crate::ui::screen::{Screen, ScreenMain};
+use crate::drivers::touchpanel::{TouchPanelEventHandler, TouchPoint};
+use crate::drivers::display::DisplaySupported;
+use crate::drivers::clock::Clock;
+use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::settings::Settings;
+
+use crate::pinetimers::ConnectedRtc;
+
+use embedded_graphics::prelude::{DrawTarget, Point, Size, Drawable, Primitive};
+use embedded_graphics::pixelcolor::RgbColor;
+use embedded_graphics::primitives::{Rectangle, PrimitiveStyle};
+use embedded_graphics::text::{Text, Alignment, Baseline, TextStyleBuilder};
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyle;
+
+use core::marker::PhantomData;
+use core::fmt::Debug;
+
+use alloc::sync::Arc;
+use alloc::boxed::Box;
+use alloc::format;
+
+#[derive(Debug)]
+pub struct ScreenBrightness<COLOR> {
+    event_handler: Arc<ScreenBrightnessEventHandler>,
+    _marker: PhantomData<COLOR>
+}
+
+#[derive(Debug)]
+pub struct ScreenBrightnessEventHandler {}
+
+impl TouchPanelEventHandler for ScreenBrightnessEventHandler {
+    fn on_slide_up(&self, _point: TouchPoint) {
+        crate::tasks::change_brightness::spawn(true).ok();
+    }
+
+    fn on_slide_down(&self, _point: TouchPoint) {
+        crate::tasks::change_brightness::spawn(false).ok();
+    }
+
+    fn on_slide_left(&self, _point: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenMain::new())).unwrap();
+    }
+
+    fn on_slide_right(&self, _point: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenMain::new())).unwrap();
+    }
+}
+
+impl<DISPLAY, COLOR> Screen<DISPLAY> for ScreenBrightness<DISPLAY>
+where
+    DISPLAY: DisplaySupported<COLOR> + DrawTarget<Color = COLOR> + Send + Debug,
+    <DISPLAY as DrawTarget>::Error: Debug,
+    COLOR: RgbColor
+{
+    fn new() -> ScreenBrightness<DISPLAY> {
+        ScreenBrightness {
+            event_handler: Arc::new(ScreenBrightnessEventHandler {}),
+            _marker: PhantomData,
+        }
+    }
+
+    fn get_event_handler(&self) -> Arc<dyn TouchPanelEventHandler> {
+        return self.event_handler.clone();
+    }
+
+    fn draw_init(&mut self, display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, settings: &Settings) {
+        display.clear(COLOR::BLACK).unwrap();
+
+        let title_style = MonoTextStyle::new(&FONT_10X20, COLOR::WHITE);
+        let hint_style = MonoTextStyle::new(&FONT_10X20, COLOR::YELLOW);
+
+        Text::with_baseline("Brightness", Point::new(0, 0), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+        Text::with_baseline(&format!("{} / 7", settings.brightness), Point::new(0, 30), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        // One bar per level, filled up to the current one
+        for level in 1..=7 {
+            let style = match level <= settings.brightness {
+                true => PrimitiveStyle::with_fill(COLOR::WHITE),
+                false => PrimitiveStyle::with_stroke(COLOR::WHITE, 1),
+            };
+            let height = 20 + level as u32 * 10;
+            Rectangle::new(
+                Point::new(15 + (level as i32 - 1) * 30, 190 - height as i32),
+                Size::new(20, height),
+            )
+                .into_styled(style)
+                .draw(display)
+                .unwrap();
+        }
+
+        let hint_text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Bottom)
+            .build();
+        Text::with_text_style("Slide up or down", Point::new(120, 240), hint_style, hint_text_style)
+            .draw(display)
+            .unwrap();
+    }
+
+    fn draw_update(&mut self, _display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {}
+}
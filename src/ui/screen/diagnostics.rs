@@ -0,0 +1,101 @@
+use crate::ui::screen::{Screen, ScreenMain};
+use crate::drivers::touchpanel::{TouchPanelEventHandler, TouchPoint};
+use crate::drivers::display::DisplaySupported;
+use crate::drivers::clock::Clock;
+use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::settings::Settings;
+use crate::drivers::crashlog::{self, CrashKind};
+
+use crate::pinetimers::ConnectedRtc;
+
+use embedded_graphics::prelude::{DrawTarget, Point, Drawable};
+use embedded_graphics::pixelcolor::RgbColor;
+use embedded_graphics::text::{Text, Baseline};
+use embedded_graphics::mono_font::ascii::{FONT_10X20, FONT_6X10};
+use embedded_graphics::mono_font::MonoTextStyle;
+
+use core::marker::PhantomData;
+use core::fmt::Debug;
+
+use alloc::sync::Arc;
+use alloc::boxed::Box;
+
+#[derive(Debug)]
+pub struct ScreenDiagnostics<COLOR> {
+    event_handler: Arc<ScreenDiagnosticsEventHandler>,
+    _marker: PhantomData<COLOR>
+}
+
+#[derive(Debug)]
+pub struct ScreenDiagnosticsEventHandler {}
+
+impl TouchPanelEventHandler for ScreenDiagnosticsEventHandler {
+    fn on_event(&self, _point: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenMain::new())).unwrap();
+    }
+}
+
+impl<DISPLAY, COLOR> Screen<DISPLAY> for ScreenDiagnostics<DISPLAY>
+where
+    DISPLAY: DisplaySupported<COLOR> + DrawTarget<Color = COLOR> + Send + Debug,
+    <DISPLAY as DrawTarget>::Error: Debug,
+    COLOR: RgbColor
+{
+    fn new() -> ScreenDiagnostics<DISPLAY> {
+        ScreenDiagnostics {
+            event_handler: Arc::new(ScreenDiagnosticsEventHandler {}),
+            _marker: PhantomData,
+        }
+    }
+
+    fn get_event_handler(&self) -> Arc<dyn TouchPanelEventHandler> {
+        return self.event_handler.clone();
+    }
+
+    fn draw_init(&mut self, display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {
+        display.clear(COLOR::BLACK).unwrap();
+
+        let title_style = MonoTextStyle::new(&FONT_10X20, COLOR::WHITE);
+        let record_style = MonoTextStyle::new(&FONT_10X20, COLOR::RED);
+        let message_style = MonoTextStyle::new(&FONT_6X10, COLOR::WHITE);
+
+        let title = match crashlog::safe_mode() {
+            true => "Crash log (safe mode)",
+            false => "Crash log",
+        };
+        Text::with_baseline(title, Point::new(0, 0), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        let records = crashlog::records();
+
+        if records.is_empty() {
+            Text::with_baseline("No crashes", Point::new(0, 30), record_style, Baseline::Top)
+                .draw(display)
+                .unwrap();
+            return;
+        }
+
+        // Each record gets a line, with the start of the message below it
+        // for panics
+        let mut y = 30;
+        for record in records {
+            Text::with_baseline(&record.summary(), Point::new(0, y), record_style, Baseline::Top)
+                .draw(display)
+                .unwrap();
+            y += 20;
+
+            if record.kind() == Some(CrashKind::Panic) {
+                let message = record.message();
+                let message = &message[..message.char_indices().nth(40).map_or(message.len(), |(i, _)| i)];
+
+                Text::with_baseline(message, Point::new(0, y), message_style, Baseline::Top)
+                    .draw(display)
+                    .unwrap();
+                y += 12;
+            }
+        }
+    }
+
+    fn draw_update(&mut self, _display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {}
+}
@@ -0,0 +1,156 @@
+use crate::ui::screen::{Screen, ScreenMain};
+use crate::drivers::touchpanel::{TouchPanelEventHandler, TouchPoint};
+use crate::drivers::display::DisplaySupported;
+use crate::drivers::clock::Clock;
+use crate::drivers::mcuboot::{MCUBoot, ImageState};
+use crate::drivers::settings::Settings;
+
+use crate::pinetimers::ConnectedRtc;
+
+use embedded_graphics::prelude::{DrawTarget, Point, Drawable};
+use embedded_graphics::pixelcolor::RgbColor;
+use embedded_graphics::text::{Text, Alignment, Baseline, TextStyleBuilder};
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyle;
+
+use core::marker::PhantomData;
+use core::fmt::Debug;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::sync::Arc;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+
+// The actions are along the bottom, confirm on the left and revert on the
+// right. They need a long press, so they don't happen by accident.
+const ACTIONS_Y: u16 = 200;
+
+#[derive(Debug)]
+pub struct ScreenFirmware<COLOR> {
+    event_handler: Arc<ScreenFirmwareEventHandler>,
+    _marker: PhantomData<COLOR>
+}
+
+#[derive(Debug)]
+pub struct ScreenFirmwareEventHandler {
+    // Whether the actions are shown, set when drawing
+    can_confirm: AtomicBool,
+    can_revert: AtomicBool,
+}
+
+impl TouchPanelEventHandler for ScreenFirmwareEventHandler {
+    fn on_slide(&self, _point: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenMain::new())).unwrap();
+    }
+
+    // Only the actions on the screen, the tasks check again
+    fn on_click_long(&self, point: TouchPoint) {
+        if point.y < ACTIONS_Y {
+            return;
+        }
+
+        if point.x < 120 && self.can_confirm.load(Ordering::Relaxed) {
+            crate::tasks::confirm_image::spawn().ok();
+        } else if point.x >= 120 && self.can_revert.load(Ordering::Relaxed) {
+            crate::tasks::revert_image::spawn().ok();
+        }
+    }
+}
+
+impl<DISPLAY, COLOR> Screen<DISPLAY> for ScreenFirmware<DISPLAY>
+where
+    DISPLAY: DisplaySupported<COLOR> + DrawTarget<Color = COLOR> + Send + Debug,
+    <DISPLAY as DrawTarget>::Error: Debug,
+    COLOR: RgbColor
+{
+    fn new() -> ScreenFirmware<DISPLAY> {
+        ScreenFirmware {
+            event_handler: Arc::new(ScreenFirmwareEventHandler {
+                can_confirm: AtomicBool::new(false),
+                can_revert: AtomicBool::new(false),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    fn get_event_handler(&self) -> Arc<dyn TouchPanelEventHandler> {
+        return self.event_handler.clone();
+    }
+
+    fn draw_init(&mut self, display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, mcuboot: &MCUBoot, _: &Settings) {
+        display.clear(COLOR::BLACK).unwrap();
+
+        let title_style = MonoTextStyle::new(&FONT_10X20, COLOR::WHITE);
+        let good_style = MonoTextStyle::new(&FONT_10X20, COLOR::GREEN);
+        let warning_style = MonoTextStyle::new(&FONT_10X20, COLOR::YELLOW);
+        let bad_style = MonoTextStyle::new(&FONT_10X20, COLOR::RED);
+
+        // Running image
+        Text::with_baseline("Running", Point::new(0, 0), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+        Text::with_baseline(&mcuboot.version_string(), Point::new(0, 25), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        let (state, state_style) = match mcuboot.trailer.state() {
+            ImageState::Confirmed => ("Confirmed", good_style),
+            ImageState::Permanent => ("Permanent", good_style),
+            ImageState::Testing => ("Not confirmed", warning_style),
+            ImageState::Corrupt => ("Invalid trailer", bad_style),
+        };
+        Text::with_baseline(state, Point::new(0, 50), state_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        // Standby image
+        Text::with_baseline("Standby", Point::new(0, 100), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+
+        let (version, validity) = match mcuboot.standby_image {
+            None => (String::from("Checking..."), None),
+            Some(standby_image) => match standby_image.version {
+                None => (String::from("No image"), None),
+                Some(version) if standby_image.signed => (format!("v{}", version), Some(("Valid", good_style))),
+                // Not signed with SIGNING_KEY, so it can't be reverted to
+                Some(version) if standby_image.intact => (format!("v{}", version), Some(("Not signed", warning_style))),
+                Some(version) => (format!("v{}", version), Some(("Invalid", bad_style))),
+            },
+        };
+        Text::with_baseline(&version, Point::new(0, 125), title_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+        if let Some((validity, validity_style)) = validity {
+            Text::with_baseline(validity, Point::new(0, 150), validity_style, Baseline::Top)
+                .draw(display)
+                .unwrap();
+        }
+
+        // Actions, only the ones that can do something
+        let can_confirm = mcuboot.trailer.state() == ImageState::Testing;
+        let can_revert = matches!(mcuboot.standby_image, Some(standby_image) if standby_image.signed);
+        self.event_handler.can_confirm.store(can_confirm, Ordering::Relaxed);
+        self.event_handler.can_revert.store(can_revert, Ordering::Relaxed);
+
+        let action_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Bottom)
+            .build();
+
+        if can_confirm {
+            Text::with_text_style("Confirm", Point::new(60, 240), warning_style, action_style)
+                .draw(display)
+                .unwrap();
+        }
+
+        if can_revert {
+            Text::with_text_style("Revert", Point::new(180, 240), warning_style, action_style)
+                .draw(display)
+                .unwrap();
+        }
+    }
+
+    fn draw_update(&mut self, _display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {}
+}
@@ -0,0 +1,73 @@
+use crate::ui::screen::{Screen, ScreenMain};
+use crate::drivers::touchpanel::{TouchPanelEventHandler, TouchPoint};
+use crate::drivers::display::DisplaySupported;
+use crate::drivers::clock::Clock;
+use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::settings::Settings;
+
+use crate::pinetimers::ConnectedRtc;
+
+use embedded_graphics::prelude::{DrawTarget, Point, Drawable};
+use embedded_graphics::pixelcolor::RgbColor;
+use embedded_graphics::text::{Text, Alignment, Baseline, TextStyleBuilder};
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyle;
+
+use core::marker::PhantomData;
+use core::fmt::Debug;
+
+use alloc::sync::Arc;
+use alloc::boxed::Box;
+
+#[derive(Debug)]
+pub struct ScreenLinkLost<COLOR> {
+    event_handler: Arc<ScreenLinkLostEventHandler>,
+    _marker: PhantomData<COLOR>
+}
+
+#[derive(Debug)]
+pub struct ScreenLinkLostEventHandler {}
+
+impl TouchPanelEventHandler for ScreenLinkLostEventHandler {
+    fn on_event(&self, _point: TouchPoint) {
+        crate::tasks::stop_vibration::spawn().ok();
+        crate::tasks::transition::spawn(Box::new(ScreenMain::new())).unwrap();
+    }
+}
+
+impl<DISPLAY, COLOR> Screen<DISPLAY> for ScreenLinkLost<DISPLAY>
+where
+    DISPLAY: DisplaySupported<COLOR> + DrawTarget<Color = COLOR> + Send + Debug,
+    <DISPLAY as DrawTarget>::Error: Debug,
+    COLOR: RgbColor
+{
+    fn new() -> ScreenLinkLost<DISPLAY> {
+        ScreenLinkLost {
+            event_handler: Arc::new(ScreenLinkLostEventHandler {}),
+            _marker: PhantomData,
+        }
+    }
+
+    fn get_event_handler(&self) -> Arc<dyn TouchPanelEventHandler> {
+        return self.event_handler.clone();
+    }
+
+    fn draw_init(&mut self, display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {
+        display.clear(COLOR::BLACK).unwrap();
+
+        let character_style = MonoTextStyle::new(&FONT_10X20, COLOR::RED);
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+
+        Text::with_text_style("Phone", Point::new(120, 105), character_style, text_style)
+            .draw(display)
+            .unwrap();
+        Text::with_text_style("disconnected", Point::new(120, 135), character_style, text_style)
+            .draw(display)
+            .unwrap();
+    }
+
+    fn draw_update(&mut self, _display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {}
+}
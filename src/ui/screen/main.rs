@@ -1,9 +1,10 @@
-use crate::ui::screen::Screen;
+use crate::ui::screen::{Screen, ScreenDiagnostics, ScreenFirmware, ScreenBeacon, ScreenBrightness};
 use crate::drivers::touchpanel::{TouchPanelEventHandler, TouchPoint};
 use crate::drivers::display::DisplaySupported;
 use crate::drivers::clock::Clock;
 use crate::pinetimers::ConnectedRtc;
 use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::settings::Settings;
 
 use embedded_graphics::prelude::{DrawTarget, Point, Drawable, Transform};
 use embedded_graphics::pixelcolor::RgbColor;
@@ -21,6 +22,7 @@ use libm::{cos, sin};
 use chrono::Timelike;
 
 use alloc::sync::Arc;
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use alloc::vec;
 
@@ -36,6 +38,28 @@ pub struct ScreenMainEventHandler {}
 
 impl TouchPanelEventHandler for ScreenMainEventHandler {
     fn on_slide_up(&self, _p: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenDiagnostics::new())).unwrap();
+    }
+
+    fn on_slide_down(&self, _p: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenFirmware::new())).unwrap();
+    }
+
+    fn on_slide_left(&self, _p: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenBeacon::new())).unwrap();
+    }
+
+    // The screen is made by the task, which reads the samples
+    fn on_slide_right(&self, _p: TouchPoint) {
+        crate::tasks::show_battery_history::spawn().ok();
+    }
+
+    fn on_click_double(&self, _p: TouchPoint) {
+        crate::tasks::transition::spawn(Box::new(ScreenBrightness::new())).unwrap();
+    }
+
+    fn on_click_long(&self, _p: TouchPoint) {
+        crate::tasks::toggle_do_not_disturb::spawn().unwrap();
     }
 }
 
@@ -79,7 +103,7 @@ where
         return self.event_handler.clone();
     }
 
-    fn draw_init(&mut self, display: &mut DISPLAY, _clock: &Clock<ConnectedRtc>, mcuboot: &MCUBoot) {
+    fn draw_init(&mut self, display: &mut DISPLAY, clock: &Clock<ConnectedRtc>, mcuboot: &MCUBoot, settings: &Settings) {
         let clock_center = Point::new(120, 120);
         let clock_radius = 90;
 
@@ -99,9 +123,22 @@ where
         Text::with_baseline(&mcuboot.version_string(), Point::new(0, 0), text_style, Baseline::Top)
             .draw(display)
             .unwrap();
+
+        // Time hasn't been set since losing power
+        if clock.approximate {
+            Text::with_baseline("~", Point::new(230, 0), text_style, Baseline::Top)
+                .draw(display)
+                .unwrap();
+        }
+
+        if settings.do_not_disturb {
+            Text::with_baseline("DND", Point::new(0, 240), text_style, Baseline::Bottom)
+                .draw(display)
+                .unwrap();
+        }
     }
 
-    fn draw_update(&mut self, display: &mut DISPLAY, clock: &Clock<ConnectedRtc>, _: &MCUBoot) {
+    fn draw_update(&mut self, display: &mut DISPLAY, clock: &Clock<ConnectedRtc>, _: &MCUBoot, _: &Settings) {
         let clock_center = Point::new(120, 120);
         let clock_radius = 90;
 
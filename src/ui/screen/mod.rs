@@ -1,12 +1,25 @@
 mod main;
 mod poes;
+mod link_lost;
+mod diagnostics;
+mod firmware;
+mod beacon;
+mod battery;
+mod brightness;
 
 pub use main::ScreenMain;
 pub use poes::ScreenPoes;
+pub use link_lost::ScreenLinkLost;
+pub use diagnostics::ScreenDiagnostics;
+pub use firmware::ScreenFirmware;
+pub use beacon::ScreenBeacon;
+pub use battery::{ScreenBattery, BATTERY_HISTORY_SECONDS};
+pub use brightness::ScreenBrightness;
 
 use crate::drivers::touchpanel::TouchPanelEventHandler;
 use crate::drivers::clock::Clock;
 use crate::drivers::mcuboot::MCUBoot;
+use crate::drivers::settings::Settings;
 
 use crate::pinetimers::ConnectedRtc;
 
@@ -20,6 +33,6 @@ pub trait Screen<D> : Send + Debug {
     // (with Screen : TouchPanelEventHandler) to TouchPanelEventHandler,
     // because we don't know the type (and size) of the current screen...
     fn get_event_handler(&self) -> Arc<dyn TouchPanelEventHandler>;
-    fn draw_init(&mut self, display: &mut D, clock: &Clock<ConnectedRtc>, mcuboot: &MCUBoot);
-    fn draw_update(&mut self, display: &mut D, clock: &Clock<ConnectedRtc>, mcuboot: &MCUBoot);
+    fn draw_init(&mut self, display: &mut D, clock: &Clock<ConnectedRtc>, mcuboot: &MCUBoot, settings: &Settings);
+    fn draw_update(&mut self, display: &mut D, clock: &Clock<ConnectedRtc>, mcuboot: &MCUBoot, settings: &Settings);
 }